@@ -2,13 +2,13 @@ use logos::{Logos};
 use indextree::{Arena, NodeId};
 pub type Span = std::ops::Range<usize>;
 use std::{collections::{HashMap,HashSet}, ops::Range};
-use diags::Diags;
-use anyhow::{Context, bail};
+use std::path::{Path, PathBuf};
+use diags::{Diags, SourceSegment};
+use anyhow::{Context, anyhow, bail};
 use std::fs::File;
 use std::io::prelude::*;
 
 
-#[allow(unused_imports)]
 #[allow(unused_imports)]
 use log::{error, warn, info, debug, trace};
 
@@ -19,18 +19,43 @@ use log::{error, warn, info, debug, trace};
 pub enum LexToken {
     #[token("section")] Section,
     #[token("align")] Align,
+    #[token("roundup_pow2")] RoundupPow2,
     #[token("set_sec")] SetSec,
     #[token("set_img")] SetImg,
     #[token("set_abs")] SetAbs,
     #[token("assert")] Assert,
+    #[token("assert_if_written")] AssertIfWritten,
+    #[token("assert_in_range")] AssertInRange,
+    #[token("at")] At,
     #[token("sizeof")] Sizeof,
+    #[token("crc32")] Crc32,
     #[token("print")] Print,
+    #[token("printf")] Printf,
+    #[token("tee")] Tee,
     #[token("to_u64")] ToU64,
     #[token("to_i64")] ToI64,
+    #[token("is_pow2")] IsPow2,
+    #[token("clamp")] Clamp,
+    #[token("align_up")] AlignUp,
+    #[token("align_down")] AlignDown,
+    #[token("abs_value")] AbsValue,
+    #[token("count_ones")] CountOnes,
+    #[token("count_zeros")] CountZeros,
+    #[token("bit_reverse")] BitReverse,
+    #[token("pow")] Pow,
+    #[token("min")] Min,
+    #[token("max")] Max,
+    #[token("pack32")] Pack32,
+    #[token("join")] Join,
+    #[token("const")] Const,
+    #[token("if")] If,
+    #[token("else")] Else,
+    #[token("repeat")] Repeat,
     #[token("abs")] Abs,
     #[token("img")] Img,
     #[token("sec")] Sec,
     #[token("wrs")] Wrs,
+    #[token("wr_header")] Header,
     #[token("wr8")] Wr8,
     #[token("wr16")] Wr16,
     #[token("wr24")] Wr24,
@@ -39,9 +64,23 @@ pub enum LexToken {
     #[token("wr48")] Wr48,
     #[token("wr56")] Wr56,
     #[token("wr64")] Wr64,
+    #[token("wr16be")] Wr16be,
+    #[token("wr24be")] Wr24be,
+    #[token("wr32be")] Wr32be,
+    #[token("wr40be")] Wr40be,
+    #[token("wr48be")] Wr48be,
+    #[token("wr56be")] Wr56be,
+    #[token("wr64be")] Wr64be,
     #[token("wrf")] Wrf,
+    #[token("wrbytes")] Wrbytes,
     #[token("wr")] Wr,
+    #[token("wr_xor")] WrXor,
+    #[token("wr_slice")] WrSlice,
+    #[token("fill")] Fill,
     #[token("output")] Output,
+    #[token("endian")] Endian,
+    #[token("include")] Include,
+    #[token("include_once")] IncludeOnce,
     #[token("==")] DoubleEq,
     #[token("!=")] NEq,
     #[token(">=")] GEq,
@@ -50,20 +89,30 @@ pub enum LexToken {
     #[token("||")] DoublePipe,
     #[token("&")] Ampersand,
     #[token("|")] Pipe,
+    #[token("=")] Equal,
+    #[token("++")] DoublePlus,
     #[token("+")] Plus,
     #[token("-")] Minus,
     #[token("*")] Asterisk,
     #[token("/")] FSlash,
     #[token("%")] Percent,
+    #[token("~")] Tilde,
+    #[token("!")] Bang,
     #[token(",")] Comma,
+    #[token(":")] Colon,
     #[token("<<")] DoubleLess,
     #[token(">>")] DoubleGreater,
+    #[token("<")] Less,
+    #[token(">")] Greater,
     #[token("{")] OpenBrace,
     #[token("}")] CloseBrace,
     #[token("(")] OpenParen,
     #[token(")")] CloseParen,
+    #[token("[")] OpenBracket,
+    #[token("]")] CloseBracket,
     #[token(";")] Semicolon,
     #[regex("[_a-zA-Z][0-9a-zA-Z_]*:")] Label,
+    #[regex("@[_a-zA-Z][0-9a-zA-Z_]*")] Attribute,
     #[regex("[_a-zA-Z][0-9a-zA-Z_]*")] Identifier,
 
     // Plain vanilla numbers that are ambiguously signed or unsigned
@@ -76,11 +125,10 @@ pub enum LexToken {
     // Signed literals are suffixed with 'i' and/or start with a minus sign
     #[regex("0[bB][01][_01]*i|0[xX][0-9a-fA-F][_0-9a-fA-F]*i|[1-9][_0-9]*i|-[1-9][_0-9]*i?|0i")] I64,
     
-    // Not only is \ special in strings and must be escaped, but also special in
-    // regex.  We use raw string here to avoid having the escape the \ for the
-    // string itself. The \\ in this raw string are escape \ for the regex
-    // engine underneath.
-    #[regex(r#""(\\"|\\.|[^"])*""#)] QuotedString,
+    // Quoted strings are scanned by a callback rather than a regex.  The
+    // regex form of an escape-aware string recurses once per character in
+    // the generated lexer, which overflows the stack on very long strings.
+    #[token("\"", lex_quoted_string)] QuotedString,
 
     // Comments and whitespace are stripped from user input during processing.
     // This stripping happens *after* we record all the line/offset info
@@ -90,8 +138,82 @@ pub enum LexToken {
     #[regex(r#"[ \t\n\f]+"#, logos::skip)]           // whitespace
     #[error]
     Unknown,
+
+    // Never produced by the lexer.  The parser converts a prefix
+    // minus sign to Negate to distinguish it from subtraction.
+    Negate,
+}
+
+/// Lexer callback that consumes the rest of a quoted string after the
+/// opening quote.  The scan is a single linear pass that skips the
+/// character after each backslash, so an escaped quote does not end the
+/// string.  Returns false for an unterminated string.
+fn lex_quoted_string(lex: &mut logos::Lexer<LexToken>) -> bool {
+    let bytes = lex.remainder().as_bytes();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'\\' => idx += 2,
+            b'"' => {
+                lex.bump(idx + 1);
+                return true;
+            }
+            _ => idx += 1,
+        }
+    }
+    false
 }
 
+/// Concise reference of the language statements and built-in functions
+/// for --help-lang.  Each entry is the syntax and a short description.
+/// Keep this table in sync with the keywords in LexToken.
+pub const LANG_REFERENCE: &[(&str, &str)] = &[
+    ("section <name> [@attribute ...] { ... }", "Defines a section of output content"),
+    ("section <name> = <name> ++ <name> ...;", "Defines a section as a concatenation of sections"),
+    ("output <name> [address] [align <alignment>];", "Writes the named section to the output"),
+    ("include \"<path>\";", "Splices in the contents of another source file"),
+    ("include_once \"<path>\";", "Same as include, but skips a file already included"),
+    ("const <name> = <expr>;", "Defines a named constant"),
+    ("if (<expr>) { ... } [else { ... }]", "Assembles only the block selected by the condition"),
+    ("repeat (<count>) { ... }", "Assembles the block count times"),
+    ("endian big|little;", "Sets the byte order of wrN writes"),
+    ("<label>:", "Marks an addressable location"),
+    ("wr <name>;", "Writes the contents of the named section"),
+    ("wr_xor <name>, <key>;", "Writes the named section XOR'd with a byte or string key"),
+    ("wr_slice <name>, <offset>, <length>;", "Writes a byte range of the named section"),
+    ("wrN <expr> [, <count>];", "Writes an N bit little-endian value, N is 8 to 64"),
+    ("wrNbe <expr> [, <count>];", "Writes an N bit big-endian value, N is 16 to 64"),
+    ("wrs <expr> [, <expr> ...];", "Writes a string"),
+    ("wr_header <expr> [, <expr> ...];", "Writes a string followed by image metadata"),
+    ("wrbytes [<expr>, ...];", "Writes a list of byte values"),
+    ("pack32(<value>:<width>, ...);", "Packs bit fields into a 32 bit value and writes it"),
+    ("wrf \"<path>\";", "Writes the contents of a file"),
+    ("fill <count>, <value>;", "Writes a byte value count times"),
+    ("align <expr> [, <pad>];", "Pads to the next multiple of the alignment"),
+    ("roundup_pow2();", "Pads the section to the next power of two size"),
+    ("set_sec|set_img|set_abs <expr> [, <pad>];", "Pads to the specified address"),
+    ("assert <expr>;", "Fails if the expression is false"),
+    ("assert_in_range(<value>, <lo>, <hi>);", "Fails if the value is outside [lo, hi]"),
+    ("assert_if_written(<name>, <expr>);", "Asserts only if the section is written"),
+    ("at(<address>);", "Fails if the absolute address is not the specified address"),
+    ("print <expr> [, <expr> ...];", "Prints the expressions to the console"),
+    ("printf <format> [, <expr> ...];", "Prints with %x, %d, %s and %b specifiers"),
+    ("tee <expr> [, <expr> ...];", "Prints and writes a string"),
+    ("sizeof(<name> [, <name>])", "Returns the size of a section or between two names"),
+    ("abs([name]) img([name]) sec([name])", "Returns an absolute, image or section address"),
+    ("crc32(<name>)", "Returns the CRC-32 of the bytes of a section"),
+    ("to_u64(<expr>) to_i64(<expr>)", "Converts to an unsigned or signed value"),
+    ("is_pow2(<expr>)", "Returns 1 if the value is a power of two"),
+    ("clamp(<value>, <lo>, <hi>)", "Bounds the value to [lo, hi]"),
+    ("align_up(<value>, <align>) align_down(...)", "Rounds to a multiple of the alignment"),
+    ("abs_value(<expr>)", "Returns the absolute value"),
+    ("count_ones(<expr>) count_zeros(<expr>)", "Counts the set or clear bits"),
+    ("bit_reverse(<value>, <width>)", "Reverses the low width bits"),
+    ("pow(<base>, <exponent>)", "Raises the base to the power of the exponent"),
+    ("min(<a>, <b>) max(<a>, <b>)", "Returns the lesser or greater value"),
+    ("join(<separator>, <expr> [, <expr> ...])", "Joins values into a string"),
+];
+
 /// The basic token info structure used everywhere.
 /// The AST constructs a vector of TokenInfos.
 #[derive(Debug, Clone, PartialEq)]
@@ -112,6 +234,52 @@ impl<'toks> TokenInfo<'toks> {
     pub fn span(&self) -> Span { self.loc.clone() }
 }
 
+/// Returns the token if the entire string lexes as exactly one token,
+/// e.g. to classify a value from the command line.
+pub fn single_token(s: &str) -> Option<LexToken> {
+    let mut lex = LexToken::lexer(s);
+    let tok = lex.next()?;
+    if lex.span() != (0..s.len()) || lex.next().is_some() {
+        return None;
+    }
+    Some(tok)
+}
+
+/// Returns the Levenshtein edit distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    // prev holds the distances for the previous row of the DP table
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b_chars.len() + 1];
+        for (j, &cb) in b_chars.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b_chars.len()]
+}
+
+/// Returns the candidate name closest to the unknown name, if any candidate
+/// is close enough to be a plausible misspelling.  Ties go to the
+/// alphabetically first candidate so suggestions are stable.
+pub fn suggest_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    // Allow roughly one edit per three characters
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut best: Option<(usize, &'a str)> = None;
+    for candidate in candidates {
+        let distance = edit_distance(name, candidate);
+        if distance == 0 || distance > max_distance {
+            continue;
+        }
+        if best.is_none_or(|b| (distance, candidate) < b) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
 /**
  * Abstract Syntax Tree
  * This structure contains the AST created from the raw lexical
@@ -141,13 +309,6 @@ impl<'toks> Ast<'toks> {
         self.tv.get(self.tok_num)
     }
 
-    /// Take the next token info object, if any.
-    fn take(&mut self) -> Option<&TokenInfo<'toks>> {
-        let tinfo = self.tv.get(self.tok_num);
-        self.tok_num += 1;
-        tinfo
-    }
-
     /// Create a new abstract syntax tree.
     pub fn new(fstr: &'toks str, diags: &mut Diags) -> Option<Self> {
         let mut arena = Arena::new();
@@ -197,10 +358,15 @@ impl<'toks> Ast<'toks> {
     }
 
     /// Return an iterator over the children of the specified AST node
-    pub fn children(&self, nid: NodeId) -> indextree::Children<usize>{
+    pub fn children(&self, nid: NodeId) -> indextree::Children<'_, usize>{
         nid.children(&self.arena)
     }
 
+    /// Returns the parent of the specified AST node, if any
+    pub fn parent(&self, nid: NodeId) -> Option<NodeId> {
+        self.arena[nid].parent()
+    }
+
     /// Returns true if the specified node has child nodes
     pub fn has_children(&self, nid: NodeId) -> bool {
         nid.children(&self.arena).next().is_some()
@@ -234,6 +400,54 @@ impl<'toks> Ast<'toks> {
             result &= match tinfo.tok {
                 LexToken::Section => self.parse_section(self.root, diags),
                 LexToken::Output => self.parse_output(self.root, diags),
+                LexToken::Endian => {
+                    let stmt_tok_num = self.tok_num;
+                    let ok = self.parse_endian(self.root, diags);
+                    if !ok {
+                        self.advance_to_statement_boundary(stmt_tok_num);
+                    }
+                    ok
+                }
+                LexToken::Const => {
+                    let stmt_tok_num = self.tok_num;
+                    let ok = self.parse_const(self.root, diags);
+                    if !ok {
+                        self.advance_to_statement_boundary(stmt_tok_num);
+                    }
+                    ok
+                }
+
+                // Writes are only meaningful inside a section.  Skip the
+                // whole statement to avoid an error for each of its tokens.
+                LexToken::Wr |
+                LexToken::WrXor |
+                LexToken::WrSlice |
+                LexToken::Wrs |
+                LexToken::Header |
+                LexToken::Wrf |
+                LexToken::Wrbytes |
+                LexToken::Fill |
+                LexToken::Wr8 |
+                LexToken::Wr16 |
+                LexToken::Wr24 |
+                LexToken::Wr32 |
+                LexToken::Wr40 |
+                LexToken::Wr48 |
+                LexToken::Wr56 |
+                LexToken::Wr64 |
+                LexToken::Wr16be |
+                LexToken::Wr24be |
+                LexToken::Wr32be |
+                LexToken::Wr40be |
+                LexToken::Wr48be |
+                LexToken::Wr56be |
+                LexToken::Wr64be => {
+                    let msg = format!("Write statement '{}' must be inside a section", tinfo.val);
+                    diags.err1("AST_43", &msg, tinfo.span());
+                    let stmt_tok_num = self.tok_num;
+                    self.advance_to_statement_boundary(stmt_tok_num);
+                    false
+                }
 
                 // Unrecognized top level token.  Report the error, but keep going
                 // to try to give the user more errors in batches.
@@ -256,6 +470,26 @@ impl<'toks> Ast<'toks> {
                 self.tv[self.tok_num-1].span());
     }
 
+    /// If an identifier was expected, but the current token is a keyword,
+    /// report the reserved keyword and return true.  This is much clearer
+    /// than a generic expected-identifier error.
+    fn err_reserved_keyword(&self, diags: &mut Diags, expected_token: LexToken) -> bool {
+        let tinfo = &self.tv[self.tok_num];
+        if expected_token != LexToken::Identifier || tinfo.tok == LexToken::Identifier {
+            return false;
+        }
+        // Keywords are the tokens spelled like identifiers
+        let mut chars = tinfo.val.chars();
+        let is_keyword = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+                         chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_keyword {
+            let m = format!("'{}' is a reserved keyword and cannot be used as an identifier",
+                            tinfo.val);
+            diags.err1("AST_36", &m, tinfo.span());
+        }
+        is_keyword
+    }
+
     fn err_invalid_expression(&self, diags: &mut Diags, code: &str) {
         let m = format!("Invalid expression '{}'", self.tv[self.tok_num].val);
         diags.err1(code, &m, self.tv[self.tok_num].span());
@@ -266,31 +500,54 @@ impl<'toks> Ast<'toks> {
     }
 
     fn err_no_close_brace(&self, diags: &mut Diags, brace_tok_num: usize) {
-        let m = format!("Missing '}}'.  The following open brace is unmatched.");
-        diags.err1("AST_14", &m, self.tv[brace_tok_num].span());
+        let m = "Missing '}'.  The following open brace is unmatched.";
+        diags.err1("AST_14", m, self.tv[brace_tok_num].span());
     }
 
-    /// Attempts to advance the token number past the next semicolon. The final
-    /// token number may be invalid.  This function is used to try to recover
-    /// from syntax errors.
+    /// Reports the token found instead of a close paren, with a secondary
+    /// label on the unmatched open paren.
+    fn err_no_close_paren(&self, diags: &mut Diags, paren_tok_num: usize) {
+        let m = format!("Missing ')', but found '{}'.  The marked open paren is unmatched.",
+                        self.tv[self.tok_num].val);
+        diags.err2("AST_54", &m, self.tv[self.tok_num].span(),
+                   self.tv[paren_tok_num].span());
+    }
+
+    /// Attempts to resynchronize to the next statement boundary after a
+    /// syntax error in the statement starting at stmt_tok_num.  The final
+    /// token number may be invalid.
     ///
-    /// If the current token is already one past a semicolon, then do nothing.
-    /// This case occurs when the semicolon itself was unexpected, e.g. missing
-    /// close paren like assert(1;
-    fn advance_past_semicolon(&mut self) {
-        assert!(self.tok_num > 0);
-        self.dbg_enter("advance_past_semicolon");
-        if let Some(prev_tinfo) = self.tv.get(self.tok_num - 1) {
-            if prev_tinfo.tok != LexToken::Semicolon {
-                while let Some(tinfo) = self.take() {
-                    if tinfo.tok == LexToken::Semicolon {
-                        break;
-                    }
-                }
+    /// Recovery stops one past the next semicolon, or *on* the next close
+    /// brace so the caller can finish the enclosing section normally.
+    ///
+    /// If the failed statement already consumed its own terminating semicolon,
+    /// then do nothing.  If the statement failed on its very first token, then
+    /// always consume that token to guarantee forward progress.
+    fn advance_to_statement_boundary(&mut self, stmt_tok_num: usize) {
+        self.dbg_enter("advance_to_statement_boundary");
+        // A statement ends with a semicolon, or with the close brace of
+        // an if or else block.
+        if self.tok_num > stmt_tok_num
+                && [LexToken::Semicolon, LexToken::CloseBrace].contains(&self.tv[self.tok_num - 1].tok) {
+            debug!("Ast::advance_to_statement_boundary: Already past end of statement");
+            return;
+        }
+
+        if self.tok_num == stmt_tok_num {
+            self.tok_num += 1;
+        }
+
+        while let Some(tok) = self.peek().map(|tinfo| tinfo.tok) {
+            if tok == LexToken::CloseBrace {
+                break;
+            }
+            self.tok_num += 1;
+            if tok == LexToken::Semicolon {
+                break;
             }
         }
-        debug!("Ast::advance_past_semicolon: Stopped on token {}", self.tok_num);
-        self.dbg_exit("advance_past_semicolon", true);
+        debug!("Ast::advance_to_statement_boundary: Stopped on token {}", self.tok_num);
+        self.dbg_exit("advance_to_statement_boundary", true);
     }
 
     /// Add the specified token as a child of the parent.
@@ -313,7 +570,7 @@ impl<'toks> Ast<'toks> {
             if expected_token == tinfo.tok {
                 self.add_to_parent_and_advance(parent);
                 result = true;
-            } else {
+            } else if !self.err_reserved_keyword(diags, expected_token) {
                 self.err_expected_after(diags, code, context);
             }
         } else {
@@ -341,24 +598,6 @@ impl<'toks> Ast<'toks> {
         false
     }
 
-    /// Expect the specified token, add it to the parent and advance.
-    fn expect_token(&mut self, tok: LexToken, diags: &mut Diags, parent : NodeId) -> bool {
-
-        if let Some(tinfo) = self.peek() {
-            if tok == tinfo.tok {
-                self.add_to_parent_and_advance(parent);
-                return true;
-            } else {
-                let msg = format!("Expected {:?}", tok);
-                self.err_expected_after(diags, "AST_26", &msg);
-            }
-        } else {
-            self.err_no_input(diags);
-        }
-
-        false
-    }
-
     /// Expect an expression which cannot be None.
     fn expect_expr(&mut self, parent : NodeId, diags: &mut Diags) -> bool {
 
@@ -377,6 +616,73 @@ impl<'toks> Ast<'toks> {
         true
     }
 
+    /// Expect comma separated expressions inside parentheses, one for each
+    /// of the expected argument descriptions.  Each expression becomes a
+    /// child of the built-in function node.
+    fn expect_builtin_args(&mut self, builtin_nid : NodeId, args: &[&str], diags: &mut Diags) -> bool {
+        if !self.expect_token_no_add(LexToken::OpenParen, diags) {
+            return false;
+        }
+        for (arg_num, expected) in args.iter().enumerate() {
+            if arg_num > 0 && !self.expect_token_no_add(LexToken::Comma, diags) {
+                return false;
+            }
+            if !self.expect_builtin_expr(builtin_nid, expected, diags) {
+                return false;
+            }
+        }
+        self.expect_token_no_add(LexToken::CloseParen, diags)
+    }
+
+    /// Report a missing or malformed built-in function argument at the
+    /// current token.  The message names the built-in function and the
+    /// expected argument, e.g. "sizeof expects a section name".
+    fn err_builtin_arg(&self, diags: &mut Diags, builtin_nid : NodeId, expected: &str) {
+        let builtin = self.get_tinfo(builtin_nid).val;
+        let tinfo = &self.tv[self.tok_num];
+        let msg = format!("{} expects {}, but found '{}'", builtin, expected, tinfo.val);
+        diags.err1("AST_51", &msg, tinfo.span());
+    }
+
+    /// Expect an identifier argument of a built-in function.  If found,
+    /// add it to the built-in function node and advance.
+    fn expect_builtin_ident(&mut self, builtin_nid : NodeId, expected: &str,
+                            diags: &mut Diags) -> bool {
+        match self.peek().map(|tinfo| tinfo.tok) {
+            Some(LexToken::Identifier) => {
+                self.add_to_parent_and_advance(builtin_nid);
+                true
+            }
+            Some(_) => {
+                if !self.err_reserved_keyword(diags, LexToken::Identifier) {
+                    self.err_builtin_arg(diags, builtin_nid, expected);
+                }
+                false
+            }
+            None => {
+                self.err_no_input(diags);
+                false
+            }
+        }
+    }
+
+    /// Expect an expression argument of a built-in function.  The
+    /// expression becomes a child of the built-in function node.
+    fn expect_builtin_expr(&mut self, builtin_nid : NodeId, expected: &str,
+                           diags: &mut Diags) -> bool {
+        // An argument that is missing entirely gets a specific error,
+        // while expression errors come from the expression parser.
+        match self.peek().map(|tinfo| tinfo.tok) {
+            Some(LexToken::CloseParen) |
+            Some(LexToken::Comma) |
+            Some(LexToken::Colon) => {
+                self.err_builtin_arg(diags, builtin_nid, expected);
+                false
+            }
+            _ => self.expect_expr(builtin_nid, diags),
+        }
+    }
+
     /// Expect zero or one instance of specified tokens.
     /// If we find an allowed found, add it to the parent and advance.
     /// If not found, do nothing and return success
@@ -421,9 +727,18 @@ impl<'toks> Ast<'toks> {
         // that a special case here.
         let sec_nid = self.add_to_parent_and_advance(parent);
 
-        // After 'section' an identifier is expected
+        // After 'section' an identifier is expected, followed by
+        // optional attributes.
         if self.expect_leaf(diags, sec_nid, LexToken::Identifier, "AST_1",
-                     "Expected an identifier after section") {
+                     "Expected an identifier after section") &&
+           self.parse_section_attributes(sec_nid, diags) {
+            // A section may be the concatenation of other sections
+            if let Some(tinfo) = self.peek() {
+                if tinfo.tok == LexToken::Equal {
+                    result = self.parse_section_concat(sec_nid, diags);
+                    return self.dbg_exit("parse_section", result);
+                }
+            }
             // After a section identifier, expect an open brace.
             // Remember the location of the opening brace to help with
             // user missing brace errors.
@@ -436,6 +751,77 @@ impl<'toks> Ast<'toks> {
         self.dbg_exit("parse_section", result)
     }
 
+    /// Parse a section defined as the concatenation of other sections
+    /// For example: section combined = a ++ b [++ c ...];
+    fn parse_section_concat(&mut self, sec_nid : NodeId, diags: &mut Diags) -> bool {
+        self.dbg_enter("parse_section_concat");
+
+        // The '=' node is the parent of the concatenated section names
+        let concat_nid = self.add_to_parent_and_advance(sec_nid);
+        let mut result = self.expect_leaf(diags, concat_nid, LexToken::Identifier, "AST_35",
+                                          "Expected a section identifier after '='");
+
+        // Omit the '++' from the AST to reduce clutter.
+        while result && self.peek().map(|tinfo| tinfo.tok) == Some(LexToken::DoublePlus) {
+            self.tok_num += 1;
+            result = self.expect_leaf(diags, concat_nid, LexToken::Identifier, "AST_55",
+                                      "Expected a section identifier after '++'");
+        }
+
+        if result {
+            result = self.expect_semi(diags, sec_nid);
+        }
+
+        self.dbg_exit("parse_section_concat", result)
+    }
+
+    /// Parse zero or more section attributes, e.g. @base 0x8000
+    fn parse_section_attributes(&mut self, sec_nid : NodeId, diags: &mut Diags) -> bool {
+        self.dbg_enter("parse_section_attributes");
+        let mut result = true;
+        let mut seen = HashSet::new();
+        while let Some(tinfo) = self.peek() {
+            if tinfo.tok != LexToken::Attribute {
+                break;
+            }
+            if !seen.insert(tinfo.val) {
+                let m = format!("Duplicate section attribute '{}'", tinfo.val);
+                diags.err1("AST_34", &m, tinfo.span());
+                result = false;
+                break;
+            }
+            match tinfo.val {
+                // Attributes without a value
+                "@ensure_nonempty" |
+                "@once" => {
+                    self.add_to_parent_and_advance(sec_nid);
+                }
+                // Attributes with a mandatory number value
+                "@base" => {
+                    let attr_nid = self.add_to_parent_and_advance(sec_nid);
+                    if let Some(val_tinfo) = self.peek() {
+                        if [LexToken::U64, LexToken::Integer].contains(&val_tinfo.tok) {
+                            self.add_to_parent_and_advance(attr_nid);
+                            continue;
+                        }
+                    }
+                    let attr_tinfo = self.get_tinfo(attr_nid);
+                    let m = format!("Section attribute '{}' requires an unsigned number", attr_tinfo.val);
+                    diags.err1("AST_33", &m, attr_tinfo.span());
+                    result = false;
+                    break;
+                }
+                _ => {
+                    let m = format!("Unknown section attribute '{}'", tinfo.val);
+                    diags.err1("AST_32", &m, tinfo.span());
+                    result = false;
+                    break;
+                }
+            }
+        }
+        self.dbg_exit("parse_section_attributes", result)
+    }
+
     /// Parse all possible content within a section.
     fn parse_section_contents(&mut self, parent : NodeId, diags: &mut Diags,
                               brace_tok_num: usize) -> bool {
@@ -443,23 +829,10 @@ impl<'toks> Ast<'toks> {
         self.dbg_enter("parse_section_contents");
         let mut result = true; // todo fixme
 
-        let mut tok_num_old = 0;
         while let Some(tinfo) = self.peek() {
             debug!("Ast::parse_section_contents: token {}:{}", self.tok_num, tinfo.val);
-            if tok_num_old == self.tok_num {
-                // In some error cases, such as a missing closing brace, parsing
-                // can get stuck without advancing the token pointer.  For
-                // example, this problem occurs because an error occurs at the
-                // very start of a new expression.  The advance_past_semicolon
-                // function won't move us forward since we're already past a
-                // semicolon and at the start of a new statement. As a simple
-                // solution, detect that we're not making forward progress and
-                // force the token number forward.
-                self.tok_num += 1;
-                debug!("parse_section_contents: Forcing forward progress.");
-                continue;
-            }
-            tok_num_old = self.tok_num;
+            // Remember where this statement started for error recovery
+            let stmt_tok_num = self.tok_num;
             // When we find a close brace, we're done with section content
             if tinfo.tok == LexToken::CloseBrace {
                 self.parse_leaf(parent);
@@ -471,6 +844,26 @@ impl<'toks> Ast<'toks> {
             let parse_ok = match tinfo.tok {
                 LexToken::Label => self.parse_label(parent, diags),
                 LexToken::Wr => self.parse_wr(parent, diags),
+                LexToken::Endian => self.parse_endian(parent, diags),
+                // Constants are global, so a conditional or repeated
+                // definition makes no sense
+                LexToken::Const if [LexToken::If, LexToken::Else, LexToken::Repeat]
+                                       .contains(&self.get_tinfo(parent).tok) => {
+                    let msg = "A const can't be defined inside an if, else or repeat block";
+                    diags.err1("AST_50", msg, tinfo.span());
+                    false
+                }
+                LexToken::Const => self.parse_const(parent, diags),
+                LexToken::If => self.parse_if(parent, diags),
+                LexToken::Repeat => self.parse_repeat(parent, diags),
+                LexToken::WrXor => self.parse_wr_xor(parent, diags),
+                LexToken::WrSlice => self.parse_wr_slice(parent, diags),
+                LexToken::Wrbytes => self.parse_wrbytes(parent, diags),
+                LexToken::Pack32 => self.parse_pack32(parent, diags),
+                LexToken::AssertIfWritten => self.parse_assert_if_written(parent, diags),
+                LexToken::AssertInRange => self.parse_assert_in_range(parent, diags),
+                LexToken::At => self.parse_at(parent, diags),
+                LexToken::RoundupPow2 => self.parse_roundup_pow2(parent, diags),
                 LexToken::Wrf |
                 LexToken::Wr8 |
                 LexToken::Wr16 |
@@ -480,12 +873,23 @@ impl<'toks> Ast<'toks> {
                 LexToken::Wr48 |
                 LexToken::Wr56 |
                 LexToken::Wr64 |
+                LexToken::Wr16be |
+                LexToken::Wr24be |
+                LexToken::Wr32be |
+                LexToken::Wr40be |
+                LexToken::Wr48be |
+                LexToken::Wr56be |
+                LexToken::Wr64be |
                 LexToken::Wrs |
+                LexToken::Header |
+                LexToken::Fill |
                 LexToken::Assert |
                 LexToken::Align |
                 LexToken::SetSec |
                 LexToken::SetImg |
                 LexToken::SetAbs |
+                LexToken::Tee |
+                LexToken::Printf |
                 LexToken::Print => self.parse_expr(parent, diags),
                 _ => {
                     self.err_invalid_expression(diags, "AST_3");
@@ -494,16 +898,16 @@ impl<'toks> Ast<'toks> {
             };
 
             if !parse_ok {
-                debug!("Ast::parse_section_contents: skipping to next ; starting from {}", self.tok_num);
-                // Consume the bad token and skip forward    
-                self.advance_past_semicolon();
+                debug!("Ast::parse_section_contents: skipping to next statement starting from {}", self.tok_num);
+                // Skip forward to the start of the next statement
+                self.advance_to_statement_boundary(stmt_tok_num);
                 result = false;
             }
         }
 
         // If we got here, we ran out of tokens before finding the close brace.
         self.err_no_close_brace(diags, brace_tok_num);
-        return self.dbg_exit("parse_section_contents", false);
+        self.dbg_exit("parse_section_contents", false)
     }
 
     // Parser for writing a section
@@ -523,6 +927,337 @@ impl<'toks> Ast<'toks> {
         self.dbg_exit("parse_wr", result)
     }
 
+    /// Parser for the byte order directive
+    /// For example: endian big;
+    fn parse_endian(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_endian");
+        let mut result = false;
+
+        // Add the endian keyword as a child of the parent and advance
+        let endian_nid = self.add_to_parent_and_advance(parent_nid);
+
+        // Next, either 'big' or 'little' is expected
+        let is_order = self.peek().is_some_and(|tinfo| tinfo.tok == LexToken::Identifier &&
+                                               ["big", "little"].contains(&tinfo.val));
+        if is_order {
+            self.add_to_parent_and_advance(endian_nid);
+            result = self.expect_semi(diags, endian_nid);
+        } else if self.peek().is_some() {
+            self.err_expected_after(diags, "AST_44", "Expected 'big' or 'little' after 'endian'");
+        } else {
+            self.err_no_input(diags);
+        }
+        self.dbg_exit("parse_endian", result)
+    }
+
+    /// Parser for a named constant
+    /// For example: const <identifier> = <expr>;
+    fn parse_const(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_const");
+        let mut result = false;
+
+        // Add the const keyword as a child of the parent and advance
+        let const_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if self.expect_leaf(diags, const_nid, LexToken::Identifier, "AST_47",
+                            "Expected a constant name after 'const'") &&
+           self.expect_token_no_add(LexToken::Equal, diags) &&
+           self.expect_expr(const_nid, diags) {
+            result = self.expect_semi(diags, const_nid);
+        }
+
+        self.dbg_exit("parse_const", result)
+    }
+
+    /// Parser for conditional section contents
+    /// For example: if (<expr>) { ... } [else { ... }]
+    /// The condition is the first child of the 'if' node, followed by the
+    /// braces and statements of the block.  An optional 'else' node is the
+    /// last child and holds either a block or a nested 'if'.
+    fn parse_if(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_if");
+
+        // Add the if keyword as a child of the parent and advance
+        let if_nid = self.add_to_parent_and_advance(parent_nid);
+
+        let mut result = self.expect_token_no_add(LexToken::OpenParen, diags) &&
+                         self.expect_expr(if_nid, diags) &&
+                         self.expect_token_no_add(LexToken::CloseParen, diags) &&
+                         self.parse_block(if_nid, diags);
+
+        if result && self.peek().is_some_and(|tinfo| tinfo.tok == LexToken::Else) {
+            let else_nid = self.add_to_parent_and_advance(if_nid);
+            result = if self.peek().is_some_and(|tinfo| tinfo.tok == LexToken::If) {
+                self.parse_if(else_nid, diags)
+            } else {
+                self.parse_block(else_nid, diags)
+            };
+        }
+
+        self.dbg_exit("parse_if", result)
+    }
+
+    /// Parser for a repeated block of section contents
+    /// For example: repeat (<expr>) { ... }
+    /// The count is the first child of the 'repeat' node, followed by the
+    /// braces and statements of the block.
+    fn parse_repeat(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_repeat");
+
+        // Add the repeat keyword as a child of the parent and advance
+        let repeat_nid = self.add_to_parent_and_advance(parent_nid);
+
+        let result = self.expect_token_no_add(LexToken::OpenParen, diags) &&
+                     self.expect_expr(repeat_nid, diags) &&
+                     self.expect_token_no_add(LexToken::CloseParen, diags) &&
+                     self.parse_block(repeat_nid, diags);
+
+        self.dbg_exit("parse_repeat", result)
+    }
+
+    /// Parser for the braces and statements of an if, else or repeat block.
+    /// The statements are children of the parent node.
+    fn parse_block(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+        let brace_tok_num = self.tok_num;
+        self.expect_leaf(diags, parent_nid, LexToken::OpenBrace, "AST_49",
+                         "Expected { to start the block") &&
+        self.parse_section_contents(parent_nid, diags, brace_tok_num)
+    }
+
+    /// Parser for writing a section XOR'd with a key
+    /// For example: wr_xor <section identifier>, <expr>;
+    fn parse_wr_xor(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_wr_xor");
+        let mut result = false;
+
+        // Add the keyword as a child of the parent and advance
+        let xor_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if self.expect_leaf(diags, xor_nid, LexToken::Identifier, "AST_41",
+                    "Expected a section identifier after 'wr_xor'") &&
+           self.expect_token_no_add(LexToken::Comma, diags) &&
+           self.expect_expr(xor_nid, diags) {
+            result = self.expect_semi(diags, xor_nid);
+        }
+        self.dbg_exit("parse_wr_xor", result)
+    }
+
+    /// Parser for writing a byte range of a section
+    /// For example: wr_slice <section identifier>, <offset expr>, <length expr>;
+    fn parse_wr_slice(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_wr_slice");
+        let mut result = false;
+
+        // Add the keyword as a child of the parent and advance
+        let slice_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if self.expect_leaf(diags, slice_nid, LexToken::Identifier, "AST_52",
+                    "Expected a section identifier after 'wr_slice'") &&
+           self.expect_token_no_add(LexToken::Comma, diags) &&
+           self.expect_expr(slice_nid, diags) &&
+           self.expect_token_no_add(LexToken::Comma, diags) &&
+           self.expect_expr(slice_nid, diags) {
+            result = self.expect_semi(diags, slice_nid);
+        }
+        self.dbg_exit("parse_wr_slice", result)
+    }
+
+    /// Parser for a conditional assert
+    /// For example: assert_if_written( <section identifier>, <expr> );
+    fn parse_assert_if_written(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_assert_if_written");
+        let mut result = false;
+
+        // Add the keyword as a child of the parent and advance
+        let assert_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if self.expect_token_no_add(LexToken::OpenParen, diags) &&
+           self.expect_leaf(diags, assert_nid, LexToken::Identifier, "AST_31",
+                    "Expected a section identifier after 'assert_if_written('") &&
+           self.expect_token_no_add(LexToken::Comma, diags) &&
+           self.expect_expr(assert_nid, diags) &&
+           self.expect_token_no_add(LexToken::CloseParen, diags) {
+            result = self.expect_semi(diags, assert_nid);
+        }
+
+        self.dbg_exit("parse_assert_if_written", result)
+    }
+
+    /// Parser for asserting that a value is within inclusive bounds
+    /// For example: assert_in_range(<value>, <lo>, <hi>);
+    fn parse_assert_in_range(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_assert_in_range");
+        let mut result = false;
+
+        // Add the keyword as a child of the parent and advance
+        let assert_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if self.expect_token_no_add(LexToken::OpenParen, diags) &&
+           self.expect_expr(assert_nid, diags) &&
+           self.expect_token_no_add(LexToken::Comma, diags) &&
+           self.expect_expr(assert_nid, diags) &&
+           self.expect_token_no_add(LexToken::Comma, diags) &&
+           self.expect_expr(assert_nid, diags) &&
+           self.expect_token_no_add(LexToken::CloseParen, diags) {
+            result = self.expect_semi(diags, assert_nid);
+        }
+
+        self.dbg_exit("parse_assert_in_range", result)
+    }
+
+    /// Parser for asserting the current absolute address
+    /// For example: at(0x1000);
+    fn parse_at(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_at");
+        let mut result = false;
+
+        // Add the keyword as a child of the parent and advance
+        let at_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if self.expect_token_no_add(LexToken::OpenParen, diags) &&
+           self.expect_expr(at_nid, diags) &&
+           self.expect_token_no_add(LexToken::CloseParen, diags) {
+            result = self.expect_semi(diags, at_nid);
+        }
+
+        self.dbg_exit("parse_at", result)
+    }
+
+    /// Parser for padding a section to a power of two size
+    /// For example: roundup_pow2();
+    fn parse_roundup_pow2(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_roundup_pow2");
+        let mut result = false;
+
+        // Add the keyword as a child of the parent and advance
+        let roundup_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if self.expect_token_no_add(LexToken::OpenParen, diags) &&
+           self.expect_token_no_add(LexToken::CloseParen, diags) {
+            result = self.expect_semi(diags, roundup_nid);
+        }
+
+        self.dbg_exit("parse_roundup_pow2", result)
+    }
+
+    /// Parser for a list of bit fields packed into a 32 bit value
+    /// For example: pack32(<expr>:<width> [, <expr>:<width>, ...]);
+    fn parse_pack32(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_pack32");
+        let mut result = false;
+
+        // Add the keyword as a child of the parent and advance
+        let pack_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if !self.expect_token_no_add(LexToken::OpenParen, diags) {
+            return self.dbg_exit("parse_pack32", false);
+        }
+
+        // Each field adds the value and width expressions as children
+        loop {
+            self.split_label_token();
+            if !self.expect_builtin_expr(pack_nid, "a field value", diags) ||
+               !self.expect_token_no_add(LexToken::Colon, diags) ||
+               !self.expect_builtin_expr(pack_nid, "a field width", diags) {
+                break;
+            }
+
+            // Omit the comma from the AST to reduce clutter.
+            if self.peek().is_some_and(|tinfo| tinfo.tok == LexToken::Comma) {
+                self.tok_num += 1;
+                continue;
+            }
+
+            if self.expect_token_no_add(LexToken::CloseParen, diags) {
+                result = self.expect_semi(diags, pack_nid);
+            }
+            break;
+        }
+
+        self.dbg_exit("parse_pack32", result)
+    }
+
+    /// The lexer takes an identifier immediately followed by a colon as a
+    /// label, e.g. the field 'flags:8'.  Where a label can't appear, split
+    /// the current label token back into an identifier and a colon.
+    fn split_label_token(&mut self) {
+        if let Some(tinfo) = self.tv.get(self.tok_num) {
+            if tinfo.tok == LexToken::Label {
+                let colon_pos = tinfo.loc.end - 1;
+                let colon = TokenInfo { tok: LexToken::Colon, loc: colon_pos..tinfo.loc.end,
+                                        val: &tinfo.val[tinfo.val.len() - 1..] };
+                let ident = TokenInfo { tok: LexToken::Identifier, loc: tinfo.loc.start..colon_pos,
+                                        val: &tinfo.val[..tinfo.val.len() - 1] };
+                self.tv[self.tok_num] = ident;
+                self.tv.insert(self.tok_num + 1, colon);
+            }
+        }
+    }
+
+    /// Parser for a bracketed list of byte expressions
+    /// For example: wrbytes [<expr> [, <expr>, ...]] ;
+    fn parse_wrbytes(&mut self, parent_nid : NodeId, diags: &mut Diags) -> bool {
+
+        self.dbg_enter("parse_wrbytes");
+        let mut result = false;
+
+        // Add the wrbytes keyword as a child of the parent and advance
+        let wrbytes_nid = self.add_to_parent_and_advance(parent_nid);
+
+        if !self.expect_token_no_add(LexToken::OpenBracket, diags) {
+            return self.dbg_exit("parse_wrbytes", false);
+        }
+
+        // Loop until we run out of comma separated expressions.
+        // After each return from parse_pratt, we should be pointing at a
+        // comma or the close bracket.
+        loop {
+            let mut expr_opt = None;
+            if !self.parse_pratt(0, &mut expr_opt, diags) {
+                break; // error occurred
+            }
+            if let Some(expr_nid) = expr_opt {
+                wrbytes_nid.append(expr_nid, &mut self.arena);
+
+                // Omit the comma from the AST to reduce clutter.
+                if let Some(tinfo) = self.peek() {
+                    if tinfo.tok == LexToken::Comma {
+                        self.tok_num += 1;
+                        continue
+                    }
+                }
+
+                // If not a comma, then we expect the close bracket and semi.
+                if self.expect_token_no_add(LexToken::CloseBracket, diags) {
+                    result = self.expect_semi(diags, wrbytes_nid);
+                }
+                break;
+            } else {
+                // Empty list or trailing comma, e.g. wrbytes [1,];
+                let msg = "Expected an expression in the wrbytes list";
+                let tinfo = self.get_tinfo(wrbytes_nid);
+                diags.err1("AST_30", msg, tinfo.span());
+                break;
+            }
+        }
+
+        self.dbg_exit("parse_wrbytes", result)
+    }
+
+    /// Binding power of the operand of a prefix operation
+    const PREFIX_BP: u8 = 15;
+
     /// Returns the (lhs,rhs) binding power for any token
     /// Higher numbers are stronger binding.
     fn get_binding_power(tok: LexToken) -> (u8,u8) {
@@ -542,7 +1277,9 @@ impl<'toks> Ast<'toks> {
             LexToken::DoubleEq |
             LexToken::NEq |
             LexToken::LEq |
-            LexToken::GEq => (5,6),
+            LexToken::GEq |
+            LexToken::Less |
+            LexToken::Greater => (5,6),
             LexToken::DoubleAmpersand => (3,4),
             LexToken::DoublePipe => (1,2),
             // comma is one of the fall through cases with 0 precedence
@@ -572,29 +1309,70 @@ impl<'toks> Ast<'toks> {
 
         match lhs_tinfo.tok {
 
-            // Finding a close paren or a semi-colon terminates an expression.
+            // Finding a close paren, close bracket or a semi-colon terminates
+            // an expression.
             LexToken::CloseParen |
+            LexToken::CloseBracket |
             LexToken::Semicolon => {
                  /* top will be None */
                  *top = None;
             }
 
+            // Prefix operations: negation, bitwise NOT and logical NOT, e.g.
+            // -(1+2), ~0xFF or !(a == b).  Prefix operations bind more tightly
+            // than any binary operation, so !a == b parses as (!a) == b.
+            LexToken::Minus |
+            LexToken::Tilde |
+            LexToken::Bang => {
+                let op_span = lhs_tinfo.span();
+                let msg = format!("Expected an expression after '{}'", lhs_tinfo.val);
+                // A prefix minus is negation rather than subtraction
+                if lhs_tinfo.tok == LexToken::Minus {
+                    self.tv[self.tok_num].tok = LexToken::Negate;
+                }
+                *top = Some(self.arena.new_node(self.tok_num));
+                self.tok_num += 1;
+
+                let mut operand_opt = None;
+                if !self.parse_pratt(Ast::PREFIX_BP, &mut operand_opt, diags) {
+                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                }
+                match operand_opt {
+                    Some(operand_nid) => { top.unwrap().append(operand_nid, &mut self.arena); }
+                    None => {
+                        diags.err1("AST_42", &msg, op_span);
+                        return self.dbg_exit_pratt("parse_pratt", &None, false);
+                    }
+                }
+            }
+
             // This open paren is precedence control in an expression, e.g. (1+2)*3.
             // This is not an open paren associated with a built-in function.
             LexToken::OpenParen => {
                 // move past the open paren without storing in the AST.
+                let paren_tok_num = self.tok_num;
                 self.tok_num += 1;
                 // lhs is everything inside parentheses.
                 if !self.parse_pratt(0, top, diags) {
                     return self.dbg_exit_pratt("parse_pratt", &None, false);
                 }
                 // Open paren must have a matching close paren.
-                if !self.expect_token_no_add(LexToken::CloseParen, diags) {
-                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                match self.peek() {
+                    Some(tinfo) if tinfo.tok == LexToken::CloseParen => { self.tok_num += 1; }
+                    Some(_) => {
+                        self.err_no_close_paren(diags, paren_tok_num);
+                        return self.dbg_exit_pratt("parse_pratt", &None, false);
+                    }
+                    None => {
+                        self.err_no_input(diags);
+                        return self.dbg_exit_pratt("parse_pratt", &None, false);
+                    }
                 }
             }
 
-            // These simple atoms end up as leaf nodes in the AST
+            // These simple atoms end up as leaf nodes in the AST.
+            // An identifier here is a symbol defined on the command line.
+            LexToken::Identifier |
             LexToken::QuotedString |
             LexToken::Integer |
             LexToken::I64 |
@@ -624,7 +1402,8 @@ impl<'toks> Ast<'toks> {
             }
 
             // Build-in functions with a mandatory identifier inside parens
-            // ( <identifier> )
+            // and an optional second identifier
+            // ( <identifier> [, <identifier>] )
             LexToken::Sizeof => {
                 *top = Some(self.arena.new_node(self.tok_num));
                 self.tok_num += 1;
@@ -632,7 +1411,30 @@ impl<'toks> Ast<'toks> {
                 if !self.expect_token_no_add(LexToken::OpenParen, diags) {
                     return self.dbg_exit_pratt("parse_pratt", &None, false);
                 }
-                if !self.expect_token(LexToken::Identifier, diags, top.unwrap()) {
+                if !self.expect_builtin_ident(top.unwrap(), "a section or label name", diags) {
+                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                }
+                if self.peek().map(|tinfo| tinfo.tok) == Some(LexToken::Comma) {
+                    self.tok_num += 1;
+                    if !self.expect_builtin_ident(top.unwrap(), "a section or label name", diags) {
+                        return self.dbg_exit_pratt("parse_pratt", &None, false);
+                    }
+                }
+                if !self.expect_token_no_add(LexToken::CloseParen, diags) {
+                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                }
+            }
+
+            // Build-in functions with a mandatory identifier inside parens
+            // ( <identifier> )
+            LexToken::Crc32 => {
+                *top = Some(self.arena.new_node(self.tok_num));
+                self.tok_num += 1;
+
+                if !self.expect_token_no_add(LexToken::OpenParen, diags) {
+                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                }
+                if !self.expect_builtin_ident(top.unwrap(), "a section name", diags) {
                     return self.dbg_exit_pratt("parse_pratt", &None, false);
                 }
                 if !self.expect_token_no_add(LexToken::CloseParen, diags) {
@@ -643,6 +1445,10 @@ impl<'toks> Ast<'toks> {
 
             // Built-in functions with a non-optional expression inside parens
             // ( <expr> )
+            LexToken::IsPow2 |
+            LexToken::AbsValue |
+            LexToken::CountOnes |
+            LexToken::CountZeros |
             LexToken::ToI64 |
             LexToken::ToU64 => {
                 *top = Some(self.arena.new_node(self.tok_num));
@@ -651,7 +1457,7 @@ impl<'toks> Ast<'toks> {
                 if !self.expect_token_no_add(LexToken::OpenParen, diags) {
                     return self.dbg_exit_pratt("parse_pratt", &None, false);
                 }
-                if !self.expect_expr(top.unwrap(), diags) {
+                if !self.expect_builtin_expr(top.unwrap(), "an expression", diags) {
                     return self.dbg_exit_pratt("parse_pratt", &None, false);
                 }
                 if !self.expect_token_no_add(LexToken::CloseParen, diags) {
@@ -659,6 +1465,63 @@ impl<'toks> Ast<'toks> {
                 }
             }
 
+            // Built-in functions with a fixed number of comma separated
+            // expressions inside parens
+            // ( <expr>, <expr>, ... )
+            LexToken::Clamp => {
+                *top = Some(self.arena.new_node(self.tok_num));
+                self.tok_num += 1;
+
+                if !self.expect_builtin_args(top.unwrap(),
+                        &["a value", "a lower bound", "an upper bound"], diags) {
+                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                }
+            }
+            LexToken::AlignUp |
+            LexToken::AlignDown |
+            LexToken::BitReverse |
+            LexToken::Pow |
+            LexToken::Min |
+            LexToken::Max => {
+                let args = match lhs_tinfo.tok {
+                    LexToken::BitReverse => ["a value", "a bit width"],
+                    LexToken::Pow => ["a base", "an exponent"],
+                    LexToken::Min | LexToken::Max => ["a value", "a value"],
+                    _ => ["a value", "an alignment"],
+                };
+                *top = Some(self.arena.new_node(self.tok_num));
+                self.tok_num += 1;
+
+                if !self.expect_builtin_args(top.unwrap(), &args, diags) {
+                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                }
+            }
+
+            // The separator followed by one or more values
+            // ( <expr>, <expr> [, <expr>, ...] )
+            LexToken::Join => {
+                *top = Some(self.arena.new_node(self.tok_num));
+                self.tok_num += 1;
+
+                if !self.expect_token_no_add(LexToken::OpenParen, diags) ||
+                   !self.expect_builtin_expr(top.unwrap(), "a separator", diags) {
+                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                }
+                // At least one value must follow the separator
+                loop {
+                    if !self.expect_token_no_add(LexToken::Comma, diags) ||
+                       !self.expect_builtin_expr(top.unwrap(), "a value", diags) {
+                        return self.dbg_exit_pratt("parse_pratt", &None, false);
+                    }
+                    if self.peek().is_some_and(|tinfo| tinfo.tok != LexToken::Comma) {
+                        break;
+                    }
+                }
+                if !self.expect_token_no_add(LexToken::CloseParen, diags) {
+                    return self.dbg_exit_pratt("parse_pratt", &None, false);
+                }
+            }
+
             _ => {
                 let msg = format!("Invalid expression operand '{}'", lhs_tinfo.val);
                 diags.err1("AST_19", &msg, lhs_tinfo.span());
@@ -682,10 +1545,13 @@ impl<'toks> Ast<'toks> {
             // Screen out disallowed operations
             let op_tinfo = op_tinfo.unwrap();
             match op_tinfo.tok {
-                // Comma, close paren and semi are terminating conditions
-                // because some upper layer is specifically looking for them.
+                // Comma, close paren, close bracket and semi are terminating
+                // conditions because some upper layer is specifically looking
+                // for them.
                 LexToken::Comma |
+                LexToken::Colon |
                 LexToken::CloseParen |
+                LexToken::CloseBracket |
                 LexToken::Semicolon => { break; }
                 LexToken::ToI64 |
                 LexToken::ToU64 |
@@ -699,6 +1565,8 @@ impl<'toks> Ast<'toks> {
                 LexToken::DoublePipe |
                 LexToken::GEq |
                 LexToken::LEq |
+                LexToken::Less |
+                LexToken::Greater |
                 LexToken::Plus |
                 LexToken::Minus |
                 LexToken::Asterisk |
@@ -785,7 +1653,7 @@ impl<'toks> Ast<'toks> {
                 // fuzz test found this with print 1,;
                 let msg = "Statement ended unexpectedly";
                 let tinfo = self.get_tinfo(print_nid);
-                diags.err1("AST_21", &msg, tinfo.span());
+                diags.err1("AST_21", msg, tinfo.span());
                 result = false;
                 break;
             }
@@ -814,16 +1682,56 @@ impl<'toks> Ast<'toks> {
                     "Expected a section name after output") {
 
             // After the section identifier, an optional absolute starting address
+            let addr_ok = self.reject_signed_output_addr(diags);
             result = self.optional_token(&[LexToken::U64, LexToken::Integer], diags, output_nid);
-                        
+
+            // Then an optional alignment for the starting address
+            if result && self.peek().map(|tinfo| tinfo.tok) == Some(LexToken::Align) {
+                result = self.parse_output_align(output_nid, diags);
+            }
+
             // finally a semicolon
-            result &= self.expect_semi(diags, output_nid);
+            result = result && self.expect_semi(diags, output_nid) && addr_ok;
         }
 
         self.dbg_exit("parse_output", result)
     }
 
-    
+    /// The output start address is unsigned.  Report a clear error for a
+    /// signed address instead of a generic missing semicolon, then skip
+    /// the address to continue parsing the statement.
+    fn reject_signed_output_addr(&mut self, diags: &mut Diags) -> bool {
+        if let Some(tinfo) = self.peek() {
+            if tinfo.tok == LexToken::I64 {
+                let m = if tinfo.val.starts_with('-') {
+                    format!("Output start address cannot be negative, found '{}'", tinfo.val)
+                } else {
+                    format!("Output start address must be unsigned, found signed '{}'", tinfo.val)
+                };
+                diags.err1("AST_39", &m, tinfo.span());
+                self.tok_num += 1;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Parse the align modifier of the output statement
+    /// For example: output foo 0x1003 align 0x1000;
+    fn parse_output_align(&mut self, output_nid : NodeId, diags: &mut Diags) -> bool {
+        self.dbg_enter("parse_output_align");
+        let align_nid = self.add_to_parent_and_advance(output_nid);
+        if let Some(val_tinfo) = self.peek() {
+            if [LexToken::U64, LexToken::Integer].contains(&val_tinfo.tok) {
+                self.add_to_parent_and_advance(align_nid);
+                return self.dbg_exit("parse_output_align", true);
+            }
+        }
+        let align_tinfo = self.get_tinfo(align_nid);
+        diags.err1("AST_37", "Output align requires an unsigned number", align_tinfo.span());
+        self.dbg_exit("parse_output_align", false)
+    }
+
      /// Adds the current token as a child of the parent and advances
      /// the token index.  The current token MUST BE VALID!
     fn parse_leaf(&mut self, parent : NodeId) {
@@ -832,7 +1740,7 @@ impl<'toks> Ast<'toks> {
         self.tok_num += 1;
     }
 
-    pub fn get_tinfo(&self, nid: NodeId) -> &'toks TokenInfo {
+    pub fn get_tinfo(&self, nid: NodeId) -> &'toks TokenInfo<'_> {
         let tok_num = *self.arena[nid].get();
         &self.tv[tok_num]
     }
@@ -878,6 +1786,30 @@ impl<'toks> Ast<'toks> {
         Ok(())
     }
 
+    /// Prints each token with the line number of the token in the source,
+    /// e.g. 'L3: Wr8 wr8'
+    pub fn dump_tokens(&self, diags: &Diags) {
+        for tinfo in &self.tv {
+            println!("L{}: {:?} {}", diags.line_number(tinfo.loc.start), tinfo.tok, tinfo.val);
+        }
+    }
+
+    fn dump_text_r(&self, nid: NodeId, depth: usize, diags: &Diags) {
+        let tinfo = self.get_tinfo(nid);
+        println!("L{}: {}{}", diags.line_number(tinfo.loc.start), "    ".repeat(depth), tinfo.val);
+        for child_nid in nid.children(&self.arena) {
+            self.dump_text_r(child_nid, depth + 1, diags);
+        }
+    }
+
+    /// Prints the AST as indented text with the line number of each node
+    /// in the source.
+    pub fn dump_text(&self, diags: &Diags) {
+        for child_nid in self.root.children(&self.arena) {
+            self.dump_text_r(child_nid, 0, diags);
+        }
+    }
+
     /**
      * Recursively dumps the AST to the console.
      */
@@ -932,6 +1864,25 @@ pub struct Label {
    pub loc: Range<usize>,
 }
 
+/*******************************
+ * Const
+ ******************************/
+#[derive(Debug)]
+pub struct Const {
+    /// The const statement node
+    pub nid: NodeId,
+
+    /// The node of the value expression
+    pub expr_nid: NodeId,
+
+    /// Location in source code of the constant name
+    pub loc: Range<usize>,
+
+    /// Source offset of the end of the const statement.  References
+    /// must come after this offset.
+    pub end: usize,
+}
+
 /*******************************
  * Output
  ******************************/
@@ -941,6 +1892,8 @@ pub struct Output<'toks> {
     pub nid: NodeId,
     pub sec_nid: NodeId,
     pub addr_nid: Option<NodeId>,
+    /// The value of the optional align modifier
+    pub align_nid: Option<NodeId>,
 }
 
 impl<'toks> Output<'toks> {
@@ -951,9 +1904,17 @@ impl<'toks> Output<'toks> {
         // AST processing guarantees this exists.
         let sec_nid = children.next().unwrap();
 
-        // Optional start address is the second child.
-        let addr_nid = children.next();
-        Output { tinfo: ast.get_tinfo(nid), nid, sec_nid, addr_nid}
+        // Optional start address and align modifier follow.
+        let mut addr_nid = None;
+        let mut align_nid = None;
+        for child_nid in children {
+            match ast.get_tinfo(child_nid).tok {
+                LexToken::U64 | LexToken::Integer => { addr_nid = Some(child_nid); }
+                LexToken::Align => { align_nid = child_nid.children(&ast.arena).next(); }
+                _ => {}
+            }
+        }
+        Output { tinfo: ast.get_tinfo(nid), nid, sec_nid, addr_nid, align_nid }
     }
 }
 
@@ -967,6 +1928,17 @@ pub struct AstDb<'toks> {
     pub sections: HashMap<&'toks str, Section<'toks>>,
     pub labels: HashMap<&'toks str, Label>,
     pub output: Output<'toks>,
+
+    /// The optional top-level endian directive, which sets the default
+    /// byte order of wrx writes.
+    pub endian: Option<NodeId>,
+
+    /// Named constants defined at top level or in a section
+    pub consts: HashMap<&'toks str, Const>,
+
+    /// The names of all sections written to the output, including
+    /// the output section itself.
+    pub written_sections: HashSet<&'toks str>,
     //pub properties: HashMap<NodeId, NodeProperty>
 }
 
@@ -975,6 +1947,10 @@ impl<'toks> AstDb<'toks> {
     // Control recursion to some safe level.  100 is just a guesstimate.
     const MAX_RECURSION_DEPTH:usize = 100;
 
+    // Control the length of a chain of nested section writes.  Less than
+    // the recursion depth so that deep nesting gets a specific diagnostic.
+    const MAX_SECTION_DEPTH:usize = 64;
+
     /// Processes a section in the AST
     /// All section names are also label names
     fn record_section(diags: &mut Diags, sec_nid: NodeId, ast: &'toks Ast,
@@ -994,7 +1970,27 @@ impl<'toks> AstDb<'toks> {
             diags.err2("AST_29", &m, sec_tinfo.span(), orig_tinfo.span());
             return false;
         }
-        sections.insert(sec_str, Section::new(&ast, sec_nid));
+        sections.insert(sec_str, Section::new(ast, sec_nid));
+        true
+    }
+
+    /// Records the named constant defined by the const statement.  Constant
+    /// names are global, so a name may be defined only once.
+    fn record_const(diags: &mut Diags, const_nid: NodeId, ast: &'toks Ast,
+                    consts: &mut HashMap<&'toks str, Const>) -> bool {
+        debug!("AstDb::record_const: NodeId {}", const_nid);
+
+        // The parser guarantees the name, expression and semicolon children
+        let children: Vec<NodeId> = const_nid.children(&ast.arena).collect();
+        let name_tinfo = ast.get_tinfo(children[0]);
+        if let Some(orig) = consts.get(name_tinfo.val) {
+            let m = format!("Duplicate const name '{}'", name_tinfo.val);
+            diags.err2("AST_48", &m, name_tinfo.span(), orig.loc.clone());
+            return false;
+        }
+        let end = ast.get_tinfo(children[2]).loc.end;
+        consts.insert(name_tinfo.val, Const { nid: const_nid, expr_nid: children[1],
+                                              loc: name_tinfo.span(), end });
         true
     }
 
@@ -1012,9 +2008,9 @@ impl<'toks> AstDb<'toks> {
             let sec_name_nid_opt = children.next();
             if sec_name_nid_opt.is_none() {
                 // error, not enough children to reach section name
-                let m = format!("Missing section name");
+                let m = "Missing section name";
                 let section_tinfo = ast.get_tinfo(parent_nid);
-                diags.err1("AST_23", &m, section_tinfo.span());
+                diags.err1("AST_23", m, section_tinfo.span());
                 return false;
             }
             num += 1;
@@ -1022,9 +2018,9 @@ impl<'toks> AstDb<'toks> {
         let sec_name_nid_opt = children.next();
         if sec_name_nid_opt.is_none() {
             // error, specified section does not exist
-            let m = format!("Missing section name");
+            let m = "Missing section name";
             let section_tinfo = ast.get_tinfo(parent_nid);
-            diags.err1("AST_11", &m, section_tinfo.span());
+            diags.err1("AST_11", m, section_tinfo.span());
             return false;
         }
         let sec_name_nid = sec_name_nid_opt.unwrap();
@@ -1032,7 +2028,10 @@ impl<'toks> AstDb<'toks> {
         let sec_str = sec_tinfo.val;
         if !self.sections.contains_key(sec_str) {
             // error, specified section does not exist
-            let m = format!("Unknown or unreachable section name '{}'", sec_str);
+            let mut m = format!("Unknown or unreachable section name '{}'", sec_str);
+            if let Some(nearest) = suggest_name(sec_str, self.sections.keys().copied()) {
+                m.push_str(&format!(".  Did you mean '{}'?", nearest));
+            }
             diags.err1("AST_16", &m, sec_tinfo.span());
             return false;
         }
@@ -1045,20 +2044,139 @@ impl<'toks> AstDb<'toks> {
         if output.is_some() {
             let m = "Multiple output statements are not allowed.";
             let orig_tinfo = output.as_ref().unwrap().tinfo;
-            diags.err2("AST_10", &m, orig_tinfo.span(), tinfo.span());
+            diags.err2("AST_10", m, orig_tinfo.span(), tinfo.span());
             return false;
         }
 
-        *output = Some(Output::new(&ast,nid));
+        *output = Some(Output::new(ast,nid));
         true // succeed
     }
 
+    /// Records the top-level endian directive.  Only one is allowed since
+    /// it sets the default byte order for the whole output.
+    pub fn record_endian(diags: &mut Diags, nid: NodeId, ast: &'toks Ast,
+                         endian: &mut Option<NodeId>) -> bool {
+        let tinfo = ast.get_tinfo(nid);
+        if let Some(orig_nid) = endian {
+            let m = "Multiple top-level endian statements are not allowed.";
+            diags.err2("AST_45", m, tinfo.span(), ast.get_tinfo(*orig_nid).span());
+            return false;
+        }
+
+        *endian = Some(nid);
+        true // succeed
+    }
+
+    /// Section names are also label names, so a label with the same name
+    /// as a section would make references such as abs(name) ambiguous.
+    /// Reports an error for each label that reuses a section name.
+    fn check_label_collisions(diags: &mut Diags, ast: &'toks Ast,
+                              sections: &HashMap<&'toks str, Section<'toks>>) -> bool {
+        let mut result = true;
+        for sec_nid in ast.root.children(&ast.arena) {
+            if ast.get_tinfo(sec_nid).tok != LexToken::Section {
+                continue;
+            }
+            // Labels may be nested in if, else and repeat blocks
+            for nid in sec_nid.descendants(&ast.arena) {
+                let tinfo = ast.get_tinfo(nid);
+                if tinfo.tok != LexToken::Label {
+                    continue;
+                }
+                // Trim the trailing colon on the label.
+                let name = &tinfo.val[..tinfo.val.len() - 1];
+                if let Some(section) = sections.get(name) {
+                    let sec_name_nid = section.nid.children(&ast.arena).next().unwrap();
+                    let m = format!("Label name '{}' is already the name of a section", name);
+                    diags.err2("AST_40", &m, tinfo.span(), ast.get_tinfo(sec_name_nid).span());
+                    result = false;
+                }
+            }
+        }
+        result
+    }
+
+    /// A constant with the same name as a section or label would replace
+    /// the section or label name wherever the name appears in an
+    /// expression.  Reports an error for each constant that reuses a
+    /// section or label name.
+    fn check_const_collisions(diags: &mut Diags, ast: &'toks Ast,
+                              consts: &HashMap<&'toks str, Const>) -> bool {
+        let mut result = true;
+        for sec_nid in ast.root.children(&ast.arena) {
+            if ast.get_tinfo(sec_nid).tok != LexToken::Section {
+                continue;
+            }
+            let sec_name_tinfo = ast.get_tinfo(sec_nid.children(&ast.arena).next().unwrap());
+            if let Some(cnst) = consts.get(sec_name_tinfo.val) {
+                let m = format!("Const name '{}' is already the name of a section",
+                                sec_name_tinfo.val);
+                diags.err2("AST_53", &m, cnst.loc.clone(), sec_name_tinfo.span());
+                result = false;
+            }
+            // Labels may be nested in if, else and repeat blocks
+            for nid in sec_nid.descendants(&ast.arena) {
+                let tinfo = ast.get_tinfo(nid);
+                if tinfo.tok != LexToken::Label {
+                    continue;
+                }
+                // Trim the trailing colon on the label.
+                let name = &tinfo.val[..tinfo.val.len() - 1];
+                if let Some(cnst) = consts.get(name) {
+                    let m = format!("Const name '{}' is already the name of a label", name);
+                    diags.err2("AST_56", &m, cnst.loc.clone(), tinfo.span());
+                    result = false;
+                }
+            }
+        }
+        result
+    }
+
+    /// Validate the write of the section named by the specified identifier
+    /// node, then recursively validate the contents of the section.
+    fn validate_section_write_r(&mut self, rdepth: usize, sec_nid: NodeId, ast: &'toks Ast,
+                 nested_sections: &mut Vec<&'toks str>, diags: &mut Diags ) -> bool {
+        let sec_tinfo = ast.get_tinfo(sec_nid);
+        let sec_str = sec_tinfo.val;
+
+        // Make sure we haven't already recursed through this section.
+        if nested_sections.contains(&sec_str) {
+            let m = "Writing section creates a cycle.";
+            diags.err1("AST_6", m, sec_tinfo.span());
+            return false;
+        }
+
+        // Report deep section nesting separately from expression depth
+        // so the user can find the offending chain of writes.
+        if nested_sections.len() >= AstDb::MAX_SECTION_DEPTH {
+            let m = format!("Maximum section nesting depth ({}) exceeded writing section '{}'.  \
+                             Section chain: {} -> {}", AstDb::MAX_SECTION_DEPTH, sec_str,
+                             nested_sections.join(" -> "), sec_str);
+            diags.err1("AST_38", &m, sec_tinfo.span());
+            return false;
+        }
+
+        // add this section to our nested sections tracker
+        let mut result = true;
+        nested_sections.push(sec_str);
+        self.written_sections.insert(sec_str);
+        let section = self.sections.get(sec_str).unwrap();
+        let children = section.nid.children(&ast.arena);
+        for nid in children {
+            result &= self.validate_nesting_r(rdepth + 1, nid,
+                                              ast, nested_sections, diags);
+        }
+        // We're done with the section, so remove it from the nesting chain.
+        nested_sections.pop();
+        result
+    }
+
     /// Recursively validate the basic hierarchy of the AST object.
     /// Nested sections tracks the current hierarchy of section writes so we
     /// catch cycles.
     // TODO - After we validate nesting, we should create an iterator over the AST
     fn validate_nesting_r(&mut self, rdepth: usize, parent_nid: NodeId, ast: &'toks Ast,
-                 nested_sections: &mut HashSet<&'toks str>, diags: &mut Diags ) -> bool {
+                 nested_sections: &mut Vec<&'toks str>, diags: &mut Diags ) -> bool {
 
         debug!("AstDb::validate_nesting_r: ENTER at depth {} for parent nid: {}", rdepth, parent_nid);
 
@@ -1075,34 +2193,56 @@ impl<'toks> AstDb<'toks> {
         result &= match tinfo.tok {
             // Wr statement must specify a valid section name
             LexToken::Wr => {
-                if !self.validate_section_name(0, parent_nid, &ast, diags) {
+                if !self.validate_section_name(0, parent_nid, ast, diags) {
                     return false;
                 }
-                let mut children = parent_nid.children(&ast.arena);
-                // the section name is the first child of the output
+                // the section name is the first child of the wr
                 // AST processing guarantees this exists.
-                let sec_nid = children.next().unwrap();
-                let sec_tinfo = ast.get_tinfo(sec_nid);
-                let sec_str = sec_tinfo.val;
-
-                // Make sure we haven't already recursed through this section.
-                if nested_sections.contains(sec_str) {
-                    let m = "Writing section creates a cycle.";
-                    diags.err1("AST_6", &m, sec_tinfo.span());
-                    false
-                } else {
-                    // add this section to our nested sections tracker
-                    nested_sections.insert(sec_str);
-                    let section = self.sections.get(sec_str).unwrap();
-                    let children = section.nid.children(&ast.arena);
-                    for nid in children {
-                        result &= self.validate_nesting_r(rdepth + 1, nid,
-                                                          ast, nested_sections, diags);
+                let sec_nid = parent_nid.children(&ast.arena).next().unwrap();
+                self.validate_section_write_r(rdepth, sec_nid, ast, nested_sections, diags)
+            }
+            // A concatenated section writes each named section in order
+            LexToken::Equal => {
+                let sec_nids: Vec<NodeId> = parent_nid.children(&ast.arena).collect();
+                for (child_num, sec_nid) in sec_nids.into_iter().enumerate() {
+                    if !self.validate_section_name(child_num, parent_nid, ast, diags) {
+                        return false;
                     }
-                    // We're done with the section, so remove it from the nesting hash.
-                    nested_sections.remove(sec_str);
-                    result
+                    result &= self.validate_section_write_r(rdepth, sec_nid, ast,
+                                                            nested_sections, diags);
+                }
+                result
+            }
+            // XOR'd and sliced writes must specify a valid section name,
+            // followed by the key or the offset and length
+            LexToken::WrXor |
+            LexToken::WrSlice => {
+                if !self.validate_section_name(0, parent_nid, ast, diags) {
+                    return false;
+                }
+                let sec_nid = parent_nid.children(&ast.arena).next().unwrap();
+                result &= self.validate_section_write_r(rdepth, sec_nid, ast,
+                                                        nested_sections, diags);
+                // Skip the section name and validate the other expressions
+                let children = parent_nid.children(&ast.arena).skip(1);
+                for nid in children {
+                    result &= self.validate_nesting_r(rdepth + 1, nid,
+                                                      ast, nested_sections, diags);
+                }
+                result
+            }
+            // Conditional assert must specify a valid section name
+            LexToken::AssertIfWritten => {
+                if !self.validate_section_name(0, parent_nid, ast, diags) {
+                    return false;
+                }
+                // Skip the section name and validate the expression
+                let children = parent_nid.children(&ast.arena).skip(1);
+                for nid in children {
+                    result &= self.validate_nesting_r(rdepth + 1, nid,
+                                                      ast, nested_sections, diags);
                 }
+                result
             }
             _ => {
                 // When no children exist, this case terminates recursion.
@@ -1120,6 +2260,42 @@ impl<'toks> AstDb<'toks> {
         result
     }
 
+    /// Reports an error for each section written before its definition in
+    /// the source.  Forward references are valid, but the strict section
+    /// order option rejects them for readability.  Reports only the first
+    /// forward write of each section.
+    pub fn check_section_order(&self, ast: &'toks Ast, diags: &mut Diags) -> bool {
+        let mut result = true;
+        let mut reported = HashSet::new();
+        // Skip the root, which has no token
+        for nid in ast.root.descendants(&ast.arena).skip(1) {
+            let sec_nids: Vec<NodeId> = match ast.get_tinfo(nid).tok {
+                // The section name is the first child of a write
+                LexToken::Wr |
+                LexToken::WrXor |
+                LexToken::WrSlice => nid.children(&ast.arena).take(1).collect(),
+                // A concatenated section writes every child section
+                LexToken::Equal => nid.children(&ast.arena).collect(),
+                _ => { continue; }
+            };
+            for sec_nid in sec_nids {
+                let sec_tinfo = ast.get_tinfo(sec_nid);
+                let section = match self.sections.get(sec_tinfo.val) {
+                    Some(section) => section,
+                    None => { continue; }
+                };
+                if sec_tinfo.loc.start < section.tinfo.loc.start &&
+                   reported.insert(sec_tinfo.val) {
+                    let m = format!("Section '{}' is written before it is defined",
+                                    sec_tinfo.val);
+                    diags.err2("AST_46", &m, sec_tinfo.span(), section.tinfo.span());
+                    result = false;
+                }
+            }
+        }
+        result
+    }
+
     pub fn new(diags: &mut Diags, ast: &'toks Ast) -> anyhow::Result<AstDb<'toks>> {
         debug!("AstDb::new");
 
@@ -1128,23 +2304,42 @@ impl<'toks> AstDb<'toks> {
 
         let mut sections: HashMap<&'toks str, Section<'toks>> = HashMap::new();
         let mut output: Option<Output<'toks>> = None;
+        let mut endian: Option<NodeId> = None;
+        let mut consts: HashMap<&'toks str, Const> = HashMap::new();
 
         // First phase, record all sections, files, and the output.
         // These are defined only at top level so no need for recursion.
         for nid in ast.root.children(&ast.arena) {
             let tinfo = ast.get_tinfo(nid);
             result = result && match tinfo.tok {
-                LexToken::Section => Self::record_section(diags, nid, &ast, &mut sections),
-                LexToken::Output => Self::record_output(diags, nid, &ast, &mut output),
+                LexToken::Section => Self::record_section(diags, nid, ast, &mut sections),
+                LexToken::Output => Self::record_output(diags, nid, ast, &mut output),
+                LexToken::Endian => Self::record_endian(diags, nid, ast, &mut endian),
+                LexToken::Const => Self::record_const(diags, nid, ast, &mut consts),
                 _ => {
                     let msg = format!("Invalid top-level expression {}", tinfo.val);
                     diags.err1("AST_24", &msg, tinfo.span().clone());
-                    diags.note0("AST_25", "At top-level, allowed expressions are 'section' and 'output'");
+                    diags.note0("AST_25", "At top-level, allowed expressions are 'section', 'output', 'endian' and 'const'");
                     false
                 }
             };
         }
 
+        // Constants defined in a section are also global
+        for sec_nid in ast.root.children(&ast.arena) {
+            if ast.get_tinfo(sec_nid).tok != LexToken::Section {
+                continue;
+            }
+            for nid in sec_nid.children(&ast.arena) {
+                if ast.get_tinfo(nid).tok == LexToken::Const {
+                    result &= Self::record_const(diags, nid, ast, &mut consts);
+                }
+            }
+        }
+
+        result &= Self::check_label_collisions(diags, ast, &sections);
+        result &= Self::check_const_collisions(diags, ast, &consts);
+
         if !result {
             bail!("AST construction failed");
         }
@@ -1156,9 +2351,10 @@ impl<'toks> AstDb<'toks> {
         }
 
         let output_nid = output.as_ref().unwrap().nid;
-        let mut ast_db = AstDb { sections, labels: HashMap::new(), output: output.unwrap() };
+        let mut ast_db = AstDb { sections, labels: HashMap::new(), output: output.unwrap(),
+                                 endian, consts, written_sections: HashSet::new() };
 
-        if !ast_db.validate_section_name(0, output_nid, &ast, diags) {
+        if !ast_db.validate_section_name(0, output_nid, ast, diags) {
             bail!("AST construction failed");
         }
 
@@ -1170,8 +2366,8 @@ impl<'toks> AstDb<'toks> {
         let sec_str = sec_tinfo.val;
 
         // add the output section to our nested sections tracker
-        let mut nested_sections = HashSet::new();
-        nested_sections.insert(sec_str);
+        let mut nested_sections = vec![sec_str];
+        ast_db.written_sections.insert(sec_str);
         let section = ast_db.sections.get(sec_str).unwrap();
 
         // We're going to need this iterator more than once
@@ -1188,3 +2384,144 @@ impl<'toks> AstDb<'toks> {
         Ok(ast_db)
     }
 }
+
+/*****************************************************************************
+ * Include preprocessing
+ * Splices the contents of included files into the source before parsing.
+ * Includes may be nested.  Relative include paths are relative to the
+ * directory of the including file.
+ *****************************************************************************/
+struct Preprocessor {
+    /// Canonical paths of every file included so far
+    included: HashSet<PathBuf>,
+
+    /// Canonical paths of the files currently being expanded, used to
+    /// detect include cycles.
+    stack: Vec<PathBuf>,
+
+    /// The preprocessed output
+    out: String,
+
+    /// Name and contents of each included file.  The file ID of an
+    /// included file is its index plus one, since ID 0 is the top level file.
+    files: Vec<(String, String)>,
+
+    /// Map from ranges of the output back to the original files
+    segments: Vec<SourceSegment>,
+}
+
+/// The result of include preprocessing
+pub struct Preprocessed {
+    /// The source with all includes spliced in
+    pub text: String,
+
+    /// Name and contents of each included file in file ID order,
+    /// starting with file ID 1.
+    pub files: Vec<(String, String)>,
+
+    /// Map from ranges of text back to the original files
+    pub segments: Vec<SourceSegment>,
+}
+
+impl Preprocessor {
+
+    /// Returns the 1-based line number of the byte offset in fstr
+    fn line_num(fstr: &str, offset: usize) -> usize {
+        fstr[..offset].matches('\n').count() + 1
+    }
+
+    /// Returns the canonical path if possible, or the path as-is
+    fn canonical(path: &Path) -> PathBuf {
+        path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    /// Copies the range of fstr to the output and records the
+    /// originating file.
+    fn copy(&mut self, fstr: &str, range: Range<usize>, file_id: usize) {
+        if range.is_empty() {
+            return;
+        }
+        let start = self.out.len();
+        self.out.push_str(&fstr[range.clone()]);
+        self.segments.push(SourceSegment { start, end: self.out.len(),
+                                           file_id, file_offset: range.start });
+    }
+
+    /// Recursively expand the include statements in fstr, which is the
+    /// contents of the file at path with the specified file ID.
+    fn expand_r(&mut self, path: &Path, fstr: &str, file_id: usize) -> anyhow::Result<()> {
+        let mut copied_to = 0; // offset in fstr already copied to out
+        let mut lex = LexToken::lexer(fstr).spanned();
+        while let Some((tok, span)) = lex.next() {
+            if tok != LexToken::Include && tok != LexToken::IncludeOnce {
+                continue;
+            }
+            let line = Preprocessor::line_num(fstr, span.start);
+
+            // include "<quoted file path>";
+            let fname_span = match lex.next() {
+                Some((LexToken::QuotedString, fname_span)) => fname_span,
+                _ => bail!("[PROC_7]: {}:{}: Expected a quoted file path after include",
+                           path.display(), line),
+            };
+            let end = match lex.next() {
+                Some((LexToken::Semicolon, semi_span)) => semi_span.end,
+                _ => bail!("[PROC_23]: {}:{}: Expected ';' after include file path",
+                           path.display(), line),
+            };
+
+            let fname = &fstr[fname_span.start + 1 .. fname_span.end - 1];
+            let inc_name = match path.parent() {
+                Some(dir) => dir.join(fname),
+                None => PathBuf::from(fname),
+            };
+            let inc_path = Preprocessor::canonical(&inc_name);
+
+            if self.stack.contains(&inc_path) {
+                bail!("[PROC_9]: {}:{}: Including '{}' creates a cycle",
+                      path.display(), line, fname);
+            }
+
+            // Copy everything before the include statement
+            self.copy(fstr, copied_to..span.start, file_id);
+            copied_to = end;
+
+            if tok == LexToken::IncludeOnce && self.included.contains(&inc_path) {
+                debug!("Preprocessor::expand_r: skipping repeated include_once of {}",
+                       inc_path.display());
+                continue;
+            }
+
+            let inc_str = std::fs::read_to_string(&inc_path)
+                    .map_err(|err| anyhow!("[PROC_8]: {}:{}: Failed to read include file '{}': {}",
+                                           path.display(), line, fname, err))?
+                    .replace("\r\n","\n");
+
+            self.included.insert(inc_path.clone());
+            self.stack.push(inc_path.clone());
+            self.files.push((inc_name.display().to_string(), inc_str.clone()));
+            let inc_id = self.files.len();
+            self.expand_r(&inc_name, &inc_str, inc_id)?;
+            self.stack.pop();
+        }
+
+        self.copy(fstr, copied_to..fstr.len(), file_id);
+        Ok(())
+    }
+}
+
+/// Returns the source with all include and include_once statements replaced
+/// by the contents of the specified files, along with the map back to the
+/// original files for diagnostics.  The name is the path of the source file,
+/// which determines the location of relative include paths.
+pub fn preprocess(name: &str, fstr: &str) -> anyhow::Result<Preprocessed> {
+    let path = Preprocessor::canonical(Path::new(name));
+    let mut pp = Preprocessor { included: HashSet::new(), stack: Vec::new(),
+                                out: String::new(), files: Vec::new(),
+                                segments: Vec::new() };
+    pp.included.insert(path.clone());
+    pp.stack.push(path.clone());
+    // Expand with the name as given so diagnostics show relative file names
+    pp.expand_r(Path::new(name), fstr, 0)?;
+    Ok(Preprocessed { text: pp.out, files: pp.files, segments: pp.segments })
+}
@@ -1,13 +1,35 @@
 // codespan crate provide error reporting help
 use codespan_reporting::diagnostic::{Diagnostic,Label};
-use codespan_reporting::files::SimpleFile;
+use codespan_reporting::files::{Files, SimpleFiles};
 use codespan_reporting::term;
-use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use codespan_reporting::term::termcolor::{Buffer, ColorChoice, StandardStream};
+use std::cell::RefCell;
+use std::io::Write;
 use std::ops::Range;
 
+/// A contiguous range of the preprocessed source that came from a
+/// single original file.  Include preprocessing splices files together,
+/// so diagnostics use these segments to report the original file and offset.
+#[derive(Clone, Debug)]
+pub struct SourceSegment {
+    /// Start offset in the preprocessed source
+    pub start: usize,
+    /// End offset (exclusive) in the preprocessed source
+    pub end: usize,
+    /// File ID as returned by add_file.  The top level file is ID 0.
+    pub file_id: usize,
+    /// Offset of start in the original file
+    pub file_offset: usize,
+}
+
 pub struct Diags<'a> {
     writer: StandardStream,
-    source_map: SimpleFile<&'a str, &'a str>,
+    /// When set, diagnostics collect here instead of printing
+    deferred: Option<RefCell<Buffer>>,
+    source_map: SimpleFiles<&'a str, &'a str>,
+    /// Maps preprocessed offsets to original files.  When empty, all
+    /// offsets refer directly to the top level file.
+    segments: Vec<SourceSegment>,
     config: codespan_reporting::term::Config,
     verbosity: u64,
     pub noprint: bool,
@@ -15,15 +37,95 @@ pub struct Diags<'a> {
 
 impl<'a, 'msg> Diags<'a> {
     pub fn new(name: &'a str, fstr: &'a str, verbosity: u64, noprint: bool) -> Self {
+        let mut source_map = SimpleFiles::new();
+        source_map.add(name, fstr);
         Self {
             writer: StandardStream::stderr(ColorChoice::Always),
-            source_map: SimpleFile::new(name,fstr),
+            deferred: None,
+            source_map,
+            segments: Vec::new(),
             config: codespan_reporting::term::Config::default(),
             verbosity,
             noprint,
         }
     }
 
+    /// Adds an included source file and returns its file ID.
+    pub fn add_file(&mut self, name: &'a str, fstr: &'a str) -> usize {
+        self.source_map.add(name, fstr)
+    }
+
+    /// Sets the map from preprocessed source offsets to original files.
+    /// Segments must be sorted by start offset.
+    pub fn set_segments(&mut self, segments: Vec<SourceSegment>) {
+        self.segments = segments;
+    }
+
+    /// Returns the file ID and original file range of the specified
+    /// range in the preprocessed source.  Ranges that extend past the
+    /// end of a segment are truncated to the segment.
+    fn map_span(&self, loc: Range<usize>) -> (usize, Range<usize>) {
+        let seg = match self.segments.iter().rposition(|s| s.start <= loc.start) {
+            Some(idx) => &self.segments[idx],
+            None => return (0, loc),
+        };
+        let start = seg.file_offset + loc.start - seg.start;
+        let end = seg.file_offset + loc.end.min(seg.end).max(loc.start) - seg.start;
+        (seg.file_id, start..end)
+    }
+
+    /// Collects all subsequent diagnostics instead of printing them.
+    /// The caller either prints them with flush_deferred or discards them.
+    pub fn defer(&mut self) {
+        self.deferred = Some(RefCell::new(Buffer::ansi()));
+    }
+
+    /// Prints any diagnostics collected since calling defer.
+    pub fn flush_deferred(&self) {
+        if let Some(buf) = &self.deferred {
+            let _ = self.writer.lock().write_all(buf.borrow().as_slice());
+            buf.borrow_mut().clear();
+        }
+    }
+
+    fn emit(&self, diag: &Diagnostic<usize>) {
+        match &self.deferred {
+            Some(buf) => {
+                let _ = term::emit(&mut *buf.borrow_mut(), &self.config, &self.source_map, diag);
+            }
+            None => {
+                let _ = term::emit(&mut self.writer.lock(), &self.config, &self.source_map, diag);
+            }
+        }
+    }
+
+    /// Returns the 1-based line number of the byte offset in the source code.
+    pub fn line_number(&self, offset: usize) -> usize {
+        let (file_id, loc) = self.map_span(offset..offset);
+        self.source_map.location(file_id, loc.start).map_or(0, |loc| loc.line_number)
+    }
+
+    /// Returns the human readable file:line:column of the byte offset
+    /// in the source code.
+    pub fn location_str(&self, offset: usize) -> String {
+        let (file_id, loc) = self.map_span(offset..offset);
+        let name = self.source_map.name(file_id).unwrap_or("?");
+        match self.source_map.location(file_id, loc.start) {
+            Some(loc) => format!("{}:{}:{}", name, loc.line_number, loc.column_number),
+            None => format!("{}:?", name),
+        }
+    }
+
+    fn primary(&self, loc: Range<usize>) -> Label<usize> {
+        let (file_id, loc) = self.map_span(loc);
+        Label::primary(file_id, loc)
+    }
+
+    fn secondary(&self, loc: Range<usize>) -> Label<usize> {
+        let (file_id, loc) = self.map_span(loc);
+        Label::secondary(file_id, loc)
+    }
+
     /// Writes the diagnostic to the terminal with primary
     /// code location.
     pub fn warn(&self, code: &str, msg: &'msg str) {
@@ -33,8 +135,19 @@ impl<'a, 'msg> Diags<'a> {
                 .with_code(code)
                 .with_message(msg);
 
-        let _ = term::emit(&mut self.writer.lock(), &self.config,
-                           &self.source_map, &diag);
+        self.emit(&diag);
+    }
+
+    /// Writes the diagnostic to the terminal with primary
+    /// code location.
+    pub fn warn1(&self, code: &str, msg: &'msg str, loc: Range<usize>) {
+        if self.verbosity == 0 { return; }
+
+        let diag = Diagnostic::warning()
+                .with_code(code)
+                .with_message(msg)
+                .with_labels(vec![self.primary(loc)]);
+        self.emit(&diag);
     }
 
     /// Writes the diagnostic to the terminal with primary
@@ -45,8 +158,7 @@ impl<'a, 'msg> Diags<'a> {
         let diag = Diagnostic::error()
                 .with_code(code)
                 .with_message(msg);
-        let _ = term::emit(&mut self.writer.lock(), &self.config,
-                           &self.source_map, &diag);
+        self.emit(&diag);
     }
 
     /// Writes the diagnostic to the terminal with primary
@@ -58,9 +170,8 @@ impl<'a, 'msg> Diags<'a> {
         let diag = Diagnostic::error()
                 .with_code(code)
                 .with_message(msg)
-                .with_labels(vec![Label::primary((), loc)]);
-        let _ = term::emit(&mut self.writer.lock(), &self.config,
-                           &self.source_map, &diag);
+                .with_labels(vec![self.primary(loc)]);
+        self.emit(&diag);
     }
 
     /// Writes the diagnostic to the terminal with primary
@@ -70,8 +181,7 @@ impl<'a, 'msg> Diags<'a> {
         let diag = Diagnostic::note()
                 .with_code(code)
                 .with_message(msg);
-        let _ = term::emit(&mut self.writer.lock(), &self.config,
-                           &self.source_map, &diag);
+        self.emit(&diag);
     }
 
     /// Writes the diagnostic to the terminal with primary
@@ -83,9 +193,8 @@ impl<'a, 'msg> Diags<'a> {
         let diag = Diagnostic::note()
                 .with_code(code)
                 .with_message(msg)
-                .with_labels(vec![Label::primary((), loc)]);
-        let _ = term::emit(&mut self.writer.lock(), &self.config,
-                           &self.source_map, &diag);
+                .with_labels(vec![self.primary(loc)]);
+        self.emit(&diag);
     }
 
     /// Writes the diagnostic to the terminal with primary
@@ -98,10 +207,9 @@ impl<'a, 'msg> Diags<'a> {
         let diag = Diagnostic::error()
                 .with_code(code)
                 .with_message(msg)
-                .with_labels(vec![Label::primary((), loc1),
-                                  Label::secondary((), loc2)]);
+                .with_labels(vec![self.primary(loc1),
+                                  self.secondary(loc2)]);
 
-        let _ = term::emit(&mut self.writer.lock(), &self.config,
-                           &self.source_map, &diag);
+        self.emit(&diag);
     }
 }
@@ -2,9 +2,12 @@ use std::{convert::TryFrom, io::Read};
 use ir::{DataType, IR, IRKind};
 use irdb::IRDb;
 use diags::Diags;
-use std::{any::Any, convert::TryInto, io::Write};
+use std::{any::Any, io::Write};
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
 use std::fs::File;
+use std::time::{SystemTime, UNIX_EPOCH};
 use anyhow::{Result,anyhow};
 
 #[allow(unused_imports)]
@@ -14,6 +17,9 @@ use log::{error, warn, info, debug, trace};
 pub struct Location {
     img: u64,
     sec: u64,
+    /// The absolute address, which is normally the image offset plus the
+    /// starting address, but may be relative to a section @base address.
+    abs: u64,
 }
 pub struct Parameter {
     data_type: DataType,
@@ -40,6 +46,15 @@ impl Parameter {
         }
     }
 
+    /// Returns the 64-bit pattern of any numeric type as a u64.  Used when
+    /// relaxed typing promotes mixed I64 and U64 operands to U64.
+    fn to_u64_promoted(&self) -> u64 {
+        match self.data_type {
+            DataType::I64 => { *self.val.downcast_ref::<i64>().unwrap() as u64 },
+            _ => self.to_u64(),
+        }
+    }
+
     fn to_u64_mut(&mut self) -> &mut u64 {
         match self.data_type {
             // Integers stored as i64
@@ -79,6 +94,29 @@ impl Parameter {
     }
 }
 
+/// Optional engine behaviors, typically selected from the command line.
+#[derive(Clone, Debug, Default)]
+pub struct EngineOptions {
+    /// Print the changed IR locations after each fixed point iteration
+    pub dump_fixedpoint: bool,
+    /// Warn about any section with a final size larger than this threshold
+    pub warn_section_size: Option<u64>,
+    /// Custom character to byte mapping for strings written with wrs
+    pub encoding_table: Option<HashMap<char, u8>>,
+    /// Fail if the output image would grow larger than this many bytes
+    pub max_image_size: Option<u64>,
+    /// Fail if the fixed point iteration needs more passes than this.
+    /// None uses a generous default limit.
+    pub max_iterations: Option<usize>,
+    /// Omit the build date from header blocks so the output is reproducible
+    pub deterministic: bool,
+    /// Flush print output only at each newline rather than after every
+    /// print statement
+    pub line_buffered: bool,
+    /// Do not warn when a wrx value does not fit the write width
+    pub no_truncation_warnings: bool,
+}
+
 pub struct Engine {
     parms: Vec<RefCell<Parameter>>,
     ir_locs: Vec<Location>,
@@ -91,13 +129,67 @@ pub struct Engine {
     /// Stack of sections for debug use
     sec_names: Vec<String>,
 
+    /// Stack of section base information.  For sections with a @base
+    /// address, holds the absolute address and image offset at the start
+    /// of the section.  None for sections without a base address.
+    sec_bases: Vec<Option<(u64, u64)>>,
+
+    /// Stack of locations where each nested wr_slice started
+    slice_starts: Vec<Location>,
+
     /// Starting absolute address, just copied from irdb for convenience
     start_addr: u64,
+
+    /// Size of the output image at the end of the last iteration
+    image_size: u64,
+
+    /// The last size of each section computed while the section start
+    /// did not precede its end.  Stands in for transient sizes.
+    last_sizes: HashMap<String, u64>,
+
+    /// Build date for header blocks, captured once so that every
+    /// iteration and the execution agree.  None in deterministic mode.
+    build_date: Option<String>,
+
+    /// True for each IR in an if or else block not taken in the last
+    /// iteration.  Execution skips the same IR.
+    skipped: Vec<bool>,
+
+    /// For each if, the last value of the condition and the number of
+    /// times the value changed between iterations.
+    branch_flips: HashMap<usize, (bool, usize)>,
+
+    /// Number of passes the last iterate took to converge
+    iter_count: usize,
+
+    options: EngineOptions,
+}
+
+/// Returns the current UTC date and time, e.g. "2021-03-04 05:06:07 UTC"
+fn utc_date_now() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, day_secs) = (secs / 86400, secs % 86400);
+
+    // Convert days since 1970-01-01 to a civil date.  See
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day,
+            day_secs / 3600, day_secs % 3600 / 60, day_secs % 60)
 }
 
 fn get_wrx_byte_width(ir : &IR) -> usize {
     let width = match ir.kind {
-        IRKind::Wr8  => 1,
+        IRKind::Wr8  |
+        IRKind::Wrbyte => 1,
         IRKind::Wr16 => 2,
         IRKind::Wr24 => 3,
         IRKind::Wr32 => 4,
@@ -105,14 +197,82 @@ fn get_wrx_byte_width(ir : &IR) -> usize {
         IRKind::Wr48 => 6,
         IRKind::Wr56 => 7,
         IRKind::Wr64 => 8,
+        IRKind::Wr16be => 2,
+        IRKind::Wr24be => 3,
+        IRKind::Wr32be => 4,
+        IRKind::Wr40be => 5,
+        IRKind::Wr48be => 6,
+        IRKind::Wr56be => 7,
+        IRKind::Wr64be => 8,
         bad => { panic!("Called get_wrx_byte_width with {:?}", bad); }
     };
 
     width
 }
 
+fn is_wrx_big_endian(ir : &IR) -> bool {
+    matches!(ir.kind, IRKind::Wr16be | IRKind::Wr24be | IRKind::Wr32be |
+                      IRKind::Wr40be | IRKind::Wr48be | IRKind::Wr56be |
+                      IRKind::Wr64be)
+}
+
+/// Computes the IEEE 802.3 CRC32 of the bytes, e.g. as used by zip and
+/// ethernet.  The CRC of "123456789" is 0xCBF43926.
+fn crc32_ieee(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Converts a host size to a u64 size.  Reports an error and returns None
+/// if the host size does not fit.
+fn size_to_u64(sz: usize, ir: &IR, diags: &mut Diags) -> Option<u64> {
+    match u64::try_from(sz) {
+        Ok(sz) => Some(sz),
+        Err(_) => {
+            let msg = format!("Size {} of {:?} does not fit in a U64", sz, ir.kind);
+            diags.err1("EXEC_39", &msg, ir.src_loc.clone());
+            None
+        }
+    }
+}
+
+/// Advances the current location by the specified size.  Reports an error
+/// and leaves the location unchanged if any address would overflow.
+fn advance_location(sz: u64, ir: &IR, diags: &mut Diags, current: &mut Location) -> bool {
+    let img = current.img.checked_add(sz);
+    let sec = current.sec.checked_add(sz);
+    let abs = current.abs.checked_add(sz);
+    if let (Some(img), Some(sec), Some(abs)) = (img, sec, abs) {
+        current.img = img;
+        current.sec = sec;
+        current.abs = abs;
+        return true;
+    }
+
+    let msg = format!("Advancing address 0x{:X} by {} bytes for {:?} overflows a U64",
+                      current.abs, sz, ir.kind);
+    diags.err1("EXEC_38", &msg, ir.src_loc.clone());
+    false
+}
+
 impl Engine {
 
+    /// A condition that changes this many times between iterations depends
+    /// on the layout that it selects and will never settle.
+    const MAX_BRANCH_FLIPS: usize = 4;
+
+    /// Limit on fixed point passes when the caller does not specify one.
+    /// Sizes that depend on their own layout, e.g. a forward reference that
+    /// selects a pad count, can oscillate forever.
+    const DEFAULT_MAX_ITERATIONS: usize = 1000;
+
     /// Debug trace that produces an indented output with section name to make
     /// section nesting more readable.
     fn trace(&self, msg: &str) {
@@ -124,24 +284,44 @@ impl Engine {
         trace!("{}{}: {}", "    ".repeat(sec_depth), sec_name, msg);
     }
 
+    /// Returns the string written by a wrs or header statement
+    fn evaluate_write_string(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags) -> Option<String> {
+        let xstr = self.evaluate_string_expr(ir, irdb, diags)?;
+        if ir.kind != IRKind::Header {
+            return Some(xstr);
+        }
+
+        // The header block is the string followed by metadata lines.  The
+        // fixed width size keeps the header size stable across iterations.
+        let mut header = format!("{}\nSize: {:#018X}\n", xstr, self.image_size);
+        if let Some(date) = &self.build_date {
+            header.push_str(&format!("Date: {}\n", date));
+        }
+        Some(header)
+    }
+
+    // Used for Wrs and Header
     fn iterate_wrs(&mut self, ir: &IR, irdb: &IRDb, diags: &mut Diags,
                     current: &mut Location) -> bool {
         self.trace(format!("Engine::iterate_wrs: img {}, sec {}",
                    current.img, current.sec).as_str());
 
-        let xstr_opt = self.evaluate_string_expr(ir, irdb, diags);
+        let xstr_opt = self.evaluate_write_string(ir, irdb, diags);
         if xstr_opt.is_none() {
             return false;
         }
 
-        let xstr = xstr_opt.unwrap();
+        let bytes_opt = self.encode_string(&xstr_opt.unwrap(), ir, diags);
+        if bytes_opt.is_none() {
+            return false;
+        }
 
-        // Will panic if usize does not fit in u64
-        let sz = xstr.len() as u64;
-        current.img += sz;
-        current.sec += sz;
-        
-        true
+        let sz_opt = size_to_u64(bytes_opt.unwrap().len(), ir, diags);
+        if sz_opt.is_none() {
+            return false;
+        }
+
+        advance_location(sz_opt.unwrap(), ir, diags, current)
     }
 
     // Used for Wr8 though Wr64
@@ -187,19 +367,48 @@ impl Engine {
         }
 
         // total size is the size of the wrx times the optional repeat count
-        let sz = byte_size * repeat_count;
+        let sz_opt = byte_size.checked_mul(repeat_count);
+        if sz_opt.is_none() {
+            let msg = format!("Size of {:?} with repeat count {} overflows a U64",
+                              ir.kind, repeat_count);
+            diags.err1("EXEC_37", &msg, ir.src_loc.clone());
+            return false;
+        }
+
+        let sz = sz_opt.unwrap();
         self.trace(format!("Engine::iterate_wrx-{}: size is {}", byte_size * 8, sz).as_str());
-        // Will panic if usize does not fit in u64
-        current.img += sz;
-        current.sec += sz;
-        
-        result
+        result & advance_location(sz, ir, diags, current)
+    }
+
+    /// Used for fill, which writes count bytes
+    fn iterate_fill(&mut self, ir: &IR, irdb: &IRDb, diags: &mut Diags,
+                    current: &mut Location) -> bool {
+        assert!(ir.operands.len() == 2);
+        self.trace(format!("Engine::iterate_fill: img {}, sec {}",
+                   current.img, current.sec).as_str());
+
+        let count = {
+            let op = self.parms[ir.operands[0]].borrow();
+            if op.data_type == DataType::U64 {
+                op.to_u64()
+            } else {
+                let temp = op.to_i64();
+                if temp < 0 {
+                    let msg = format!("Fill count cannot be negative, but found '{}'", temp);
+                    diags.err1("EXEC_53", &msg, irdb.parms[ir.operands[0]].src_loc.clone());
+                    return false;
+                }
+                temp as u64
+            }
+        };
+
+        advance_location(count, ir, diags, current)
     }
 
     /// Used for wr file
     /// There is nothing really to iterate other than advancing
     /// the location counter by the size of the file.
-    fn iterate_wrf(&mut self, ir: &IR, irdb: &IRDb, _diags: &mut Diags,
+    fn iterate_wrf(&mut self, ir: &IR, irdb: &IRDb, diags: &mut Diags,
                         current: &mut Location) -> bool {
         
         // The operand is a file path
@@ -218,36 +427,63 @@ impl Engine {
                                 img {}, sec {}", file_path, byte_size,
                                 current.img, current.sec).as_str());
 
-        current.img += byte_size;
-        current.sec += byte_size;
-        
-        true
+        advance_location(byte_size, ir, diags, current)
+    }
+
+
+    /// Convert the string to the bytes written to the output.  Without an
+    /// encoding table, these are just the UTF-8 bytes of the string.
+    /// Returns None if the encoding table does not map a character.
+    fn encode_string(&self, xstr: &str, ir: &IR, diags: &mut Diags) -> Option<Vec<u8>> {
+        let table = match &self.options.encoding_table {
+            Some(table) => table,
+            None => { return Some(xstr.as_bytes().to_vec()); }
+        };
+
+        let mut bytes = Vec::with_capacity(xstr.len());
+        for ch in xstr.chars() {
+            match table.get(&ch) {
+                Some(&byte) => bytes.push(byte),
+                None => {
+                    let msg = format!("Character {:?} is not in the encoding table", ch);
+                    diags.err1("EXEC_42", &msg, ir.src_loc.clone());
+                    return None;
+                }
+            }
+        }
+        Some(bytes)
     }
 
+    /// Returns the string representation of the operand, or None if the
+    /// operand type has no string representation.
+    fn stringify_operand(&self, op_num: usize, irdb: &IRDb, diags: &mut Diags) -> Option<String> {
+        let op = self.parms[op_num].borrow();
+        debug!("Processing string expr operand {} with data type {:?}", op_num, op.data_type);
+        match op.data_type {
+            DataType::QuotedString => Some(op.to_str().to_string()),
+            DataType::U64 => Some(format!("{:#X}", op.to_u64())),
+            DataType::Integer |
+            DataType::I64 => Some(format!("{}", op.to_i64())),
+            bad => {
+                let msg = format!("Cannot stringify type '{:?}'", bad );
+                let src_loc = irdb.parms[op_num].src_loc.clone();
+                diags.err1("EXEC_14", &msg, src_loc);
+                None
+            }
+        }
+    }
 
     /// Compute the string representation of the expression.
     /// Returns the resulting string in xstr.
     /// If the diags noprint option is true, suppress printing.
     /// Returns None of failure
     fn evaluate_string_expr(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags) -> Option<String> {
-        let num_ops = ir.operands.len();
         let mut result = true;
         let mut xstr = String::new();
-        for local_op_num in 0..num_ops {
-            let op_num = ir.operands[local_op_num];
-            let op = self.parms[op_num].borrow();
-            debug!("Processing string expr operand {} with data type {:?}", local_op_num, op.data_type);
-            match op.data_type {
-                DataType::QuotedString => { xstr.push_str(op.to_str()); }
-                DataType::U64 => { xstr.push_str(format!("{:#X}", op.to_u64()).as_str()); }
-                DataType::Integer |
-                DataType::I64 => { xstr.push_str(format!("{}", op.to_i64()).as_str()); }
-                bad => {
-                    let msg = format!("Cannot stringify type '{:?}'", bad );
-                    let src_loc = irdb.parms[op_num].src_loc.clone();
-                    diags.err1("EXEC_14", &msg, src_loc);
-                    result = false;
-                }
+        for &op_num in &ir.operands {
+            match self.stringify_operand(op_num, irdb, diags) {
+                Some(s) => { xstr.push_str(&s); }
+                None => { result = false; }
             }
         }
 
@@ -261,179 +497,502 @@ impl Engine {
 
 
     fn do_u64_add(&self, ir: &IR, in0: u64, in1: u64, out: &mut u64, diags: &mut Diags) -> bool {
-        let check = in0.checked_add(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_add(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Add expression '{} + {}' will overflow type U64", in0, in1);
             diags.err1("EXEC_1", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_i64_add(&self, ir: &IR, in0: i64, in1: i64, out: &mut i64, diags: &mut Diags) -> bool {
-        let check = in0.checked_add(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_add(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Add expression '{} + {}' will overflow type I64", in0, in1);
             diags.err1("EXEC_21", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_u64_sub(&self, ir: &IR, in0: u64, in1: u64, out: &mut u64, diags: &mut Diags) -> bool {
-        let check = in0.checked_sub(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_sub(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Subtract expression '{} - {}' will underflow type U64", in0, in1);
             diags.err1("EXEC_4", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_i64_sub(&self, ir: &IR, in0: i64, in1: i64, out: &mut i64, diags: &mut Diags) -> bool {
-        let check = in0.checked_sub(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_sub(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Subtract expression '{} - {}' will underflow type I64", in0, in1);
             diags.err1("EXEC_24", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_u64_mul(&self, ir: &IR, in0: u64, in1: u64, out: &mut u64, diags: &mut Diags) -> bool {
-        let check = in0.checked_mul(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_mul(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Multiply expression '{} * {}' will overflow type U64", in0, in1);
             diags.err1("EXEC_6", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_i64_mul(&self, ir: &IR, in0: i64, in1: i64, out: &mut i64, diags: &mut Diags) -> bool {
-        let check = in0.checked_mul(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_mul(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Multiply expression '{} * {}' will overflow data type I64", in0, in1);
             diags.err1("EXEC_26", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_u64_div(&self, ir: &IR, in0: u64, in1: u64, out: &mut u64, diags: &mut Diags) -> bool {
-        let check = in0.checked_div(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_div(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Exception in divide expression '{} / {}'", in0, in1);
             diags.err1("EXEC_7", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_u64_mod(&self, ir: &IR, in0: u64, in1: u64, out: &mut u64, diags: &mut Diags) -> bool {
-        let check = in0.checked_rem(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_rem(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Exception in modulo expression '{} % {}'", in0, in1);
             diags.err1("EXEC_28", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_i64_div(&self, ir: &IR, in0: i64, in1: i64, out: &mut i64, diags: &mut Diags) -> bool {
-        let check = in0.checked_div(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_div(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Exception in divide expression '{} / {}'", in0, in1);
             diags.err1("EXEC_27", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_i64_mod(&self, ir: &IR, in0: i64, in1: i64, out: &mut i64, diags: &mut Diags) -> bool {
-        let check = in0.checked_rem(in1);
-        if check.is_none() {
+        if let Some(val) = in0.checked_rem(in1) {
+            *out = val;
+            true
+        } else {
             let msg = format!("Exception in modulo expression '{} % {}'", in0, in1);
             diags.err1("EXEC_30", &msg, ir.src_loc.clone());
             false
-        } else {
-            *out = check.unwrap();
-            true
         }
     }
 
     fn do_u64_shl(&self, ir: &IR, in0: u64, in1: u64, out: &mut u64, diags: &mut Diags) -> bool {
         let mut result = true;
-        let shift_amount = u32::try_from(in1);
-        if shift_amount.is_err() {
+        if let Ok(shift_amount) = u32::try_from(in1) {
+            *out = in0.checked_shl(shift_amount).unwrap_or(0);
+        } else {
             let msg = format!("Shift amount {} is too large in Left Shift expression '{} << {}'", in1, in0, in1);
             diags.err1("EXEC_9", &msg, ir.src_loc.clone());
             result = false;
-        } else {
-            *out = in0.checked_shl(shift_amount.unwrap()).unwrap_or(0);
         }
         result
     }
 
     fn do_i64_shl(&self, ir: &IR, in0: i64, in1: i64, out: &mut i64, diags: &mut Diags) -> bool {
         let mut result = true;
-        let shift_amount = u32::try_from(in1);
-        if shift_amount.is_err() {
+        if let Ok(shift_amount) = u32::try_from(in1) {
+            *out = in0.checked_shl(shift_amount).unwrap_or(0);
+        } else {
             let msg = format!("Shift amount {} is too large in Left Shift expression '{} << {}'", in1, in0, in1);
             diags.err1("EXEC_29", &msg, ir.src_loc.clone());
             result = false;
-        } else {
-            *out = in0.checked_shl(shift_amount.unwrap()).unwrap_or(0);
         }
         result
     }
 
     fn do_u64_shr(&self, ir: &IR, in0: u64, in1: u64, out: &mut u64, diags: &mut Diags) -> bool {
         let mut result = true;
-        let shift_amount = u32::try_from(in1);
-        if shift_amount.is_err() {
+        if let Ok(shift_amount) = u32::try_from(in1) {
+            *out = in0.checked_shr(shift_amount).unwrap_or(0);
+        } else {
             let msg = format!("Shift amount {} is too large in Right Shift expression '{} >> {}'",
                             in1, in0, in1);
             diags.err1("EXEC_10", &msg, ir.src_loc.clone());
             result = false;
-        } else {
-            *out = in0.checked_shr(shift_amount.unwrap()).unwrap_or(0);
         }
         result
     }
 
     fn do_i64_shr(&self, ir: &IR, in0: i64, in1: i64, out: &mut i64, diags: &mut Diags) -> bool {
         let mut result = true;
-        let shift_amount = u32::try_from(in1);
-        if shift_amount.is_err() {
+        if let Ok(shift_amount) = u32::try_from(in1) {
+            *out = in0.checked_shr(shift_amount).unwrap_or(0);
+        } else {
             let msg = format!("Shift amount {} is too large in Right Shift expression '{} >> {}'",
                             in1, in0, in1);
             diags.err1("EXEC_20", &msg, ir.src_loc.clone());
             result = false;
-        } else {
-            *out = in0.checked_shr(shift_amount.unwrap()).unwrap_or(0);
         }
         result
     }
 
+    /// Determine if the input operand is a power of two.  Negative values
+    /// are never a power of two.
+    fn iterate_is_pow2(&mut self, ir: &IR, current: &Location) -> bool {
+        self.trace(format!("Engine::iterate_is_pow2: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 2);
+        let in_parm0 = self.parms[ir.operands[0]].borrow();
+        let mut out_parm = self.parms[ir.operands[1]].borrow_mut();
+        let x = match in_parm0.data_type {
+            DataType::U64 => in_parm0.to_u64(),
+            _ => {
+                let in0 = in_parm0.to_i64();
+                if in0 < 0 { 0 } else { in0 as u64 }
+            }
+        };
+        *out_parm.to_u64_mut() = (x != 0 && (x & (x - 1)) == 0) as u64;
+        true
+    }
+
+    /// Count the one or zero bits in the input operand.  Signed and
+    /// ambiguous integers use their 64-bit two's complement bit pattern.
+    fn iterate_count_bits(&mut self, ir: &IR, operation: IRKind, current: &Location) -> bool {
+        self.trace(format!("Engine::iterate_count_bits: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 2);
+        let in_parm0 = self.parms[ir.operands[0]].borrow();
+        let mut out_parm = self.parms[ir.operands[1]].borrow_mut();
+        let x = match in_parm0.data_type {
+            DataType::U64 => in_parm0.to_u64(),
+            _ => in_parm0.to_i64() as u64,
+        };
+        *out_parm.to_u64_mut() = match operation {
+            IRKind::CountOnes => x.count_ones(),
+            IRKind::CountZeros => x.count_zeros(),
+            bad => panic!("Forgot to handle bit count {:?}", bad),
+        } as u64;
+        true
+    }
+
+    /// Reverse the low bits of the value operand.  The width operand is the
+    /// number of bits to reverse, from 1 to 64.  Higher bits are discarded.
+    fn iterate_bit_reverse(&mut self, ir: &IR, irdb: &IRDb, current: &Location,
+                           diags: &mut Diags) -> bool {
+        self.trace(format!("Engine::iterate_bit_reverse: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 3);
+        let val = self.parms[ir.operands[0]].borrow();
+        let width = self.parms[ir.operands[1]].borrow();
+        let mut out_parm = self.parms[ir.operands[2]].borrow_mut();
+        let x = match val.data_type {
+            DataType::U64 => val.to_u64(),
+            _ => val.to_i64() as u64,
+        };
+        let w = match width.data_type {
+            DataType::U64 => i128::from(width.to_u64()),
+            _ => i128::from(width.to_i64()),
+        };
+        if !(1..=64).contains(&w) {
+            let msg = format!("Bit_reverse width must be from 1 to 64, but found {}", w);
+            diags.err1("EXEC_59", &msg, irdb.parms[ir.operands[1]].src_loc.clone());
+            return false;
+        }
+        *out_parm.to_u64_mut() = x.reverse_bits() >> (64 - w);
+        true
+    }
+
+    /// Join the string representation of the value operands with the
+    /// separator operand between each value.
+    fn iterate_join(&mut self, ir: &IR, irdb: &IRDb, current: &Location,
+                    diags: &mut Diags) -> bool {
+        self.trace(format!("Engine::iterate_join: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        let (&out_num, in_nums) = ir.operands.split_last().unwrap();
+        let mut strs = Vec::new();
+        for &op_num in in_nums {
+            match self.stringify_operand(op_num, irdb, diags) {
+                Some(s) => strs.push(s),
+                None => return false,
+            }
+        }
+        let joined = strs[1..].join(&strs[0]);
+        let mut out_parm = self.parms[out_num].borrow_mut();
+        *out_parm.val.downcast_mut::<String>().unwrap() = joined;
+        true
+    }
+
+    /// Bound the value operand to the inclusive range [lo, hi].
+    /// The output has the same type as the inputs.
+    fn iterate_clamp(&mut self, ir: &IR, irdb: &IRDb, current: &Location,
+                     diags: &mut Diags) -> bool {
+        self.trace(format!("Engine::iterate_clamp: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 4);
+        let val = self.parms[ir.operands[0]].borrow();
+        let lo = self.parms[ir.operands[1]].borrow();
+        let hi = self.parms[ir.operands[2]].borrow();
+        let mut out_parm = self.parms[ir.operands[3]].borrow_mut();
+
+        if out_parm.data_type == DataType::U64 {
+            let (v, l, h) = (val.to_u64_promoted(), lo.to_u64_promoted(), hi.to_u64_promoted());
+            if l > h {
+                let msg = format!("Clamp lower bound {:#X} is greater than upper bound {:#X}", l, h);
+                diags.err2("EXEC_45", &msg, irdb.parms[ir.operands[1]].src_loc.clone(),
+                           irdb.parms[ir.operands[2]].src_loc.clone());
+                return false;
+            }
+            *out_parm.to_u64_mut() = v.max(l).min(h);
+        } else {
+            let (v, l, h) = (val.to_i64(), lo.to_i64(), hi.to_i64());
+            if l > h {
+                let msg = format!("Clamp lower bound {} is greater than upper bound {}", l, h);
+                diags.err2("EXEC_76", &msg, irdb.parms[ir.operands[1]].src_loc.clone(),
+                           irdb.parms[ir.operands[2]].src_loc.clone());
+                return false;
+            }
+            *out_parm.to_i64_mut() = v.max(l).min(h);
+        }
+        true
+    }
+
+    /// Round the value operand up or down to a multiple of the alignment
+    /// operand.  The output has the same type as the inputs.
+    fn iterate_align_value(&mut self, ir: &IR, irdb: &IRDb, operation: IRKind,
+                           current: &Location, diags: &mut Diags) -> bool {
+        self.trace(format!("Engine::iterate_align_value: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 3);
+        let val = self.parms[ir.operands[0]].borrow();
+        let align = self.parms[ir.operands[1]].borrow();
+        let mut out_parm = self.parms[ir.operands[2]].borrow_mut();
+        let align_loc = irdb.parms[ir.operands[1]].src_loc.clone();
+
+        let aligned = if out_parm.data_type == DataType::U64 {
+            let (v, a) = (val.to_u64_promoted(), align.to_u64_promoted());
+            if a == 0 {
+                diags.err1("EXEC_50", "Alignment must be greater than zero", align_loc);
+                return false;
+            }
+            let out = match operation {
+                IRKind::AlignUp => v.checked_add(a - 1).map(|x| x / a * a),
+                IRKind::AlignDown => Some(v / a * a),
+                bad => panic!("Forgot to handle align {:?}", bad),
+            };
+            out.map(|x| *out_parm.to_u64_mut() = x)
+        } else {
+            let (v, a) = (val.to_i64(), align.to_i64());
+            if a <= 0 {
+                diags.err1("EXEC_77", "Alignment must be greater than zero", align_loc);
+                return false;
+            }
+            let out = match operation {
+                IRKind::AlignUp => v.checked_add(a - 1).and_then(|x| x.div_euclid(a).checked_mul(a)),
+                IRKind::AlignDown => v.div_euclid(a).checked_mul(a),
+                bad => panic!("Forgot to handle align {:?}", bad),
+            };
+            out.map(|x| *out_parm.to_i64_mut() = x)
+        };
+
+        if aligned.is_none() {
+            let msg = format!("{:?} result will overflow type {:?}", operation, out_parm.data_type);
+            diags.err1("EXEC_51", &msg, ir.src_loc.clone());
+            return false;
+        }
+        true
+    }
+
+    /// Raise the base to the power of the exponent.  The output has the
+    /// common type of the inputs.
+    fn iterate_pow(&mut self, ir: &IR, irdb: &IRDb, current: &Location,
+                   diags: &mut Diags) -> bool {
+        self.trace(format!("Engine::iterate_pow: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 3);
+        let base = self.parms[ir.operands[0]].borrow();
+        let exp = self.parms[ir.operands[1]].borrow();
+        let mut out_parm = self.parms[ir.operands[2]].borrow_mut();
+
+        let power = if out_parm.data_type == DataType::U64 {
+            u32::try_from(exp.to_u64_promoted()).ok()
+                    .and_then(|e| base.to_u64_promoted().checked_pow(e))
+                    .map(|x| *out_parm.to_u64_mut() = x)
+        } else {
+            let e = exp.to_i64();
+            if e < 0 {
+                let msg = format!("Exponent cannot be negative, but found '{}'", e);
+                diags.err1("EXEC_66", &msg, irdb.parms[ir.operands[1]].src_loc.clone());
+                return false;
+            }
+            u32::try_from(e).ok()
+                    .and_then(|e| base.to_i64().checked_pow(e))
+                    .map(|x| *out_parm.to_i64_mut() = x)
+        };
+
+        if power.is_none() {
+            let msg = format!("Pow result will overflow type {:?}", out_parm.data_type);
+            diags.err1("EXEC_65", &msg, ir.src_loc.clone());
+            return false;
+        }
+        true
+    }
+
+    /// Select the lesser or greater of the two inputs.  Like arithmetic,
+    /// the comparison is unsigned if the common output type is U64 and
+    /// signed otherwise.
+    fn iterate_min_max(&mut self, ir: &IR, operation: IRKind, current: &Location) -> bool {
+        self.trace(format!("Engine::iterate_min_max: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 3);
+        let lhs = self.parms[ir.operands[0]].borrow();
+        let rhs = self.parms[ir.operands[1]].borrow();
+        let mut out_parm = self.parms[ir.operands[2]].borrow_mut();
+
+        if out_parm.data_type == DataType::U64 {
+            let (l, r) = (lhs.to_u64_promoted(), rhs.to_u64_promoted());
+            *out_parm.to_u64_mut() = if operation == IRKind::Min { l.min(r) } else { l.max(r) };
+        } else {
+            let (l, r) = (lhs.to_i64(), rhs.to_i64());
+            *out_parm.to_i64_mut() = if operation == IRKind::Min { l.min(r) } else { l.max(r) };
+        }
+        true
+    }
+
+    /// Pack the value and width pairs into the U64 output operand.  The first
+    /// field occupies the least significant bits.  LinearDb already verified
+    /// that the widths total 32 bits.
+    fn iterate_pack32(&mut self, ir: &IR, irdb: &IRDb, current: &Location,
+                      diags: &mut Diags) -> bool {
+        self.trace(format!("Engine::iterate_pack32: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        let (out_num, fields) = ir.operands.split_last().unwrap();
+        let mut packed = 0u64;
+        let mut shift = 0;
+        for field in fields.chunks(2) {
+            let val = self.parms[field[0]].borrow();
+            let width = self.parms[field[1]].borrow().to_u64_promoted();
+            let mask = (1u64 << width) - 1;
+            // Negative values must fit as a signed field
+            let (fits, val_str) = if val.data_type == DataType::U64 {
+                (val.to_u64() <= mask, format!("{:#X}", val.to_u64()))
+            } else {
+                let v = val.to_i64();
+                (v >= -(1i64 << (width - 1)) && v <= mask as i64, format!("{}", v))
+            };
+            if !fits {
+                let msg = format!("Value {} does not fit in {} bits", val_str, width);
+                diags.err1("EXEC_69", &msg, irdb.parms[field[0]].src_loc.clone());
+                return false;
+            }
+            packed |= (val.to_u64_promoted() & mask) << shift;
+            shift += width;
+        }
+        *self.parms[*out_num].borrow_mut().to_u64_mut() = packed;
+        true
+    }
+
+    /// Compute the numeric absolute value of the input operand.
+    /// The output has the same type as the input.
+    fn iterate_abs_value(&mut self, ir: &IR, current: &Location, diags: &mut Diags) -> bool {
+        self.trace(format!("Engine::iterate_abs_value: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 2);
+        let in_parm0 = self.parms[ir.operands[0]].borrow();
+        let mut out_parm = self.parms[ir.operands[1]].borrow_mut();
+        if out_parm.data_type == DataType::U64 {
+            // Unsigned values are already positive
+            *out_parm.to_u64_mut() = in_parm0.to_u64();
+            return true;
+        }
+
+        let in0 = in_parm0.to_i64();
+        match in0.checked_abs() {
+            Some(val) => {
+                *out_parm.to_i64_mut() = val;
+                true
+            }
+            None => {
+                let msg = format!("Absolute value of '{}' will overflow type I64", in0);
+                diags.err1("EXEC_46", &msg, ir.src_loc.clone());
+                false
+            }
+        }
+    }
+
+    /// For bitwise NOT, complement the bits of the input operand.  The output
+    /// has the same type as the input.  Ambiguous integers are complemented
+    /// as I64.  For logical NOT, the output is a U64 boolean that is true
+    /// if the input is zero.
+    fn iterate_not(&mut self, ir: &IR, operation: IRKind, current: &Location) -> bool {
+        self.trace(format!("Engine::iterate_not: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 2);
+        let in_parm0 = self.parms[ir.operands[0]].borrow();
+        let mut out_parm = self.parms[ir.operands[1]].borrow_mut();
+        let in_is_u64 = in_parm0.data_type == DataType::U64;
+        match operation {
+            IRKind::LogicalNot => {
+                let is_zero = if in_is_u64 { in_parm0.to_u64() == 0 } else { in_parm0.to_i64() == 0 };
+                *out_parm.to_u64_mut() = is_zero as u64;
+            }
+            IRKind::BitNot if in_is_u64 => { *out_parm.to_u64_mut() = !in_parm0.to_u64(); }
+            IRKind::BitNot => { *out_parm.to_i64_mut() = !in_parm0.to_i64(); }
+            bad => panic!("Forgot to handle not {:?}", bad),
+        }
+        true
+    }
+
+    /// Negate the input operand.  The output is always I64, so negating
+    /// a U64 larger than the magnitude of the most negative I64 is an error.
+    fn iterate_negate(&mut self, ir: &IR, current: &Location, diags: &mut Diags) -> bool {
+        self.trace(format!("Engine::iterate_negate: img {}, sec {}",
+                               current.img, current.sec).as_str());
+        assert!(ir.operands.len() == 2);
+        let in_parm0 = self.parms[ir.operands[0]].borrow();
+        let mut out_parm = self.parms[ir.operands[1]].borrow_mut();
+        let (neg, in_str) = match in_parm0.data_type {
+            DataType::U64 => {
+                let in0 = in_parm0.to_u64();
+                (i64::try_from(-(in0 as i128)).ok(), format!("{:#X}", in0))
+            }
+            _ => {
+                let in0 = in_parm0.to_i64();
+                (in0.checked_neg(), format!("{}", in0))
+            }
+        };
+        match neg {
+            Some(out) => {
+                *out_parm.to_i64_mut() = out;
+                true
+            }
+            None => {
+                let msg = format!("Negation of '{}' will overflow type I64", in_str);
+                diags.err1("EXEC_52", &msg, ir.src_loc.clone());
+                false
+            }
+        }
+    }
+
     fn iterate_type_conversion(&mut self, ir: &IR, irdb: &IRDb, operation: IRKind,
                     current: &Location, diags: &mut Diags) -> bool {
         self.trace(format!("Engine::iterate_type_conversion: img {}, sec {}",
@@ -484,7 +1043,7 @@ impl Engine {
                     }
                     bad => {
                         let src_loc = irdb.parms[in_parm_num0].src_loc.clone();
-                        let msg = format!("Can't convert from {:?} to U64", bad);
+                        let msg = format!("Can't convert from {:?} to I64", bad);
                         diags.err1("EXEC_12", &msg, src_loc);
                         result = false;
                     }
@@ -515,6 +1074,15 @@ impl Engine {
         let lhs_dt = lhs.data_type;
         let rhs_dt = rhs.data_type;
 
+        // Plus between two strings is concatenation.  IRDb already verified
+        // that strings appear only as both operands of Add.
+        if lhs_dt == DataType::QuotedString {
+            let cat = format!("{}{}", lhs.to_str(), rhs.to_str());
+            let mut out_parm = self.parms[out_num].borrow_mut();
+            *out_parm.val.downcast_mut::<String>().unwrap() = cat;
+            return true;
+        }
+
         if lhs_dt != rhs_dt {
             let mut dt_ok = false;
             // Right and left side data types are not equal.
@@ -523,10 +1091,13 @@ impl Engine {
                 if [DataType::I64, DataType::U64, DataType::Integer].contains(&lhs_dt) {
                     dt_ok = true; // Integers work with s/u types
                 }
-            } else if lhs_dt == DataType::Integer {
-                if [DataType::I64, DataType::U64].contains(&rhs_dt) {
-                    dt_ok = true; // Integers work with s/u types
-                }
+            } else if lhs_dt == DataType::Integer
+                      && [DataType::I64, DataType::U64].contains(&rhs_dt) {
+                dt_ok = true; // Integers work with s/u types
+            } else if irdb.relax_types {
+                // IRDb already warned about promoting mixed I64 and U64 to U64
+                dt_ok = [DataType::I64, DataType::U64].contains(&lhs_dt) &&
+                        [DataType::I64, DataType::U64].contains(&rhs_dt);
             }
 
             if !dt_ok {
@@ -544,8 +1115,8 @@ impl Engine {
         // check both parms since one might be an ambiguous integer
         // If either side is unsigned, the whole thing is unsigned
         if (lhs_dt == DataType::U64) || (rhs_dt == DataType::U64) {
-            let in0 = lhs.to_u64();
-            let in1 = rhs.to_u64();
+            let in0 = lhs.to_u64_promoted();
+            let in1 = rhs.to_u64_promoted();
             let mut out_parm = self.parms[out_num].borrow_mut();
             let out = out_parm.val.downcast_mut::<u64>().unwrap();
 
@@ -554,6 +1125,8 @@ impl Engine {
                 IRKind::NEq        => *out = (in0 != in1) as u64,
                 IRKind::GEq        => *out = (in0 >= in1) as u64,
                 IRKind::LEq        => *out = (in0 <= in1) as u64,
+                IRKind::Greater    => *out = (in0 > in1) as u64,
+                IRKind::Less       => *out = (in0 < in1) as u64,
                 IRKind::BitAnd     => *out = in0 & in1,
                 IRKind::LogicalAnd => *out = ((in0 != 0) && (in1 != 0)) as u64,
                 IRKind::BitOr      => *out = in0 | in1,
@@ -581,6 +1154,8 @@ impl Engine {
                 IRKind::LogicalOr  => { let out = out_parm.to_u64_mut(); *out = ((in0 != 0) || (in1 != 0)) as u64 }
                 IRKind::LEq        => { let out = out_parm.to_u64_mut(); *out = (in0 <= in1) as u64 }
                 IRKind::GEq        => { let out = out_parm.to_u64_mut(); *out = (in0 >= in1) as u64 }
+                IRKind::Less       => { let out = out_parm.to_u64_mut(); *out = (in0 < in1) as u64 }
+                IRKind::Greater    => { let out = out_parm.to_u64_mut(); *out = (in0 > in1) as u64 }
                 IRKind::NEq        => { let out = out_parm.to_u64_mut(); *out = (in0 != in1) as u64 }
                 IRKind::DoubleEq   => { let out = out_parm.to_u64_mut(); *out = (in0 == in1) as u64 }
                 
@@ -611,6 +1186,21 @@ impl Engine {
                     current: &Location) -> bool {
         self.trace(format!("Engine::iterate_sizeof: img {}, sec {}",
                             current.img, current.sec).as_str());
+
+        // sizeof(start, end) is the distance between two identifiers.
+        // Until the locations converge, the end may transiently precede
+        // the start, so report zero and check the final order in execute.
+        if ir.operands.len() == 3 {
+            let imgs = self.get_span_imgs(ir, irdb, diags);
+            if imgs.is_none() {
+                return false;
+            }
+            let (start_img, end_img) = imgs.unwrap();
+            let mut out_parm = self.parms[ir.operands[2]].borrow_mut();
+            *out_parm.to_u64_mut() = end_img.saturating_sub(start_img);
+            return true;
+        }
+
         // sizeof takes one input and produces one output
         // we've already discarded surrounding () on the operand
         assert!(ir.operands.len() == 2);
@@ -641,24 +1231,106 @@ impl Engine {
             // When the start has a larger image offset than the end, it means
             // something before this section grew significant during the current
             // iteration.  The starting offset has already been updated during
-            // this iteration, but not yet th end.  In this case, report a zero
-            // size and wait for the next iteration where the ending offset will
-            // be more accurate.
-            self.trace(format!("Starting img offset {} > ending img offset {} in {}",
-                       start_loc.img, end_loc.img, sec_name).as_str());
-            *out = 0;
+            // this iteration, but not yet th end.  In this case, report the last
+            // stable size and wait for the next iteration where the ending offset
+            // will be more accurate.  The last stable size is usually closer to
+            // the final size than zero, so dependent writes change less.
+            let sz = self.last_sizes.get(sec_name).copied().unwrap_or(0);
+            self.trace(format!("Starting img offset {} > ending img offset {} in {}, \
+                       using last stable size {}", start_loc.img, end_loc.img,
+                       sec_name, sz).as_str());
+            *out = sz;
 
         } else {
             let sz = end_loc.img - start_loc.img;
             self.trace(format!("Sizeof {} is currently {}", sec_name, sz).as_str());
-            // We'll at least panic at runtime if conversion from
-            // usize to u64 fails instead of bad output binary.
-            *out = sz.try_into().unwrap();
+            self.last_sizes.insert(sec_name.to_string(), sz);
+            *out = sz;
         }
         
         true
     }
 
+    /// When the condition is false, skip to the else block or the end of
+    /// the if.  Report a condition that keeps changing between iterations,
+    /// since the layout can't converge.
+    fn iterate_if(&mut self, ir: &IR, lid: usize, irdb: &IRDb, diags: &mut Diags,
+                  skip_to: &mut Option<usize>) -> bool {
+        let taken = self.parms[ir.operands[0]].borrow().to_bool();
+        self.trace(format!("Engine::iterate_if: lid {} taken {}", lid, taken).as_str());
+        if !taken {
+            *skip_to = Some(irdb.branch_targets[&lid]);
+        }
+
+        let flips = self.branch_flips.entry(lid).or_insert((taken, 0));
+        if flips.0 != taken {
+            *flips = (taken, flips.1 + 1);
+        }
+        if flips.1 >= Engine::MAX_BRANCH_FLIPS {
+            let msg = format!("The if condition changed {} times between passes.  The \
+                               condition depends on the sizes or addresses of the block it \
+                               selects, so the output layout never converges", flips.1);
+            diags.err1("EXEC_62", &msg, ir.src_loc.clone());
+            return false;
+        }
+        true
+    }
+
+    /// Returns the offset and length operands of a wr_slice.  Reports an
+    /// error if either is negative.
+    fn slice_range(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags) -> Option<(u64, u64)> {
+        let mut vals = [0u64; 2];
+        for (idx, val) in vals.iter_mut().enumerate() {
+            let op = self.parms[ir.operands[idx]].borrow();
+            *val = if op.data_type == DataType::U64 {
+                op.to_u64()
+            } else {
+                let temp = op.to_i64();
+                if temp < 0 {
+                    let msg = format!("The wr_slice offset and length cannot be negative, \
+                                       but found '{}'", temp);
+                    diags.err1("EXEC_63", &msg, irdb.parms[ir.operands[idx]].src_loc.clone());
+                    return None;
+                }
+                temp as u64
+            };
+        }
+        Some((vals[0], vals[1]))
+    }
+
+    /// Only the slice of the section occupies space in the output, so move
+    /// the location counter back to the start of the section plus the length.
+    fn iterate_slice_end(&mut self, ir: &IR, irdb: &IRDb, diags: &mut Diags,
+                         current: &mut Location) -> bool {
+        let start = self.slice_starts.pop().unwrap();
+        let length = match self.slice_range(ir, irdb, diags) {
+            Some((_, length)) => length,
+            None => { return false; }
+        };
+        *current = start;
+        advance_location(length, ir, diags, current)
+    }
+
+    /// The CRC depends on the output bytes, which don't exist during
+    /// iteration.  Leave the output operand alone, which is zero until
+    /// resolve_crcs feeds back the real value.
+    fn iterate_crc32(&mut self, ir: &IR, irdb: &IRDb, diags: &mut Diags) -> bool {
+        let sec_name = irdb.get_opnd_as_identifier(ir, 0);
+        if !irdb.sized_locs.contains_key(sec_name) {
+            let msg = format!("Can't take crc32() of section '{}' not used in output.",
+                    sec_name);
+            diags.err1("EXEC_56", &msg, ir.src_loc.clone());
+            return false;
+        }
+        if irdb.sliced_sections.contains(sec_name) {
+            let msg = format!("Can't take crc32() of section '{}' written only by wr_slice",
+                              sec_name);
+            diags.err1("EXEC_73", &msg, ir.src_loc.clone());
+            return false;
+        }
+        true
+    }
+
     /// Compute the transient current address.  This case is called when
     /// Abs/Img/Sec is called without an identifier.
     fn iterate_current_address(&mut self, ir: &IR, current: &Location) -> bool {
@@ -674,11 +1346,10 @@ impl Engine {
         match ir.kind {
             IRKind::Abs => { 
                 // Will panic if usize does not fit in a u64
-                let img: u64 = current.img.try_into().unwrap();
-                *out = img + self.start_addr;
+                *out = current.abs;
             }
-            IRKind::Img => { *out = current.img.try_into().unwrap(); }
-            IRKind::Sec => { *out = current.sec.try_into().unwrap(); }
+            IRKind::Img => { *out = current.img; }
+            IRKind::Sec => { *out = current.sec; }
             bad => {
                 panic!("Called iterate_current_address with bogus IR {:?}", bad);
             }
@@ -687,10 +1358,10 @@ impl Engine {
         true
     }
 
-    /// Compute the required number of bytes to align the current absolute location.
-    /// We don't actually align anything yet, since that happens in a subsequent
-    /// wr8 instruction.
-    fn iterate_align(&mut self, ir: &IR, _irdb: &IRDb, _diags: &mut Diags,
+    /// Compute the required number of bytes to align the current absolute location
+    /// to the next power of two boundary.  We don't actually align anything yet,
+    /// since that happens in a subsequent wr8 instruction.
+    fn iterate_align(&mut self, ir: &IR, irdb: &IRDb, diags: &mut Diags,
                         current: &Location) -> bool {
         self.trace(format!("Engine::iterate_align: img {}, sec {}",
                             current.img, current.sec).as_str());
@@ -715,21 +1386,51 @@ impl Engine {
         let align_parm_num = ir.operands[0];
         let align_parm = self.parms[align_parm_num].borrow();
         let align_val = align_parm.to_u64();
+        if !align_val.is_power_of_two() {
+            let msg = format!("Alignment must be a non-zero power of 2, but found {}", align_val);
+            diags.err1("EXEC_67", &msg, irdb.parms[align_parm_num].src_loc.clone());
+            return false;
+        }
 
-        // We'll at least panic at runtime if conversion from
-        // usize to u64 fails instead of bad output binary.
-        let img : u64 = current.img;
-        let abs_val = img + self.start_addr;
+        // Zero when we're already aligned, so no pad bytes needed
+        *out = (align_val - current.abs % align_val) % align_val;
 
-        let remainder = abs_val.checked_rem(align_val).unwrap();
+        debug!("Engine::iterate_align: alignment amount is {}", *out);
+        true
+    }
 
-        *out = if remainder == 0 {
-            0 // we're already aligned, no pad bytes needed
-        } else {
-            align_val - remainder
-        };
+    /// Compute the required number of bytes to pad the current section to the
+    /// next power of two size.  An empty section or a section that is already a
+    /// power of two size needs no padding.  We don't actually pad anything yet,
+    /// since that happens in a subsequent wr8 instruction.
+    fn iterate_roundup_pow2(&mut self, ir: &IR, _irdb: &IRDb, diags: &mut Diags,
+                            current: &Location) -> bool {
+        self.trace(format!("Engine::iterate_roundup_pow2: img {}, sec {}",
+                            current.img, current.sec).as_str());
 
-        debug!("Engine::iterate_align: alignment amount is {}", *out);
+        // The only parameter is the result operand for the number
+        // of bytes required to pad.
+        assert!(ir.operands.len() == 1);
+        let mut out_parm = self.parms[ir.operands[0]].borrow_mut();
+        let out = out_parm.to_u64_mut();
+
+        let sz = current.sec;
+        if sz == 0 {
+            *out = 0;
+            return true;
+        }
+
+        match sz.checked_next_power_of_two() {
+            Some(pow2) => { *out = pow2 - sz; }
+            None => {
+                let msg = format!("Rounding section size {} up to a power of two \
+                                   overflows a U64", sz);
+                diags.err1("EXEC_41", &msg, ir.src_loc.clone());
+                return false;
+            }
+        }
+
+        debug!("Engine::iterate_roundup_pow2: pad amount is {}", *out);
         true
     }
 
@@ -764,7 +1465,7 @@ impl Engine {
         let set_val = set_parm.to_u64();
 
         let loc = match ir.kind {
-            IRKind::SetAbs => current.img + self.start_addr,
+            IRKind::SetAbs => current.abs,
             IRKind::SetImg => current.img,
             IRKind::SetSec => current.sec,
             bad => panic!("called iterate_set for IR {:?}", bad),
@@ -784,6 +1485,25 @@ impl Engine {
         true
     }
 
+    /// Returns the image offsets of the two identifiers in sizeof(start, end)
+    fn get_span_imgs(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags) -> Option<(u64, u64)> {
+        let mut imgs = [0u64; 2];
+        for (idx, &op_num) in ir.operands[0..2].iter().enumerate() {
+            let op = self.parms[op_num].borrow();
+            let name = op.to_identifier();
+            match irdb.addressed_locs.get(name) {
+                Some(&lid) => { imgs[idx] = self.ir_locs[lid].img; }
+                None => {
+                    let msg = format!("Address of section or label '{}' not reachable in output.",
+                                      name);
+                    diags.err1("EXEC_75", &msg, irdb.parms[op_num].src_loc.clone());
+                    return None;
+                }
+            }
+        }
+        Some((imgs[0], imgs[1]))
+    }
+
     /// Compute the transient address of the identifier.  This case is called when
     /// Abs/Img/Sec is called with an identifier.
     fn iterate_identifier_address(&mut self, ir: &IR, irdb: &IRDb, diags: &mut Diags,
@@ -811,16 +1531,21 @@ impl Engine {
             diags.err1("EXEC_11", &msg, ir.src_loc.clone());
             return false;
         }
+        if ir.kind != IRKind::Sec && irdb.sliced_sections.contains(name) {
+            let msg = format!("Section '{}' is written only by wr_slice, so it has no \
+                               address in the output", name);
+            diags.err1("EXEC_72", &msg, ir.src_loc.clone());
+            return false;
+        }
         let ir_num = ir_num.unwrap();
         let start_loc = &self.ir_locs[*ir_num];
         match ir.kind {
             // Will panic if usize does not fit in a u64
             IRKind::Abs => {
-                let img: u64 = start_loc.img.try_into().unwrap();
-                *out = img + self.start_addr;
+                *out = start_loc.abs;
             }
-            IRKind::Img => { *out = start_loc.img.try_into().unwrap(); }
-            IRKind::Sec => { *out = start_loc.sec.try_into().unwrap(); }
+            IRKind::Img => { *out = start_loc.img; }
+            IRKind::Sec => { *out = start_loc.sec; }
             bad => {
                 panic!("Called iterate_current_address with bogus IR {:?}", bad);
             }
@@ -847,40 +1572,76 @@ impl Engine {
 
     /// At the start of a section, push the old section offset
     /// and reset the current section offset to zero.
+    /// If the section has a base address, the absolute address
+    /// restarts from the base address.
     fn iterate_section_start(&mut self, ir: &IR, irdb: &IRDb, _diags: &mut Diags,
                              current: &mut Location) -> bool {
-        let sec_name = irdb.get_opnd_as_identifier(&ir, 0).to_string();
+        let sec_name = irdb.get_opnd_as_identifier(ir, 0).to_string();
         // Track that's we've entered this new section
         self.sec_names.push(sec_name);
         self.trace(format!("Engine::iterate_section_start: img {}, sec {}",
                             current.img, current.sec).as_str());
         self.sec_offsets.push(current.sec);
         current.sec = 0;
+
+        // Optional second operand is the section base address
+        if ir.operands.len() == 2 {
+            let base = self.parms[ir.operands[1]].borrow().to_u64();
+            self.sec_bases.push(Some((current.abs, current.img)));
+            current.abs = base;
+        } else {
+            self.sec_bases.push(None);
+        }
         
         true
     }
 
     /// At the end of a section, pop the last section offset and add
     /// its value to the current section offset
-    fn iterate_section_end(&mut self, ir: &IR, irdb: &IRDb, _diags: &mut Diags,
+    fn iterate_section_end(&mut self, ir: &IR, irdb: &IRDb, diags: &mut Diags,
                             current: &mut Location) -> bool {
-        let sec_name = irdb.get_opnd_as_identifier(&ir, 0).to_string();
+        let sec_name = irdb.get_opnd_as_identifier(ir, 0).to_string();
         self.trace(format!("Engine::iterate_section_end: '{}', img {}, sec {}",
                 sec_name, current.img, current.sec).as_str());
         current.sec += self.sec_offsets.pop().unwrap();
+
+        // After a section with a base address, the parent's absolute
+        // address continues as if the section had no base address.
+        let mut result = true;
+        if let Some((abs_start, img_start)) = self.sec_bases.pop().unwrap() {
+            match abs_start.checked_add(current.img - img_start) {
+                Some(abs) => { current.abs = abs; }
+                None => {
+                    let msg = format!("Absolute address after section '{}' overflows a U64",
+                                      sec_name);
+                    diags.err1("EXEC_74", &msg, ir.src_loc.clone());
+                    result = false;
+                }
+            }
+        }
         // Track that's we've exited this section
         self.sec_names.pop();
         
-        true
+        result
     }
 
-    pub fn new(irdb: &IRDb, diags: &mut Diags, abs_start: usize) -> Option<Engine> {
+    pub fn new(irdb: &IRDb, diags: &mut Diags, abs_start: usize,
+               options: EngineOptions) -> Option<Engine> {
         // The first iterate loop may access any IR location, so initialize all
         // ir_locs locations to zero.  
-        let ir_locs = vec![Location {img: 0, sec: 0}; irdb.ir_vec.len()];
+        let ir_locs = vec![Location {img: 0, sec: 0, abs: 0}; irdb.ir_vec.len()];
 
+        let build_date = if options.deterministic { None } else { Some(utc_date_now()) };
         let mut engine = Engine { parms: Vec::new(), ir_locs, sec_offsets: Vec::new(),
-                                         sec_names: Vec::new(), start_addr: irdb.start_addr };
+                                         sec_names: Vec::new(), sec_bases: Vec::new(),
+                                         slice_starts: Vec::new(),
+                                         start_addr: irdb.start_addr,
+                                         image_size: 0, last_sizes: HashMap::new(),
+                                         build_date,
+                                         skipped: vec![false; irdb.ir_vec.len()],
+                                         branch_flips: HashMap::new(),
+                                         iter_count: 0,
+                                         options };
         engine.trace("Engine::new:");
 
         // Initialize parameters from the IR operands.
@@ -891,39 +1652,324 @@ impl Engine {
         }
 
 
-        let result = engine.iterate(&irdb, diags, abs_start);
+        let result = engine.iterate(irdb, diags, abs_start);
         if !result {
             return None;
         }
 
+        if !engine.resolve_crcs(irdb, diags, abs_start) {
+            return None;
+        }
+
+        if !engine.check_nonempty_sections(irdb, diags) {
+            return None;
+        }
+
+        if !engine.check_once_sections(irdb, diags) {
+            return None;
+        }
+
+        if let Some(threshold) = engine.options.warn_section_size {
+            engine.warn_section_sizes(irdb, diags, threshold);
+        }
+
         engine.trace("Engine::new: EXIT");
         Some(engine)
     }
 
+    /// Computes the crc32() of the specified section over the rendered image.
+    /// Returns None if the section bytes are not in the image.
+    fn section_crc(&self, ir: &IR, irdb: &IRDb, image: &[u8]) -> Option<u64> {
+        let sec_name = irdb.get_opnd_as_identifier(ir, 0);
+        let ir_rng = irdb.sized_locs.get(sec_name)?;
+        let start = usize::try_from(self.ir_locs[ir_rng.start].img).ok()?;
+        let end = usize::try_from(self.ir_locs[ir_rng.end].img).ok()?;
+        Some(crc32_ieee(image.get(start..end)?) as u64)
+    }
+
+    /// Renders the output image without reporting anything and returns
+    /// the CRC for each crc32() IR in crc_lids.  Returns Ok(None) if
+    /// rendering fails, in which case execute reports the errors later.
+    /// Reports an error if the bytes of a section are not in the image.
+    fn render_crcs(&self, irdb: &IRDb, crc_lids: &[usize], diags: &mut Diags)
+                   -> Result<Option<Vec<u64>>> {
+        let mut quiet_diags = Diags::new("", "", 0, true);
+        let mut image = Vec::new();
+        if self.execute_pass(irdb, &mut quiet_diags, &mut image, true).is_err() {
+            return Ok(None);
+        }
+        let mut crcs = Vec::new();
+        for &lid in crc_lids {
+            let ir = &irdb.ir_vec[lid];
+            match self.section_crc(ir, irdb, &image) {
+                Some(crc) => crcs.push(crc),
+                None => {
+                    let msg = format!("The bytes of section '{}' for crc32() are not in \
+                                       the output image", irdb.get_opnd_as_identifier(ir, 0));
+                    diags.err1("EXEC_71", &msg, ir.src_loc.clone());
+                    return Err(anyhow!("Crc32 failed"));
+                }
+            }
+        }
+        Ok(Some(crcs))
+    }
+
+    /// Resolves the value of every crc32() after the locations converge.
+    /// The CRC covers the output bytes, which depend on everything in the
+    /// section, so it can't take part in the fixed point iteration.
+    /// Instead, render the image with every CRC at zero, compute the CRCs,
+    /// then iterate again to feed the CRCs into dependent expressions.
+    /// A section may hold the CRC of another section, so repeat until the
+    /// CRCs stop changing.  Each repeat settles at least one more level of
+    /// nesting, so CRCs still changing after one render per crc32() plus
+    /// one must depend on their own value.  This works only if the CRC
+    /// values do not change the layout.
+    fn resolve_crcs(&mut self, irdb: &IRDb, diags: &mut Diags, abs_start: usize) -> bool {
+        let crc_lids: Vec<usize> = irdb.ir_vec.iter().enumerate()
+                .filter(|(_, ir)| ir.kind == IRKind::Crc32)
+                .map(|(lid, _)| lid)
+                .collect();
+        if crc_lids.is_empty() {
+            return true;
+        }
+        self.trace("Engine::resolve_crcs:");
+
+        let mut crcs = vec![0; crc_lids.len()];
+        let mut new_crcs = crcs.clone();
+        for _ in 0..=crc_lids.len() {
+            new_crcs = match self.render_crcs(irdb, &crc_lids, diags) {
+                Ok(Some(crcs)) => crcs,
+                Ok(None) => { return true; }
+                Err(_) => { return false; }
+            };
+            if new_crcs == crcs {
+                return true;
+            }
+            for (&lid, &crc) in crc_lids.iter().zip(new_crcs.iter()) {
+                let out_num = irdb.ir_vec[lid].operands[1];
+                *self.parms[out_num].borrow_mut().to_u64_mut() = crc;
+            }
+
+            let old_locations = self.ir_locs.clone();
+            if !self.iterate(irdb, diags, abs_start) {
+                return false;
+            }
+            if self.ir_locs != old_locations {
+                diags.err0("EXEC_57", "The value of a crc32() changed the size or location of \
+                                       output bytes.  A CRC must not affect the output layout.");
+                return false;
+            }
+            std::mem::swap(&mut crcs, &mut new_crcs);
+        }
+
+        // The CRCs that changed on the last render never settled
+        for (idx, &lid) in crc_lids.iter().enumerate() {
+            if crcs[idx] != new_crcs[idx] {
+                let ir = &irdb.ir_vec[lid];
+                let msg = format!("The crc32() of section '{}' depends on its own value",
+                                  irdb.get_opnd_as_identifier(ir, 0));
+                diags.err1("EXEC_58", &msg, ir.src_loc.clone());
+            }
+        }
+        false
+    }
+
+    /// Report an error for each section with the @ensure_nonempty attribute
+    /// that produced no bytes.  The check IR immediately precedes the end
+    /// of the section, so the final section offset at the IR is the size.
+    fn check_nonempty_sections(&self, irdb: &IRDb, diags: &mut Diags) -> bool {
+        let mut result = true;
+        for (lid, ir) in irdb.ir_vec.iter().enumerate() {
+            if ir.kind == IRKind::EnsureNonempty && !self.skipped[lid]
+                    && self.ir_locs[lid].sec == 0 {
+                let sec_name = irdb.get_opnd_as_identifier(ir, 0);
+                let msg = format!("Section '{}' is empty, but has the @ensure_nonempty \
+                                   attribute", sec_name);
+                diags.err1("EXEC_43", &msg, ir.src_loc.clone());
+                result = false;
+            }
+        }
+        result
+    }
+
+    /// Report an error for each section with the @once attribute written
+    /// more than once.  Writes in a block not taken don't count.  The error
+    /// points at the second write.
+    fn check_once_sections(&self, irdb: &IRDb, diags: &mut Diags) -> bool {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut second_writes = Vec::new();
+        for (lid, ir) in irdb.ir_vec.iter().enumerate() {
+            if ir.kind != IRKind::SectionStart || self.skipped[lid] {
+                continue;
+            }
+            let sec_name = irdb.get_opnd_as_identifier(ir, 0);
+            if !irdb.once_sections.contains(sec_name) {
+                continue;
+            }
+            let count = counts.entry(sec_name.to_string()).or_insert(0);
+            *count += 1;
+            if *count == 2 {
+                second_writes.push((sec_name, ir));
+            }
+        }
+        for (sec_name, ir) in &second_writes {
+            let msg = format!("Section '{}' has the @once attribute, but is written {} times \
+                               in the output", sec_name, counts[*sec_name]);
+            diags.err1("EXEC_70", &msg, ir.src_loc.clone());
+        }
+        second_writes.is_empty()
+    }
+
+    /// Warn about each section with a final size larger than the threshold.
+    /// Large sections are often the result of a runaway repeat count.
+    fn warn_section_sizes(&self, irdb: &IRDb, diags: &mut Diags, threshold: u64) {
+        // Sort by name for a stable warning order
+        let mut sec_names: Vec<&String> = irdb.sized_locs.keys().collect();
+        sec_names.sort();
+        for sec_name in sec_names {
+            let ir_rng = &irdb.sized_locs[sec_name];
+            let sz = self.ir_locs[ir_rng.end].img - self.ir_locs[ir_rng.start].img;
+            if sz > threshold {
+                let msg = format!("Section '{}' size {} ({:#X}) exceeds the warning \
+                                   threshold {} ({:#X})", sec_name, sz, sz, threshold, threshold);
+                diags.warn("EXEC_40", &msg);
+            }
+        }
+    }
+
+    /// Returns the name and output image byte range of each section written
+    /// directly by the output section, in output order.  A section written
+    /// by wr_slice is not a section of the output.
+    pub fn top_level_sections(&self, irdb: &IRDb) -> Vec<(String, Range<usize>)> {
+        let mut sections = Vec::new();
+        let mut depth = 0;
+        let mut slice_depth = 0;
+        let mut start = 0;
+        for (lid, ir) in irdb.ir_vec.iter().enumerate() {
+            if self.skipped[lid] {
+                continue;
+            }
+            match ir.kind {
+                IRKind::SliceStart => { slice_depth += 1; }
+                IRKind::SliceEnd => { slice_depth -= 1; }
+                _ if slice_depth > 0 => {}
+                IRKind::SectionStart => {
+                    depth += 1;
+                    // Depth 1 is the output section itself
+                    if depth == 2 {
+                        start = self.ir_locs[lid].img as usize;
+                    }
+                }
+                IRKind::SectionEnd => {
+                    if depth == 2 {
+                        let end = self.ir_locs[lid].img as usize;
+                        sections.push((irdb.get_opnd_as_identifier(ir, 0).to_string(),
+                                       start..end));
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        sections
+    }
+
+    /// Print each label with its final addresses and source location.
+    pub fn list_labels(&self, irdb: &IRDb, diags: &Diags) {
+        for (lid, ir) in irdb.ir_vec.iter().enumerate() {
+            if ir.kind != IRKind::Label || self.skipped[lid] {
+                continue;
+            }
+            let loc = &self.ir_locs[lid];
+            println!("{}: abs {:#X}, img {:#X}, defined at {}",
+                     irdb.get_opnd_as_identifier(ir, 0), loc.abs, loc.img,
+                     diags.location_str(ir.src_loc.start));
+        }
+    }
+
+    /// Returns the final location of each IR as line oriented text, e.g.
+    /// "loc 3 Wr8 img 0x3 sec 0x0 abs 0x1003".  IRs in an if or else
+    /// block not taken end with "skipped".
+    pub fn emit_state(&self, irdb: &IRDb) -> String {
+        let mut out = String::from("brink-state 1\n");
+        for (lid, ir) in irdb.ir_vec.iter().enumerate() {
+            let loc = &self.ir_locs[lid];
+            out.push_str(&format!("loc {} {:?} img {:#X} sec {:#X} abs {:#X}", lid, ir.kind,
+                                  loc.img, loc.sec, loc.abs));
+            if self.skipped[lid] {
+                out.push_str(" skipped");
+            }
+            out.push('\n');
+        }
+        out
+    }
+
     pub fn dump_locations(&self) {
         for (idx,loc) in self.ir_locs.iter().enumerate() {
             debug!("{}: {:?}", idx, loc);
         }
     }
 
+    /// Print the IR locations that changed during the specified iteration.
+    /// On the first iteration, changes are relative to the all-zero
+    /// initial locations.
+    fn dump_fixedpoint_pass(&self, irdb: &IRDb, iter_count: usize, old_locations: &[Location],
+                            total_size: u64) {
+        let zero = Location { img: 0, sec: 0, abs: 0 };
+        let mut changed = Vec::new();
+        for (lid, loc) in self.ir_locs.iter().enumerate() {
+            let old = old_locations.get(lid).unwrap_or(&zero);
+            if loc != old {
+                changed.push(format!("    lid {} {:?}: img {} -> {}, sec {} -> {}, abs {:#X} -> {:#X}",
+                                lid, irdb.ir_vec[lid].kind, old.img, loc.img, old.sec, loc.sec,
+                                old.abs, loc.abs));
+            }
+        }
+        println!("fixedpoint pass {}: size {}, {} of {} locations changed",
+                 iter_count, total_size, changed.len(), self.ir_locs.len());
+        for line in changed {
+            println!("{}", line);
+        }
+    }
+
+    /// Returns the number of passes the location counters took to converge
+    pub fn iteration_count(&self) -> usize {
+        self.iter_count
+    }
+
     pub fn iterate(&mut self, irdb: &IRDb, diags: &mut Diags, abs_start: usize) -> bool {
         self.trace(format!("Engine::iterate: abs_start = {}", abs_start).as_str());
         let mut result = true;
         let mut old_locations = Vec::new();
         let mut stable = false;
-        let mut iter_count = 0;
+        self.iter_count = 0;
+        let max_iterations = self.options.max_iterations.unwrap_or(Engine::DEFAULT_MAX_ITERATIONS);
+
+        // Without any size or address references, nothing can depend on a
+        // location computed later in the pass, so one pass is final.
+        let single_pass = !irdb.ir_vec.iter().any(|ir| matches!(ir.kind,
+                IRKind::Sizeof | IRKind::Abs | IRKind::Img | IRKind::Sec));
+
         while result && !stable {
-            self.trace(format!("Engine::iterate: Iteration count {}", iter_count).as_str());
-            iter_count += 1;
-            let mut current = Location{ img: 0, sec: 0 };
+            self.trace(format!("Engine::iterate: Iteration count {}", self.iter_count).as_str());
+            self.iter_count += 1;
+            let mut current = Location{ img: 0, sec: 0, abs: self.start_addr };
 
             // make sure we exited as many sections as we entered on each iteration
-            assert!(self.sec_offsets.len() == 0);
+            assert!(self.sec_offsets.is_empty());
+
+            // The end of the if or else block currently being skipped
+            let mut skip_to = None;
 
             for (lid,ir) in irdb.ir_vec.iter().enumerate() {
                 debug!("Engine::iterate on lid {} at img offset {}", lid, current.img);
                 // record our location after each IR
                 self.ir_locs[lid] = current.clone();
+                // IR in a block not taken occupies no space
+                self.skipped[lid] = skip_to.is_some_and(|end| lid < end);
+                if self.skipped[lid] {
+                    continue;
+                }
                 let operation = ir.kind;
                 result &= match operation {
 
@@ -942,17 +1988,37 @@ impl Engine {
                     IRKind::DoubleEq |
                     IRKind::GEq |
                     IRKind::LEq |
-                    IRKind::NEq =>    self.iterate_arithmetic(&ir, irdb, operation, &current, diags),
+                    IRKind::Less |
+                    IRKind::Greater |
+                    IRKind::NEq =>    self.iterate_arithmetic(ir, irdb, operation, &current, diags),
                     IRKind::ToI64 |
-                    IRKind::ToU64 =>  self.iterate_type_conversion(&ir, irdb, operation, &current, diags),
-                    IRKind::Sizeof => self.iterate_sizeof(&ir, irdb, diags, &mut current),
+                    IRKind::ToU64 =>  self.iterate_type_conversion(ir, irdb, operation, &current, diags),
+                    IRKind::IsPow2 => self.iterate_is_pow2(ir, &current),
+                    IRKind::CountOnes |
+                    IRKind::CountZeros => self.iterate_count_bits(ir, operation, &current),
+                    IRKind::Clamp => self.iterate_clamp(ir, irdb, &current, diags),
+                    IRKind::Join => self.iterate_join(ir, irdb, &current, diags),
+                    IRKind::BitReverse => self.iterate_bit_reverse(ir, irdb, &current, diags),
+                    IRKind::AlignUp |
+                    IRKind::AlignDown => self.iterate_align_value(ir, irdb, operation, &current, diags),
+                    IRKind::Pow => self.iterate_pow(ir, irdb, &current, diags),
+                    IRKind::Min |
+                    IRKind::Max => self.iterate_min_max(ir, operation, &current),
+                    IRKind::Pack32 => self.iterate_pack32(ir, irdb, &current, diags),
+                    IRKind::AbsValue => self.iterate_abs_value(ir, &current, diags),
+                    IRKind::Negate => self.iterate_negate(ir, &current, diags),
+                    IRKind::BitNot |
+                    IRKind::LogicalNot => self.iterate_not(ir, operation, &current),
+                    IRKind::Sizeof => self.iterate_sizeof(ir, irdb, diags, &current),
+                    IRKind::Crc32 => self.iterate_crc32(ir, irdb, diags),
 
                     // Unlike print, we have to iterate on the string write operation since
                     // the size of the string affects the size of the output image.
                     IRKind::Abs |
                     IRKind::Img |
                     IRKind::Sec => self.iterate_address(ir, irdb, diags, &current),
-                    IRKind::Wrs => self.iterate_wrs(&ir, irdb, diags, &mut current),
+                    IRKind::Header |
+                    IRKind::Wrs => self.iterate_wrs(ir, irdb, diags, &mut current),
                     IRKind::SectionStart => self.iterate_section_start(ir, irdb, diags, &mut current),
                     IRKind::SectionEnd =>   self.iterate_section_end(ir, irdb, diags, &mut current),
 
@@ -963,25 +2029,75 @@ impl Engine {
                     IRKind::Wr40 |
                     IRKind::Wr48 |
                     IRKind::Wr56 |
-                    IRKind::Wr64 => self.iterate_wrx(&ir, irdb, diags, &mut current),
-                    IRKind::Align => self.iterate_align(&ir, irdb, diags, &mut current),
+                    IRKind::Wr64 |
+                    IRKind::Wr16be |
+                    IRKind::Wr24be |
+                    IRKind::Wr32be |
+                    IRKind::Wr40be |
+                    IRKind::Wr48be |
+                    IRKind::Wr56be |
+                    IRKind::Wr64be |
+                    IRKind::Wrbyte => self.iterate_wrx(ir, irdb, diags, &mut current),
+                    IRKind::Align => self.iterate_align(ir, irdb, diags, &current),
+                    IRKind::RoundupPow2 => self.iterate_roundup_pow2(ir, irdb, diags, &current),
                     IRKind::SetSec |
                     IRKind::SetImg |
-                    IRKind::SetAbs => self.iterate_set(&ir, irdb, diags, &mut current),
+                    IRKind::SetAbs => self.iterate_set(ir, irdb, diags, &current),
+
+                    IRKind::Wrf => self.iterate_wrf(ir, irdb, diags, &mut current),
+                    IRKind::Fill => self.iterate_fill(ir, irdb, diags, &mut current),
+
+                    IRKind::SliceStart => {
+                        self.slice_starts.push(current.clone());
+                        true
+                    }
+                    IRKind::SliceEnd => self.iterate_slice_end(ir, irdb, diags, &mut current),
 
-                    IRKind::Wrf => self.iterate_wrf(&ir, irdb, diags, &mut current),
+                    IRKind::If => self.iterate_if(ir, lid, irdb, diags, &mut skip_to),
+                    // Reaching an else means the if block was taken
+                    IRKind::Else => {
+                        skip_to = Some(irdb.branch_targets[&lid]);
+                        true
+                    }
+                    IRKind::EndIf => { true }
                     
                     // The following IR types are evaluated only at execute time.
                     // Nothing to do during iteration.
                     IRKind::Label |
                     IRKind::Assert |
+                    IRKind::AssertIfWritten |
+                    IRKind::AssertInRange |
+                    IRKind::At |
+                    IRKind::EnsureNonempty |
+                    IRKind::Endian |
+                    IRKind::XorStart |
+                    IRKind::XorEnd |
                     IRKind::Print |
+                    IRKind::Printf |
                     IRKind::I64 |
                     IRKind::U64 => { true }
                 }
             }
-            if self.ir_locs == old_locations {
+            if self.options.dump_fixedpoint {
+                self.dump_fixedpoint_pass(irdb, self.iter_count, &old_locations, current.img);
+            }
+            self.image_size = current.img;
+            if self.options.max_image_size.is_some_and(|max| current.img > max) {
+                let msg = format!("Output image size {} exceeds the maximum of {} bytes",
+                                  current.img, self.options.max_image_size.unwrap());
+                diags.err0("EXEC_48", &msg);
+                return false;
+            }
+            if single_pass || self.ir_locs == old_locations {
                 stable = true;
+                if self.options.dump_fixedpoint {
+                    println!("fixedpoint: converged after {} passes", self.iter_count);
+                }
+            } else if self.iter_count >= max_iterations {
+                let msg = format!("Location counters did not converge after {} passes",
+                                  self.iter_count);
+                diags.err0("EXEC_49", &msg);
+                return false;
             } else {
                 // Record the current location information
                 old_locations = self.ir_locs.clone();
@@ -996,14 +2112,11 @@ impl Engine {
     fn assert_info_operand(&self, opnd_num: usize, irdb: &IRDb, diags: &mut Diags) {
         let opnd = self.parms[opnd_num].borrow();
         let ir_opnd = &irdb.parms[opnd_num];
-        match opnd.data_type {
-            DataType::U64 => {
-                let val = opnd.to_u64();
-                let msg = format!("Operand has value {}", val);
-                let primary_code_ref = ir_opnd.src_loc.clone();
-                diags.note1("EXEC_8", &msg, primary_code_ref);
-            }
-            _ => {}
+        if opnd.data_type == DataType::U64 {
+            let val = opnd.to_u64();
+            let msg = format!("Operand has value {}", val);
+            let primary_code_ref = ir_opnd.src_loc.clone();
+            diags.note1("EXEC_8", &msg, primary_code_ref);
         }
     }
 
@@ -1027,17 +2140,17 @@ impl Engine {
         }
     }
 
-    fn execute_assert(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, _file: &File)
+    fn execute_assert(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags)
                       -> Result<()> {
         self.trace("Engine::execute_assert:");
         let mut result = Ok(());
         let opnd_num = ir.operands[0];
         self.trace(format!("engine::execute_assert: checking operand {}", opnd_num).as_str());
         let parm = self.parms[opnd_num].borrow();
-        if parm.to_bool() == false {
+        if !parm.to_bool() {
             // assert failed
-            let msg = format!("Assert expression failed");
-            diags.err1("EXEC_2", &msg, ir.src_loc.clone());
+            let msg = "Assert expression failed";
+            diags.err1("EXEC_2", msg, ir.src_loc.clone());
 
             // If the boolean the assertion failed on is an output of an operation,
             // then backtrack to print information about that operation.  To backtrack
@@ -1050,9 +2163,71 @@ impl Engine {
         result
     }
 
+    /// Returns true if a write of the section is in a block taken
+    fn section_is_written(&self, irdb: &IRDb, sec_name: &str) -> bool {
+        irdb.ir_vec.iter().enumerate().any(|(lid, ir)| {
+            ir.kind == IRKind::SectionStart && !self.skipped[lid] &&
+                irdb.get_opnd_as_identifier(ir, 0) == sec_name
+        })
+    }
+
+    /// The size is computed during iteration.  For sizeof(start, end),
+    /// verify that the end does not precede the start in the final layout.
+    fn execute_sizeof(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags) -> Result<()> {
+        if ir.operands.len() != 3 {
+            return Ok(());
+        }
+        let (start_img, end_img) = self.get_span_imgs(ir, irdb, diags)
+                .ok_or_else(|| anyhow!("Sizeof failed"))?;
+        if end_img < start_img {
+            let start = self.parms[ir.operands[0]].borrow();
+            let end = self.parms[ir.operands[1]].borrow();
+            let msg = format!("In sizeof({}, {}), '{}' at image offset {:#X} precedes '{}' \
+                               at image offset {:#X}", start.to_identifier(), end.to_identifier(),
+                               end.to_identifier(), end_img, start.to_identifier(), start_img);
+            diags.err1("EXEC_55", &msg, ir.src_loc.clone());
+            return Err(anyhow!("Sizeof failed"));
+        }
+        Ok(())
+    }
+
+    /// Reports the value and both bounds when the value is out of range.
+    fn execute_assert_in_range(&self, ir: &IR, diags: &mut Diags) -> Result<()> {
+        self.trace("Engine::execute_assert_in_range:");
+        if self.parms[ir.operands[0]].borrow().to_bool() {
+            return Ok(());
+        }
+
+        let vals: Vec<String> = ir.operands[1..].iter().map(|&op_num| {
+            let op = self.parms[op_num].borrow();
+            match op.data_type {
+                DataType::U64 => format!("{}", op.to_u64()),
+                _ => format!("{}", op.to_i64()),
+            }
+        }).collect();
+        let msg = format!("Value {} is not in range {} to {}", vals[0], vals[1], vals[2]);
+        diags.err1("EXEC_54", &msg, ir.src_loc.clone());
+        Err(anyhow!("Assert failed"))
+    }
+
+    /// Verify that the at statement is at the expected absolute address
+    fn execute_at(&self, ir: &IR, lid: usize, diags: &mut Diags) -> Result<()> {
+        self.trace("Engine::execute_at:");
+        let expected = self.parms[ir.operands[0]].borrow().to_u64_promoted();
+        let actual = self.ir_locs[lid].abs;
+        if expected == actual {
+            return Ok(());
+        }
+
+        let msg = format!("Expected absolute address {:#X}, but the actual address is {:#X}",
+                          expected, actual);
+        diags.err1("EXEC_68", &msg, ir.src_loc.clone());
+        Err(anyhow!("At failed"))
+    }
+
     /// Execute the print statement.
     /// If the diags noprint option is true, suppress printing.
-    fn execute_print(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, _file: &File)
+    fn execute_print(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags)
                       -> Result<()> {
         self.trace("Engine::execute_print:");
         if diags.noprint {
@@ -1062,40 +2237,167 @@ impl Engine {
 
         let xstr_opt = self.evaluate_string_expr(ir, irdb, diags);
         if xstr_opt.is_none() {
-            let msg = format!("Evaluating string expression failed.");
-            diags.err1("EXEC_16", &msg, ir.src_loc.clone());
+            let msg = "Evaluating string expression failed.";
+            diags.err1("EXEC_16", msg, ir.src_loc.clone());
             return Err(anyhow!("Wrs failed"));
         }
 
         let xstr = xstr_opt.unwrap();
         print!("{}", xstr);
+        self.flush_print();
+        Ok(())
+    }
+
+    /// Unless line buffered, flush print output so that it precedes any
+    /// later diagnostics or output, even without a trailing newline.
+    fn flush_print(&self) {
+        if !self.options.line_buffered {
+            let _ = std::io::stdout().flush();
+        }
+    }
+
+    /// Formats the printf arguments according to the format string in the
+    /// first operand.  IRDb validation guarantees the specifiers are valid
+    /// and match the number of arguments.
+    fn format_printf(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags) -> Option<String> {
+        let fmt = self.parms[ir.operands[0]].borrow().to_str().to_string();
+        let mut xstr = String::new();
+        let mut arg_num = 1;
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                xstr.push(c);
+                continue;
+            }
+            let spec = chars.next().unwrap();
+            if spec == '%' {
+                xstr.push('%');
+                continue;
+            }
+            let op_num = ir.operands[arg_num];
+            arg_num += 1;
+            let op = self.parms[op_num].borrow();
+            let formatted = match (spec, op.data_type) {
+                ('s', DataType::QuotedString) => Some(op.to_str().to_string()),
+                ('s', DataType::U64) => Some(format!("{:#X}", op.to_u64())),
+                ('s', DataType::Integer) |
+                ('s', DataType::I64) |
+                ('d', DataType::Integer) |
+                ('d', DataType::I64) => Some(format!("{}", op.to_i64())),
+                ('d', DataType::U64) => Some(format!("{}", op.to_u64())),
+                ('x', DataType::U64) => Some(format!("{:x}", op.to_u64())),
+                ('x', DataType::Integer) |
+                ('x', DataType::I64) => Some(format!("{:x}", op.to_i64())),
+                ('b', DataType::U64) => Some(format!("{:b}", op.to_u64())),
+                ('b', DataType::Integer) |
+                ('b', DataType::I64) => Some(format!("{:b}", op.to_i64())),
+                _ => None,
+            };
+            match formatted {
+                Some(s) => xstr.push_str(&s),
+                None => {
+                    let msg = format!("Cannot format type '{:?}' with '%{}'", op.data_type, spec);
+                    let src_loc = irdb.parms[op_num].src_loc.clone();
+                    diags.err1("EXEC_44", &msg, src_loc);
+                    return None;
+                }
+            }
+        }
+        Some(xstr)
+    }
+
+    fn execute_printf(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags)
+                      -> Result<()> {
+        self.trace("Engine::execute_printf:");
+        if diags.noprint {
+            debug!("Suppressing printf statements.");
+            return Ok(());
+        }
+
+        match self.format_printf(ir, irdb, diags) {
+            Some(xstr) => {
+                print!("{}", xstr);
+                self.flush_print();
+                Ok(())
+            }
+            None => Err(anyhow!("Printf failed")),
+        }
+    }
+
+    /// XOR the bytes written by a wr_xor section with the key, which is
+    /// either a single byte value or a repeating string.
+    fn execute_xor_end(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, bytes: &mut [u8])
+                       -> Result<()> {
+        self.trace("Engine::execute_xor_end:");
+        let key_num = ir.operands[0];
+        let key_parm = self.parms[key_num].borrow();
+        let key = match key_parm.data_type {
+            DataType::QuotedString => key_parm.to_str().as_bytes().to_vec(),
+            DataType::U64 => u8::try_from(key_parm.to_u64()).ok().into_iter().collect(),
+            _ => u8::try_from(key_parm.to_i64()).ok().into_iter().collect(),
+        };
+        if key.is_empty() {
+            let msg = "The wr_xor key must be a byte value from 0 to 255 or a non-empty string";
+            diags.err1("EXEC_47", msg, irdb.parms[key_num].src_loc.clone());
+            return Err(anyhow!("Wr_xor failed"));
+        }
+        for (byte, k) in bytes.iter_mut().zip(key.iter().cycle()) {
+            *byte ^= k;
+        }
         Ok(())
     }
 
-    fn execute_wrs(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, file: &mut File)
+    /// Keep only the slice of the bytes written since the wr_slice started.
+    fn execute_slice_end(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, buf: &mut Vec<u8>,
+                         start: usize) -> Result<()> {
+        self.trace("Engine::execute_slice_end:");
+        let (offset, length) = match self.slice_range(ir, irdb, diags) {
+            Some(range) => range,
+            None => { return Err(anyhow!("Wr_slice failed")); }
+        };
+        let sec_size = (buf.len() - start) as u64;
+        match offset.checked_add(length) {
+            Some(end) if end <= sec_size => {
+                buf.truncate(start + end as usize);
+                buf.drain(start..start + offset as usize);
+                Ok(())
+            }
+            _ => {
+                let msg = format!("Slice at offset {} with length {} extends past the end \
+                                   of the {} byte section", offset, length, sec_size);
+                diags.err1("EXEC_64", &msg, ir.src_loc.clone());
+                Err(anyhow!("Wr_slice failed"))
+            }
+        }
+    }
+
+    fn execute_wrs(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, buf: &mut Vec<u8>)
                    -> Result<()> {
         self.trace("Engine::execute_wrs:");
-        let xstr_opt = self.evaluate_string_expr(ir, irdb, diags);
+        let xstr_opt = self.evaluate_write_string(ir, irdb, diags);
         if xstr_opt.is_none() {
-            let msg = format!("Evaluating string expression failed.");
-            diags.err1("EXEC_15", &msg, ir.src_loc.clone());
+            let msg = "Evaluating string expression failed.";
+            diags.err1("EXEC_15", msg, ir.src_loc.clone());
+            return Err(anyhow!("Wrs failed"));
+        }
+
+        let bytes_opt = self.encode_string(&xstr_opt.unwrap(), ir, diags);
+        if bytes_opt.is_none() {
             return Err(anyhow!("Wrs failed"));
         }
 
-        let xstr = xstr_opt.unwrap();
-        let bufs = xstr.as_bytes();
         // the map_error lambda just converts io::error to a std::error
-        let result = file.write_all(bufs)
+        let result = buf.write_all(&bytes_opt.unwrap())
                                      .map_err(|err|err.into());
         if result.is_err() {
-            let msg = format!("Writing string failed");
-            diags.err1("EXEC_3", &msg, ir.src_loc.clone());
+            let msg = "Writing string failed";
+            diags.err1("EXEC_3", msg, ir.src_loc.clone());
         }
         
         result
     }
 
-    fn execute_wrf(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, file: &mut File)
+    fn execute_wrf(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, out_buf: &mut Vec<u8>)
                    -> Result<()> {
         self.trace("Engine::execute_wrf:");
 
@@ -1107,7 +2409,7 @@ impl Engine {
         let file_info = irdb.files.get(path).unwrap();
 
         // open the file, which may fail
-        let fh_result = File::open(path);
+        let fh_result = File::open(&file_info.path);
 
         if fh_result.is_err() {
             let fh_err = fh_result.err().unwrap();
@@ -1137,11 +2439,11 @@ impl Engine {
 
             let bytes_read = read_result.unwrap();
             total_bytes += bytes_read;
-            let write_result = file.write_all(&buf[0..bytes_read])
+            let write_result = out_buf.write_all(&buf[0..bytes_read])
                                         .map_err(|err|err.into());
             if write_result.is_err() {
-                let msg = format!("Writing buffer failed");
-                diags.err1("EXEC_35", &msg, ir.src_loc.clone());
+                let msg = "Writing buffer failed";
+                diags.err1("EXEC_35", msg, ir.src_loc.clone());
                 return write_result;
             }
 
@@ -1150,14 +2452,18 @@ impl Engine {
             }
         }
 
-        assert!(total_bytes as u64 == file_info.size);
+        // Convert outside the assert, which must not report diagnostics
+        let total_bytes = size_to_u64(total_bytes, ir, diags);
+        if total_bytes.is_none() {
+            return Err(anyhow!("Wrf failed"));
+        }
+        assert!(total_bytes == Some(file_info.size));
 
-        
         Ok(())
     }
 
-    fn execute_wrx(&self, ir: &IR, _irdb: &IRDb, diags: &mut Diags, file: &mut File)
-                   -> Result<()> {
+    fn execute_wrx(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, big_endian: bool,
+                   out_buf: &mut Vec<u8>) -> Result<()> {
         self.trace(format!("Engine::execute_wrx: {:?}", ir.kind ).as_str());
         let byte_size = get_wrx_byte_width(ir);
 
@@ -1168,19 +2474,47 @@ impl Engine {
         // Extract bytes as little-endian.  One a big-endian machine, the LSB will
         // bit the highest address location, which is wrong since we're writing
         // from the lowest address.
-        let buf = match parm.data_type {
+        let val = match parm.data_type {
             DataType::Integer |
-            DataType::I64 => {
-                let val = parm.to_i64();
-                val.to_le_bytes()
-            }
-            DataType::U64 => {
-                let val = parm.to_u64();
-                val.to_le_bytes()
-            }
+            DataType::I64 => parm.to_i64() as u64,
+            DataType::U64 => parm.to_u64(),
             bad => { panic!("Unexpected parameter type {:?} in execute_wrx", bad); }
         };
 
+        // The value fits if either the signed or unsigned interpretation
+        // fits the write width.
+        if byte_size < 8 && !self.options.no_truncation_warnings {
+            let bits = byte_size * 8;
+            let fits = match parm.data_type {
+                DataType::U64 => val >> bits == 0,
+                _ => {
+                    let sval = parm.to_i64();
+                    sval >= -(1i64 << (bits - 1)) && sval < (1i64 << bits)
+                }
+            };
+            if !fits {
+                let shown = match parm.data_type {
+                    DataType::U64 => format!("{:#X}", val),
+                    _ => format!("{}", parm.to_i64()),
+                };
+                let op = format!("{:?}", ir.kind).to_lowercase();
+                let msg = format!("Value {} does not fit in {} byte(s), so {} writes only \
+                                   the low bytes {:#X}", shown, byte_size, op,
+                                   val & ((1u64 << bits) - 1));
+                diags.warn1("EXEC_61", &msg, irdb.parms[opnd_num].src_loc.clone());
+            }
+        }
+
+        // Big-endian writes take the low-order bytes of the value, which
+        // are at the end of the big-endian byte array.
+        let le_buf = val.to_le_bytes();
+        let be_buf = val.to_be_bytes();
+        let buf = if big_endian || is_wrx_big_endian(ir) {
+            &be_buf[be_buf.len() - byte_size..]
+        } else {
+            &le_buf[0..byte_size]
+        };
+
         let mut repeat_count = 1;
 
         if ir.operands.len() == 2 {
@@ -1195,7 +2529,7 @@ impl Engine {
         // The map_error lambda just converts io::error to a std::error
         // Write only the number of bytes required for the width of the wrx
         while repeat_count > 0 {
-            let result = file.write_all(&buf[0..byte_size])
+            let result = out_buf.write_all(buf)
                                         .map_err(|err|err.into());
             if result.is_err() {
                 let msg = format!("{:?} failed", ir.kind);
@@ -1208,12 +2542,103 @@ impl Engine {
         Ok(())
     }
 
-    pub fn execute(&self, irdb: &IRDb, diags: &mut Diags, file: &mut File)
+    /// Write the fill value truncated to a byte.  Iterate already verified
+    /// that the count is not negative.
+    fn execute_fill(&self, ir: &IR, buf: &mut Vec<u8>) -> Result<()> {
+        self.trace("Engine::execute_fill:");
+        let count = self.parms[ir.operands[0]].borrow().to_u64_promoted();
+        let byte = self.parms[ir.operands[1]].borrow().to_u64_promoted() as u8;
+        let count = usize::try_from(count)?;
+        buf.resize(buf.len() + count, byte);
+        Ok(())
+    }
+
+    /// Write one byte from a wrbytes list.  Unlike wr8, the value must fit
+    /// in a byte rather than being silently truncated.  Negative signed values
+    /// down to -128 are allowed and written as two's complement.
+    fn execute_wrbyte(&self, ir: &IR, irdb: &IRDb, diags: &mut Diags, buf: &mut Vec<u8>)
                    -> Result<()> {
+        self.trace("Engine::execute_wrbyte:");
+        let opnd_num = ir.operands[0];
+        let fits = {
+            let parm = self.parms[opnd_num].borrow();
+            match parm.data_type {
+                DataType::Integer |
+                DataType::I64 => {
+                    let val = parm.to_i64();
+                    (-128..=255).contains(&val)
+                }
+                DataType::U64 => { parm.to_u64() <= 0xFF }
+                bad => { panic!("Unexpected parameter type {:?} in execute_wrbyte", bad); }
+            }
+        };
+
+        if !fits {
+            let parm = self.parms[opnd_num].borrow();
+            let val = match parm.data_type {
+                DataType::U64 => format!("{:#X}", parm.to_u64()),
+                _ => format!("{}", parm.to_i64()),
+            };
+            let msg = format!("Value {} in wrbytes list does not fit in a byte", val);
+            diags.err1("EXEC_36", &msg, irdb.parms[opnd_num].src_loc.clone());
+            return Err(anyhow!("Wrbytes value out of range"));
+        }
+
+        self.execute_wrx(ir, irdb, diags, false, buf)
+    }
+
+    /// Execute the IR, writing the output bytes to out.
+    /// After the first error, execution continues to report more errors,
+    /// but no further bytes are written.  On error, out receives only the
+    /// output produced before the first failing operation.
+    /// Since wr_xor modifies bytes after they are written, the image is
+    /// assembled in memory and written to out at the end.
+    pub fn execute<W: Write>(&self, irdb: &IRDb, diags: &mut Diags, out: &mut W)
+                             -> Result<()> {
+        let mut buf = Vec::new();
+        let result = self.execute_pass(irdb, diags, &mut buf, false);
+        if out.write_all(&buf).and_then(|_| out.flush()).is_err() {
+            diags.err0("EXEC_60", "Writing the output image failed");
+            return Err(anyhow!("Write failed"));
+        }
+        result
+    }
+
+    /// Execute the IR into buf.  When render_only is true, only produce the
+    /// output bytes and skip checks and console output, which may not be
+    /// valid until crc32() values are resolved.
+    fn execute_pass(&self, irdb: &IRDb, diags: &mut Diags, buf: &mut Vec<u8>,
+                    render_only: bool) -> Result<()> {
         self.trace("Engine::execute:");
         let mut result;
         let mut error_count = 0;
-        for ir in &irdb.ir_vec {
+        // Output buffer offsets where each nested wr_xor started
+        let mut xor_starts = Vec::new();
+        // Output buffer offsets where each nested wr_slice started
+        let mut slice_starts = Vec::new();
+        // Byte order of wrx writes.  A section restores the byte order of
+        // its parent when it ends.
+        let mut big_endian = false;
+        let mut endian_stack = Vec::new();
+        for (lid, ir) in irdb.ir_vec.iter().enumerate() {
+            if self.skipped[lid] {
+                continue;
+            }
+            let is_write = [IRKind::Wr8, IRKind::Wr16, IRKind::Wr24, IRKind::Wr32,
+                            IRKind::Wr40, IRKind::Wr48, IRKind::Wr56, IRKind::Wr64,
+                            IRKind::Wr16be, IRKind::Wr24be, IRKind::Wr32be, IRKind::Wr40be,
+                            IRKind::Wr48be, IRKind::Wr56be, IRKind::Wr64be,
+                            IRKind::Wrbyte, IRKind::Wrs, IRKind::Header,
+                            IRKind::Wrf, IRKind::Fill].contains(&ir.kind);
+            if is_write && error_count > 0 {
+                continue;
+            }
+            let is_check = [IRKind::Assert, IRKind::AssertIfWritten, IRKind::AssertInRange,
+                            IRKind::At, IRKind::Sizeof, IRKind::Print, IRKind::Printf]
+                            .contains(&ir.kind);
+            if is_check && render_only {
+                continue;
+            }
             result = match ir.kind {
                 IRKind::Wr8  |
                 IRKind::Wr16 |
@@ -1222,26 +2647,104 @@ impl Engine {
                 IRKind::Wr40 |
                 IRKind::Wr48 |
                 IRKind::Wr56 |
-                IRKind::Wr64 => { self.execute_wrx(ir, irdb, diags, file) }
-                IRKind::Assert => { self.execute_assert(ir, irdb, diags, file) }
-                IRKind::Print => { self.execute_print(ir, irdb, diags, file) }
-                IRKind::Wrs => { self.execute_wrs(ir, irdb, diags, file) }
-                IRKind::Wrf => { self.execute_wrf(ir, irdb, diags, file) }
+                IRKind::Wr64 |
+                IRKind::Wr16be |
+                IRKind::Wr24be |
+                IRKind::Wr32be |
+                IRKind::Wr40be |
+                IRKind::Wr48be |
+                IRKind::Wr56be |
+                IRKind::Wr64be => { self.execute_wrx(ir, irdb, diags, big_endian, buf) }
+                IRKind::Wrbyte => { self.execute_wrbyte(ir, irdb, diags, buf) }
+                IRKind::Assert => { self.execute_assert(ir, irdb, diags) }
+                IRKind::AssertIfWritten => {
+                    if self.section_is_written(irdb, irdb.get_opnd_as_identifier(ir, 1)) {
+                        self.execute_assert(ir, irdb, diags)
+                    } else {
+                        Ok(())
+                    }
+                }
+                IRKind::AssertInRange => { self.execute_assert_in_range(ir, diags) }
+                IRKind::At => { self.execute_at(ir, lid, diags) }
+                IRKind::Sizeof => { self.execute_sizeof(ir, irdb, diags) }
+                IRKind::Print => { self.execute_print(ir, irdb, diags) }
+                IRKind::Printf => { self.execute_printf(ir, irdb, diags) }
+                IRKind::Header |
+                IRKind::Wrs => { self.execute_wrs(ir, irdb, diags, buf) }
+                IRKind::Wrf => { self.execute_wrf(ir, irdb, diags, buf) }
+                IRKind::Fill => { self.execute_fill(ir, buf) }
+                IRKind::XorStart => {
+                    xor_starts.push(buf.len());
+                    Ok(())
+                }
+                IRKind::Endian => {
+                    big_endian = self.parms[ir.operands[0]].borrow().to_identifier() == "big";
+                    Ok(())
+                }
+                IRKind::SectionStart => {
+                    endian_stack.push(big_endian);
+                    Ok(())
+                }
+                IRKind::SectionEnd => {
+                    big_endian = endian_stack.pop().unwrap();
+                    Ok(())
+                }
+                IRKind::XorEnd => {
+                    let start = xor_starts.pop().unwrap();
+                    self.execute_xor_end(ir, irdb, diags, &mut buf[start..])
+                }
+                IRKind::SliceStart => {
+                    slice_starts.push(buf.len());
+                    Ok(())
+                }
+                IRKind::SliceEnd => {
+                    let start = slice_starts.pop().unwrap();
+                    // After an error, writes are skipped and the section
+                    // bytes are incomplete.
+                    if error_count > 0 {
+                        Ok(())
+                    } else {
+                        self.execute_slice_end(ir, irdb, diags, buf, start)
+                    }
+                }
                 // the rest of these operations are computed during iteration
                 IRKind::SetSec |
                 IRKind::SetImg |
                 IRKind::SetAbs |
                 IRKind::Align |
+                IRKind::RoundupPow2 |
+                IRKind::EnsureNonempty |
+                IRKind::If |
+                IRKind::Else |
+                IRKind::EndIf |
                 IRKind::Abs |
                 IRKind::Img |
                 IRKind::Sec |
+                IRKind::Crc32 |
                 IRKind::Label |
-                IRKind::Sizeof |
                 IRKind::ToI64 |
                 IRKind::ToU64 |
+                IRKind::IsPow2 |
+                IRKind::CountOnes |
+                IRKind::CountZeros |
+                IRKind::Clamp |
+                IRKind::Join |
+                IRKind::BitReverse |
+                IRKind::AlignUp |
+                IRKind::AlignDown |
+                IRKind::Pow |
+                IRKind::Min |
+                IRKind::Max |
+                IRKind::Pack32 |
+                IRKind::AbsValue |
+                IRKind::Negate |
+                IRKind::BitNot |
+                IRKind::LogicalNot |
                 IRKind::NEq |
                 IRKind::GEq |
                 IRKind::LEq |
+                IRKind::Less |
+                IRKind::Greater |
                 IRKind::DoubleEq |
                 IRKind::I64 |
                 IRKind::U64 |
@@ -1254,8 +2757,6 @@ impl Engine {
                 IRKind::Divide |
                 IRKind::Add |
                 IRKind::Subtract |
-                IRKind::SectionStart |
-                IRKind::SectionEnd |
                 IRKind::LeftShift |
                 IRKind::RightShift => { Ok(()) }
             };
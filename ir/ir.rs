@@ -15,33 +15,66 @@ pub enum DataType {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IRKind {
     Abs,
+    AbsValue,
     Add,
     Align,
+    AlignDown,
+    AlignUp,
     Assert,
+    AssertIfWritten,
+    AssertInRange,
+    At,
     BitAnd,
+    BitNot,
     BitOr,
+    BitReverse,
+    Clamp,
+    CountOnes,
+    CountZeros,
+    Crc32,
     Divide,
     DoubleEq,
+    Else,
+    EndIf,
+    Endian,
+    EnsureNonempty,
+    Fill,
     GEq,
+    Header,
+    Greater,
     I64,
+    If,
     Img,
+    IsPow2,
+    Join,
     Label,
     LeftShift,
     LEq,
+    Less,
     LogicalAnd,
+    LogicalNot,
     LogicalOr,
+    Max,
+    Min,
     Modulo,
     Multiply,
+    Negate,
     NEq,
+    Pack32,
+    Pow,
     SetSec,
     SetImg,
     SetAbs,
     Print,
+    Printf,
     RightShift,
+    RoundupPow2,
     Sec,
     SectionEnd,
     SectionStart,
     Sizeof,
+    SliceEnd,
+    SliceStart,
     Subtract,
     ToI64,
     ToU64,
@@ -54,8 +87,45 @@ pub enum IRKind {
     Wr48,
     Wr56,
     Wr64,
+    Wr16be,
+    Wr24be,
+    Wr32be,
+    Wr40be,
+    Wr48be,
+    Wr56be,
+    Wr64be,
+    Wrbyte,
     Wrf,
     Wrs,
+    XorEnd,
+    XorStart,
+}
+
+/// Converts escape sequences in the body of a quoted string in a single
+/// pass.  Unrecognized escapes are passed through unchanged.
+fn unescape(body: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.clone().next() {
+            Some('"') => out.push('"'),
+            Some('n') => out.push('\n'),
+            Some('0') => out.push('\0'),
+            Some('t') => out.push('\t'),
+            _ => {
+                // Not an escape we know, so keep the backslash and
+                // process the next character normally.
+                out.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    out
 }
 
 #[derive(Debug)]
@@ -84,7 +154,7 @@ impl IROperand {
     }
     
     pub fn is_output_of(&self) -> Option<usize> {
-        return self.ir_lid;
+        self.ir_lid
     }
 
     /// Converts the specified string into the specified type
@@ -92,22 +162,22 @@ impl IROperand {
                     is_constant: bool, diags: &mut Diags) -> Option<Box<dyn Any>> {
         match data_type {
             DataType::QuotedString => {
+                // The engine computes string outputs, e.g. concatenation
+                if !is_constant {
+                    return Some(Box::new(String::new()));
+                }
                 // Trim quotes and convert escape characters
                 // For trimming, don't use trim_matches since that
                 // will incorrectly strip trailing escaped quotes.
-                return Some(Box::new(sval
+                return Some(Box::new(unescape(sval
                         .strip_prefix('\"').unwrap()
-                        .strip_suffix('\"').unwrap()
-                        .replace("\\\"", "\"")
-                        .replace("\\n", "\n")
-                        .replace("\\0", "\0")
-                        .replace("\\t", "\t")));
+                        .strip_suffix('\"').unwrap())));
             }
             DataType::U64 => {
                 if is_constant {
                     // Strip the trailing 'u' if any
                     let sval_no_u = sval.strip_suffix('u').unwrap_or(sval);
-                    let res = parse::<u64>(&sval_no_u);
+                    let res = parse::<u64>(sval_no_u);
                     if let Ok(v) = res {
                         return Some(Box::new(v));
                     } else {
@@ -168,9 +238,9 @@ impl IROperand {
 
     pub fn clone_val_box(&self) -> Box<dyn Any> {
         match self.data_type {
-            DataType::U64 => { Box::new(self.val.downcast_ref::<u64>().unwrap().clone()) },
+            DataType::U64 => { Box::new(*self.val.downcast_ref::<u64>().unwrap()) },
             DataType::Integer | // Integer stored as i64
-            DataType::I64 => { Box::new(self.val.downcast_ref::<i64>().unwrap().clone()) },
+            DataType::I64 => { Box::new(*self.val.downcast_ref::<i64>().unwrap()) },
             DataType::QuotedString |
             DataType::Identifier => {Box::new(self.val.downcast_ref::<String>().unwrap().clone())},
             DataType::Unknown => {Box::new(self.val.downcast_ref::<String>().unwrap().clone())},
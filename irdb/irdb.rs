@@ -6,10 +6,12 @@ use lineardb::{LinearDb};
 use log::{error, warn, info, debug, trace};
 
 use ir::{DataType, IR, IRKind, IROperand};
-use std::{collections::HashMap, fs, ops::Range, path::Path, path::PathBuf};
+use std::{collections::{HashMap, HashSet}, fs, num::IntErrorKind, ops::Range, path::Path,
+          path::PathBuf};
 use parse_int::parse;
 
 pub struct FileInfo {
+    /// The path resolved against the directory of the input file
     pub path: String,
     pub size: u64,
     pub src_loc: Range<usize>,
@@ -30,9 +32,38 @@ pub struct IRDb {
     /// Used for items with a size (potentially zero) such as sections.
     pub sized_locs: HashMap<String,Range<usize>>,
 
+    /// When true, mixed I64 and U64 operands promote to U64 with a
+    /// warning instead of an error.
+    pub relax_types: bool,
+
+    /// Relative wrf file paths are relative to this directory, which is
+    /// the directory of the input file.
+    pub base_dir: PathBuf,
+
     /// Maps an identifier to the start indices in the ir_vec.
     /// Used for items that are addressable, including sections and labels
     pub addressed_locs: HashMap<String,usize>,
+
+    /// Symbols defined on the command line with -D, mapping the name to
+    /// the unparsed value.
+    pub defines: HashMap<String,String>,
+
+    /// Maps the number of each linear operand that refers to a -D symbol
+    /// to the data type and string representation of the value.
+    define_lops: HashMap<usize,(DataType,String)>,
+
+    /// Maps the index of each if and else in the ir_vec to the index
+    /// where execution continues when the block is not taken.  For an if,
+    /// that's just past the matching else, or the end if without an else.
+    /// For an else, that's the matching end if.
+    pub branch_targets: HashMap<usize,usize>,
+
+    /// Sections with the @once attribute
+    pub once_sections: HashSet<String>,
+
+    /// Sections written by wr_slice.  The whole section never appears
+    /// in the output, so it has no address or CRC of its own.
+    pub sliced_sections: HashSet<String>,
 }
 
 impl IRDb {
@@ -62,6 +93,7 @@ impl IRDb {
         match lop.tok {
             // The following produce a boolean regardless of input data types
             ast::LexToken::Align |
+            ast::LexToken::RoundupPow2 |
             ast::LexToken::SetSec |
             ast::LexToken::SetImg |
             ast::LexToken::SetAbs |
@@ -69,21 +101,49 @@ impl IRDb {
             ast::LexToken::NEq |
             ast::LexToken::GEq |
             ast::LexToken::LEq |
+            ast::LexToken::Less |
+            ast::LexToken::Greater |
             ast::LexToken::Abs |
             ast::LexToken::Img |
             ast::LexToken::Sec |
             ast::LexToken::DoublePipe |
             ast::LexToken::DoubleAmpersand |
             ast::LexToken::Sizeof |
+            ast::LexToken::Crc32 |
             ast::LexToken::ToU64 |
+            ast::LexToken::IsPow2 |
+            ast::LexToken::CountOnes |
+            ast::LexToken::CountZeros |
+            ast::LexToken::BitReverse |
+            ast::LexToken::Pack32 |
+            ast::LexToken::Bang |
             ast::LexToken::U64 => { data_type = Some(DataType::U64) } // TODO: this will be I64 when we convert bool
             ast::LexToken::ToI64 |
+            ast::LexToken::Negate |
             ast::LexToken::I64 => { data_type = Some(DataType::I64) }
             ast::LexToken::Integer => { data_type = Some(DataType::Integer) }
+            ast::LexToken::Join |
             ast::LexToken::QuotedString => { data_type = Some(DataType::QuotedString) }
             ast::LexToken::Label => { data_type = Some(DataType::Identifier) }
-            ast::LexToken::Identifier => { data_type = Some(DataType::Identifier) }
+            ast::LexToken::Identifier => {
+                data_type = match self.define_lops.get(&lop_num) {
+                    Some((dt, _)) => Some(*dt),
+                    None => Some(DataType::Identifier),
+                };
+            }
             
+            // The output type is the common numeric type of all inputs
+            ast::LexToken::AbsValue |
+            ast::LexToken::Tilde |
+            ast::LexToken::AlignUp |
+            ast::LexToken::AlignDown |
+            ast::LexToken::Pow |
+            ast::LexToken::Min |
+            ast::LexToken::Max |
+            ast::LexToken::Clamp => {
+                data_type = self.get_common_numeric_type_r(depth, lop_num, lin_db, diags);
+            }
+
             // The following produce an output type that depends on inputs
             ast::LexToken::DoubleLess |
             ast::LexToken::DoubleGreater |
@@ -114,9 +174,14 @@ impl IRDb {
                     let rhs_opt = self.get_operand_data_type_r(depth + 1, rhs_num, lin_db, diags);
                     if let Some(rhs_dt) = rhs_opt {
                         // We now have both lhs and rhs data types
+                        // Plus between two strings is concatenation
+                        let is_concat = lop.tok == ast::LexToken::Plus &&
+                                        lhs_dt == DataType::QuotedString;
                         if lhs_dt == rhs_dt {
                             let allowed = [DataType::I64, DataType::U64, DataType::Integer];
-                            if !allowed.contains(&lhs_dt) {
+                            if is_concat {
+                                data_type = Some(lhs_dt);
+                            } else if !allowed.contains(&lhs_dt) {
                                 let msg = format!("Error, found data type '{:?}', but operation '{:?}' requires one of {:?}.",
                                                 lhs_dt, lop.tok, allowed);
                                 diags.err1("IRDB_2", &msg, lin_ir.src_loc.clone());
@@ -131,14 +196,22 @@ impl IRDb {
                                     dt_ok = true; // Integers work with s/u types
                                     data_type = Some(lhs_dt);
                                 }
-                            } else if lhs_dt == DataType::Integer {
-                                if [DataType::I64, DataType::U64].contains(&rhs_dt) {
-                                    dt_ok = true; // Integers work with s/u types
-                                    data_type = Some(rhs_dt);
-                                }
+                            } else if lhs_dt == DataType::Integer
+                                      && [DataType::I64, DataType::U64].contains(&rhs_dt) {
+                                dt_ok = true; // Integers work with s/u types
+                                data_type = Some(rhs_dt);
+                            } else if self.relax_types_ok(lhs_dt, rhs_dt) {
+                                dt_ok = true;
+                                data_type = Some(DataType::U64);
                             }
                 
-                            if !dt_ok {
+                            if !dt_ok && lop.tok == ast::LexToken::Plus &&
+                               (lhs_dt == DataType::QuotedString || rhs_dt == DataType::QuotedString) {
+                                let msg = format!("Cannot add a string and a number.  Left is {:?}, right is {:?}.",
+                                lhs_dt, rhs_dt);
+                                diags.err1("IRDB_24", &msg, lin_ir.src_loc.clone());
+                                diags.note0("IRDB_25", "'+' concatenates only when both operands are strings");
+                            } else if !dt_ok {
                                 let msg = format!("Error, data type mismatch in input operands.  Left is {:?}, right is {:?}.",
                                 lhs_dt, rhs_dt);
                                 diags.err1("IRDB_1", &msg, lin_ir.src_loc.clone());
@@ -155,19 +228,49 @@ impl IRDb {
             ast::LexToken::Wr48 |
             ast::LexToken::Wr56 |
             ast::LexToken::Wr64 |
+            ast::LexToken::Wr16be |
+            ast::LexToken::Wr24be |
+            ast::LexToken::Wr32be |
+            ast::LexToken::Wr40be |
+            ast::LexToken::Wr48be |
+            ast::LexToken::Wr56be |
+            ast::LexToken::Wr64be |
             ast::LexToken::Assert |
+            ast::LexToken::AssertIfWritten |
+            ast::LexToken::AssertInRange |
+            ast::LexToken::At |
             ast::LexToken::Print |
+            ast::LexToken::Printf |
+            ast::LexToken::Tee |
+            ast::LexToken::Const |
+            ast::LexToken::If |
+            ast::LexToken::Else |
+            ast::LexToken::Repeat |
             ast::LexToken::Section |
             ast::LexToken::OpenBrace |
             ast::LexToken::CloseBrace |
             ast::LexToken::Comma |
+            ast::LexToken::Colon |
             ast::LexToken::OpenParen |
             ast::LexToken::CloseParen |
             ast::LexToken::Semicolon |
             ast::LexToken::Wrs |
+            ast::LexToken::Header |
             ast::LexToken::Wr |
+            ast::LexToken::WrXor |
+            ast::LexToken::WrSlice |
             ast::LexToken::Wrf |
+            ast::LexToken::Fill |
+            ast::LexToken::Wrbytes |
+            ast::LexToken::OpenBracket |
+            ast::LexToken::CloseBracket |
             ast::LexToken::Output |
+            ast::LexToken::Endian |
+            ast::LexToken::Attribute |
+            ast::LexToken::Include |
+            ast::LexToken::IncludeOnce |
+            ast::LexToken::Equal |
+            ast::LexToken::DoublePlus |
             ast::LexToken::Unknown => { panic!("Token '{:?}' has no associated data type.", lop.tok); }
         };
 
@@ -175,6 +278,136 @@ impl IRDb {
         data_type
     }
 
+    /// With relaxed types, mixed I64 and U64 operands promote to U64.
+    /// Returns true if the data types should be promoted.  Operand
+    /// validation reports the warning.
+    fn relax_types_ok(&self, dt0: DataType, dt1: DataType) -> bool {
+        let mixed = [DataType::I64, DataType::U64];
+        self.relax_types && dt0 != dt1 && mixed.contains(&dt0) && mixed.contains(&dt1)
+    }
+
+    /// Warns if the specified number of input operands mix I64 and U64,
+    /// which is only possible with relaxed types.
+    fn warn_mixed_types(&self, ir: &IR, num_inputs: usize, diags: &mut Diags) {
+        let types: Vec<DataType> = ir.operands[..num_inputs].iter()
+                .map(|&op_num| self.parms[op_num].data_type).collect();
+        if types.contains(&DataType::I64) && types.contains(&DataType::U64) {
+            let msg = format!("Promoting mixed I64 and U64 operands of '{:?}' to U64", ir.kind);
+            diags.warn1("IRDB_22", &msg, ir.src_loc.clone());
+        }
+    }
+
+    /// Returns the common numeric data type of the input operands of the
+    /// IR that produces the specified output operand.  Integer inputs adopt
+    /// the type of any I64 or U64 input.  Returns None on error.
+    fn get_common_numeric_type_r(&mut self, depth: usize, lop_num: usize, lin_db: &LinearDb,
+                                 diags: &mut Diags) -> Option<DataType> {
+        let lop = &lin_db.operand_vec[lop_num];
+        let lin_ir_lid = lop.ir_lid.expect("Output operand does not have a source lid");
+        let lin_ir = &lin_db.ir_vec[lin_ir_lid];
+        // The lop this function was called with *is* the output operand
+        let (out_num, in_nums) = lin_ir.operand_vec.split_last().unwrap();
+        assert!(*out_num == lop_num);
+
+        let allowed = [DataType::I64, DataType::U64, DataType::Integer];
+        let mut common = DataType::Integer;
+        for &in_num in in_nums {
+            let dt = self.get_operand_data_type_r(depth + 1, in_num, lin_db, diags)?;
+            if !allowed.contains(&dt) {
+                let msg = format!("Error, found data type '{:?}', but operation '{:?}' requires one of {:?}.",
+                                  dt, lop.tok, allowed);
+                diags.err1("IRDB_36", &msg, lin_ir.src_loc.clone());
+                return None;
+            }
+            if dt == DataType::Integer || dt == common {
+                continue;
+            }
+            if self.relax_types_ok(common, dt) {
+                common = DataType::U64;
+                continue;
+            }
+            if common != DataType::Integer {
+                let msg = format!("Error, data type mismatch in input operands.  Found both {:?} and {:?}.",
+                                  common, dt);
+                diags.err1("IRDB_35", &msg, lin_ir.src_loc.clone());
+                return None;
+            }
+            common = dt;
+        }
+        Some(common)
+    }
+
+    /// Resolves each identifier operand used as a value to its -D symbol.
+    /// Identifiers that name a section or label for operations such as
+    /// sizeof() are not values.  An integer value keeps its integer type
+    /// and any other value is a string.
+    fn resolve_defines(&mut self, lin_db: &LinearDb, diags: &mut Diags) -> bool {
+        let mut ident_lops = HashSet::new();
+        let mut label_locs = HashMap::new();
+        for lir in &lin_db.ir_vec {
+            if [IRKind::SectionStart, IRKind::SectionEnd, IRKind::Label, IRKind::Endian,
+                IRKind::Abs, IRKind::Img, IRKind::Sec, IRKind::Sizeof, IRKind::Crc32]
+                    .contains(&lir.op) {
+                ident_lops.extend(lir.operand_vec.iter().copied());
+            }
+            // Only the last operand of assert_if_written is the section name
+            if lir.op == IRKind::AssertIfWritten {
+                ident_lops.extend(lir.operand_vec.last().copied());
+            }
+            if lir.op == IRKind::Label {
+                let label = &lin_db.operand_vec[lir.operand_vec[0]];
+                label_locs.insert(label.sval.as_str(), label.src_loc.clone());
+            }
+        }
+
+        let mut result = true;
+        for (lop_num, lop) in lin_db.operand_vec.iter().enumerate() {
+            if lop.tok != ast::LexToken::Identifier || ident_lops.contains(&lop_num) {
+                continue;
+            }
+            // A -D symbol never replaces a section or label name
+            let (kind, name_loc) = match lin_db.section_locs.get(&lop.sval) {
+                Some(loc) => ("section", Some(loc)),
+                None => ("label", label_locs.get(lop.sval.as_str())),
+            };
+            if let Some(name_loc) = name_loc {
+                let m = if self.defines.contains_key(&lop.sval) {
+                    format!("Symbol '{}' is both a -D command line symbol and the name of a {}",
+                            lop.sval, kind)
+                } else {
+                    format!("The {} name '{}' is not a value.  Use abs({}), img({}) or \
+                             sizeof({}) for its address or size", kind, lop.sval, lop.sval,
+                             lop.sval, lop.sval)
+                };
+                diags.err2("IRDB_31", &m, lop.src_loc.clone(), name_loc.clone());
+                result = false;
+                continue;
+            }
+            let value = self.defines.get(&lop.sval);
+            if value.is_none() {
+                let mut m = format!("Unknown symbol '{}'.  Define the symbol on the command \
+                                     line with -D {}=<value>", lop.sval, lop.sval);
+                if let Some(nearest) = ast::suggest_name(&lop.sval,
+                                            self.defines.keys().map(|name| name.as_str())) {
+                    m.push_str(&format!(".  Did you mean '{}'?", nearest));
+                }
+                diags.err1("IRDB_29", &m, lop.src_loc.clone());
+                result = false;
+                continue;
+            }
+            let value = value.unwrap();
+            let resolved = match ast::single_token(value) {
+                Some(ast::LexToken::Integer) => (DataType::Integer, value.clone()),
+                Some(ast::LexToken::U64) => (DataType::U64, value.clone()),
+                Some(ast::LexToken::I64) => (DataType::I64, value.clone()),
+                Some(ast::LexToken::QuotedString) => (DataType::QuotedString, value.clone()),
+                _ => (DataType::QuotedString, format!("\"{}\"", value)),
+            };
+            self.define_lops.insert(lop_num, resolved);
+        }
+        result
+    }
+
     /// Process untyped linear operands into real IR operands
     fn process_lin_operands(&mut self, lin_db: &LinearDb, diags: &mut Diags) -> bool {
         trace!("IRDb::process_lin_operands: Enter");
@@ -194,9 +427,15 @@ impl IRDb {
             // will convert the string representation to its native value.
             let is_constant = lop.ir_lid.is_none();
 
+            // A -D symbol takes the string representation of its value
+            let sval = match self.define_lops.get(&lop_num) {
+                Some((_, sval)) => sval,
+                None => &lop.sval,
+            };
+
             // During construction of the IROperand, the string in the linear operand is converted
             // to an actual typed value, which can fail, e.g. integer out of range
-            let opnd = IROperand::new( lop.ir_lid, &lop.sval, &lop.src_loc, data_type,
+            let opnd = IROperand::new( lop.ir_lid, sval, &lop.src_loc, data_type,
                                                     is_constant, diags);
             if let Some(opnd) = opnd {
                 self.parms.push(opnd);
@@ -216,6 +455,75 @@ impl IRDb {
         true
     }
 
+    // Every join input operand must be a string or a number.  The last
+    // operand is the string output.
+    fn validate_join_operands(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let mut result = true;
+        for &opnd_num in &ir.operands[..ir.operands.len() - 1] {
+            let opnd = &self.parms[opnd_num];
+            if ![DataType::Integer, DataType::I64, DataType::U64, DataType::QuotedString]
+                    .contains(&opnd.data_type) {
+                let m = format!("'join' operands must be strings or numbers, found '{:?}'.",
+                                opnd.data_type);
+                diags.err2("IRDB_28", &m, ir.src_loc.clone(), opnd.src_loc.clone());
+                result = false;
+            }
+        }
+        result
+    }
+
+    // Validate the printf format string and that the number of format
+    // specifiers matches the number of remaining operands.
+    fn validate_printf_operands(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let fmt_opnd = &self.parms[ir.operands[0]];
+        if fmt_opnd.data_type != DataType::QuotedString || !fmt_opnd.is_constant {
+            let m = "The first 'printf' operand must be a format string in double-quotes.";
+            diags.err2("IRDB_18", m, ir.src_loc.clone(), fmt_opnd.src_loc.clone());
+            return false;
+        }
+
+        let mut num_specs = 0;
+        let mut chars = fmt_opnd.to_str().chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                continue;
+            }
+            match chars.next() {
+                Some('%') => {}
+                Some('x') | Some('d') | Some('s') | Some('b') => { num_specs += 1; }
+                bad => {
+                    let bad = bad.map_or(String::new(), |c| c.to_string());
+                    let m = format!("Unknown printf format specifier '%{}'.  Expected one of \
+                                     %x, %d, %s, %b or %%.", bad);
+                    diags.err1("IRDB_19", &m, fmt_opnd.src_loc.clone());
+                    return false;
+                }
+            }
+        }
+
+        let num_args = ir.operands.len() - 1;
+        if num_specs != num_args {
+            let m = format!("Format string has {} specifier(s), but found {} argument(s).",
+                            num_specs, num_args);
+            diags.err2("IRDB_20", &m, ir.src_loc.clone(), fmt_opnd.src_loc.clone());
+            return false;
+        }
+        true
+    }
+
+    // The XOR key is a byte value or a repeating string
+    fn validate_xor_key_operand(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let key_opnd = &self.parms[ir.operands[0]];
+        if ![DataType::Integer, DataType::I64, DataType::U64, DataType::QuotedString]
+                .contains(&key_opnd.data_type) {
+            let m = format!("'wr_xor' key must be a byte value or a quoted string, found '{:?}'.",
+                            key_opnd.data_type);
+            diags.err2("IRDB_23", &m, ir.src_loc.clone(), key_opnd.src_loc.clone());
+            return false;
+        }
+        true
+    }
+
     // Validate write file operands
     fn validate_wrf_operands(&mut self, ir: &IR, diags: &mut Diags) -> bool {
         let len = ir.operands.len();
@@ -237,24 +545,27 @@ impl IRDb {
         }
 
         let path_str = path_opnd.to_str();
-        let path = Path::new(path_str);
 
         // Determine if we already know about this file
         if self.files.contains_key(path_str) {
             return true; // Already recorded this file, nothing more to do.
         }
 
+        // Relative paths are relative to the input file.  Joining an
+        // absolute path just returns the absolute path.
+        let path = self.base_dir.join(path_str);
+
         // open the file and determine the size
-        let fm_result = fs::metadata(path);
+        let fm_result = fs::metadata(&path);
         if fm_result.is_err() {
             // Canonicalizing a missing file doesn't work, so
-            // just use the current directory.
-            let pbuf_result = PathBuf::from("./").canonicalize();
-            let full_path = if pbuf_result.is_err() {
-                "!!Cannot determine full path!!".to_string()
-            } else {
+            // just use the base directory.
+            let pbuf_result = PathBuf::from("./").join(&self.base_dir).canonicalize();
+            let full_path = if let Ok(pbuf) = pbuf_result {
                 // Hmm... seems like a lot of work to get the string
-                pbuf_result.unwrap().to_str().unwrap().to_string()
+                pbuf.to_str().unwrap().to_string()
+            } else {
+                "!!Cannot determine full path!!".to_string()
             };
             let os_err = fm_result.err().unwrap().to_string();
             let m = format!("Error getting metadata for file '{}'\n\
@@ -274,7 +585,7 @@ impl IRDb {
 
         let size = fm.len();
 
-        let finfo = FileInfo { path: path_str.to_string(), size,
+        let finfo = FileInfo { path: path.to_string_lossy().into_owned(), size,
                                 src_loc: path_opnd.src_loc.clone() };
 
         self.files.insert(path_str.to_string(), finfo);
@@ -316,6 +627,96 @@ impl IRDb {
                 return false;
             }
         }
+        self.warn_mixed_types(ir, 2, diags);
+        true
+    }
+
+    /// Pack32 has value and width operands for each field plus the U64
+    /// output operand.  LinearDb already verified the widths.
+    fn validate_pack_operands(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let inputs = &ir.operands[..ir.operands.len() - 1];
+        for &op_num in inputs {
+            let opnd = &self.parms[op_num];
+            if ![DataType::Integer, DataType::I64, DataType::U64].contains(&opnd.data_type) {
+                let m = format!("pack32 fields require integers, found '{:?}'.", opnd.data_type);
+                diags.err2("IRDB_30", &m, ir.src_loc.clone(), opnd.src_loc.clone());
+                return false;
+            }
+        }
+        true
+    }
+
+    /// LinearDb lowers assert_in_range to a boolean followed by the value
+    /// and bounds, which the comparisons already type checked.
+    fn validate_assert_in_range(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let len = ir.operands.len();
+        if len != 4 {
+            let m = format!("'assert_in_range' requires a value and two bounds, \
+                             but found {} total operands.", len);
+            diags.err1("IRDB_27", &m, ir.src_loc.clone());
+            return false;
+        }
+        true
+    }
+
+    /// assert_if_written requires an integer or boolean expression followed
+    /// by the section name
+    fn validate_assert_if_written(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let len = ir.operands.len();
+        if len != 2 {
+            let m = format!("'assert_if_written' requires a section and an expression, \
+                             but found {} total operands.", len);
+            diags.err1("IRDB_33", &m, ir.src_loc.clone());
+            return false;
+        }
+        let opnd = &self.parms[ir.operands[0]];
+        if ![DataType::Integer, DataType::I64, DataType::U64].contains(&opnd.data_type) {
+            let m = format!("'assert_if_written' expression requires an integer or boolean \
+                             operand, found '{:?}'.", opnd.data_type);
+            diags.err2("IRDB_34", &m, ir.src_loc.clone(), opnd.src_loc.clone());
+            return false;
+        }
+        true
+    }
+
+    /// Fill requires both a count and a value
+    fn validate_fill(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let len = ir.operands.len();
+        if len != 2 {
+            let m = format!("'fill' requires a count and a value, but found {} operands.", len);
+            diags.err1("IRDB_26", &m, ir.src_loc.clone());
+            return false;
+        }
+        self.validate_numeric_1_or_2(ir, diags)
+    }
+
+    /// Add is either numeric addition or concatenation of two strings
+    fn validate_add(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let is_concat = ir.operands.len() == 3 &&
+                        ir.operands[0..2].iter().all(|&op_num|
+                            self.parms[op_num].data_type == DataType::QuotedString);
+        is_concat || self.validate_numeric_2(ir, diags)
+    }
+
+    // Expect 3 operands which are int or bool
+    fn validate_numeric_3(&self, ir: &IR, diags: &mut Diags) -> bool {
+        let len = ir.operands.len();
+        if len != 4 {
+            let m = format!("'{:?}' expression requires 3 input and one output \
+                                    operands, but found {} total operands.", ir.kind, len);
+            diags.err1("IRDB_21", &m, ir.src_loc.clone());
+            return false;
+        }
+        for op_num in 0..3 {
+            let opnd = &self.parms[ir.operands[op_num]];
+            if ![DataType::Integer, DataType::I64, DataType::U64].contains(&opnd.data_type) {
+                let m = format!("'{:?}' expression requires an integer, found '{:?}'.",
+                                    ir.kind, opnd.data_type);
+                diags.err2("IRDB_37", &m, ir.src_loc.clone(), opnd.src_loc.clone());
+                return false;
+            }
+        }
+        self.warn_mixed_types(ir, 3, diags);
         true
     }
 
@@ -352,7 +753,7 @@ impl IRDb {
     }
 
     fn validate_operands(&mut self, ir: &IR, diags: &mut Diags) -> bool {
-        let result = match ir.kind {
+        match ir.kind {
             IRKind::Align |
             IRKind::SetSec |
             IRKind::SetImg |
@@ -364,14 +765,50 @@ impl IRDb {
             IRKind::Wr40 |
             IRKind::Wr48 |
             IRKind::Wr56 |
-            IRKind::Wr64 => { self.validate_numeric_1_or_2(ir, diags) }
-            IRKind::Assert => { self.validate_numeric_1(ir, diags) }
+            IRKind::Wr64 |
+            IRKind::Wr16be |
+            IRKind::Wr24be |
+            IRKind::Wr32be |
+            IRKind::Wr40be |
+            IRKind::Wr48be |
+            IRKind::Wr56be |
+            IRKind::Wr64be => { self.validate_numeric_1_or_2(ir, diags) }
+            IRKind::Wrbyte |
+            IRKind::If |
+            IRKind::Assert |
+            IRKind::At => { self.validate_numeric_1(ir, diags) }
+            // Numeric input operand plus the U64 output operand
+            IRKind::IsPow2 |
+            IRKind::CountOnes |
+            IRKind::CountZeros => { self.validate_numeric_1_or_2(ir, diags) }
+            IRKind::Clamp => { self.validate_numeric_3(ir, diags) }
+            IRKind::AlignUp |
+            IRKind::AlignDown |
+            IRKind::BitReverse |
+            IRKind::Pow |
+            IRKind::Min |
+            IRKind::Max => { self.validate_numeric_2(ir, diags) }
+            IRKind::AbsValue |
+            IRKind::BitNot |
+            IRKind::LogicalNot |
+            IRKind::Negate => { self.validate_numeric_1_or_2(ir, diags) }
             IRKind::Wrf => { self.validate_wrf_operands(ir, diags) }
+            IRKind::Fill => { self.validate_fill(ir, diags) }
+            IRKind::AssertInRange => { self.validate_assert_in_range(ir, diags) }
+            IRKind::AssertIfWritten => { self.validate_assert_if_written(ir, diags) }
+            IRKind::XorEnd => { self.validate_xor_key_operand(ir, diags) }
+            IRKind::SliceEnd => { self.validate_numeric_1_or_2(ir, diags) }
             IRKind::Wrs |
+            IRKind::Header |
             IRKind::Print => { self.validate_string_expr_operands(ir, diags) }
+            IRKind::Printf => { self.validate_printf_operands(ir, diags) }
+            IRKind::Join => { self.validate_join_operands(ir, diags) }
+            IRKind::Pack32 => { self.validate_pack_operands(ir, diags) }
             IRKind::NEq |
             IRKind::LEq |
             IRKind::GEq |
+            IRKind::Less |
+            IRKind::Greater |
             IRKind::DoubleEq |
             IRKind::LeftShift |
             IRKind::RightShift |
@@ -383,26 +820,37 @@ impl IRDb {
             IRKind::BitOr |
             IRKind::LogicalOr |
             IRKind::Subtract |
-            IRKind::Add => { self.validate_numeric_2(ir, diags) }
+            IRKind::Add => { self.validate_add(ir, diags) }
             IRKind::ToI64 |
             IRKind::ToU64 |
             IRKind::U64 |
             IRKind::I64 |
             IRKind::SectionStart |
             IRKind::SectionEnd |
+            IRKind::EnsureNonempty |
+            IRKind::Endian |
+            IRKind::XorStart |
+            IRKind::SliceStart |
+            IRKind::Else |
+            IRKind::EndIf |
+            IRKind::RoundupPow2 |
             IRKind::Sizeof |
+            IRKind::Crc32 |
             IRKind::Label |
             IRKind::Abs |
             IRKind::Img |
             IRKind::Sec => { true }
-        };
-        result
+        }
     }
 
     /// Convert the linear IR to real IR.  Conversion from Linear IR to real IR can fail,
     /// which is a hassle we don't want to deal with during linearization of the AST.
     fn process_linear_ir(&mut self, lin_db: &LinearDb, diags: &mut Diags) -> bool {
         let mut result = true;
+        // Each open if and its else, if any, for matching branch targets
+        let mut open_ifs: Vec<(usize, Option<usize>)> = Vec::new();
+        // Nesting depth of wr_slice
+        let mut slice_depth = 0;
         for lir in &lin_db.ir_vec {
             let kind = lir.op;
             // The operands are just indices into the operands array
@@ -420,6 +868,11 @@ impl IRDb {
                     IRKind::SectionStart => {
                         // create the section entry and set the starting IR number
                         let sec_name = self.get_opnd_as_identifier(&ir, 0).to_string();
+                        // A section referenced by name has only one instance,
+                        // so a sliced instance is the only one.
+                        if slice_depth > 0 {
+                            self.sliced_sections.insert(sec_name.clone());
+                        }
                         let rng = Range {start: ir_num, end: 0};
                         self.sized_locs.insert(sec_name.clone(), rng);
                         self.addressed_locs.insert(sec_name, ir_num);
@@ -430,6 +883,25 @@ impl IRDb {
                         let rng = self.sized_locs.get_mut(&sec_name).unwrap();
                         rng.end = ir_num;
                     }
+                    IRKind::SliceStart => { slice_depth += 1; }
+                    IRKind::SliceEnd => { slice_depth -= 1; }
+                    IRKind::If => { open_ifs.push((ir_num, None)); }
+                    IRKind::Else => {
+                        // An if with a bad condition is already an error
+                        if let Some(open_if) = open_ifs.last_mut() {
+                            open_if.1 = Some(ir_num);
+                        }
+                    }
+                    IRKind::EndIf => {
+                        match open_ifs.pop() {
+                            Some((if_num, Some(else_num))) => {
+                                self.branch_targets.insert(if_num, else_num + 1);
+                                self.branch_targets.insert(else_num, ir_num);
+                            }
+                            Some((if_num, None)) => { self.branch_targets.insert(if_num, ir_num); }
+                            None => {}
+                        }
+                    }
                     _ => {}
                 }
                 self.ir_vec.push(ir);
@@ -440,26 +912,73 @@ impl IRDb {
         result
     }
 
-    pub fn new(lin_db: &LinearDb, diags: &mut Diags) -> Option<IRDb> {
+    /// defines: Symbols defined on the command line, mapping each name to
+    /// the unparsed value
+    pub fn new(lin_db: &LinearDb, diags: &mut Diags, relax_types: bool,
+               base_dir: &Path, defines: &HashMap<String,String>) -> Option<IRDb> {
 
         // If the user specified a starting address in the output statement
         // then convert to a real number
         let mut start_addr = 0;
 
         if let Some(addr_str) = lin_db.output_addr_str.as_ref() {
-            if let Ok(addr) = parse::<u64>(addr_str) {
-                start_addr = addr;
-            } else {
-                let m = format!("Malformed integer operand {}", addr_str);
-                let primary_code_ref = lin_db.output_addr_loc.as_ref().unwrap();
-                diags.err1("IRDB_3", &m, primary_code_ref.clone());
-                return None;                
+            match parse::<u64>(addr_str) {
+                Ok(addr) => { start_addr = addr; }
+                Err(err) => {
+                    let primary_code_ref = lin_db.output_addr_loc.as_ref().unwrap();
+                    // Distinguish a well-formed but too large address from garbage
+                    if *err.kind() == IntErrorKind::PosOverflow {
+                        let m = format!("Output address {} is out of range.  The maximum \
+                                         address is {:#X}", addr_str, u64::MAX);
+                        diags.err1("IRDB_15", &m, primary_code_ref.clone());
+                    } else {
+                        let m = format!("Malformed integer operand {}", addr_str);
+                        diags.err1("IRDB_3", &m, primary_code_ref.clone());
+                    }
+                    return None;
+                }
+            }
+        }
+
+        // If the user specified an output alignment, round the starting
+        // address up to the alignment boundary.
+        if let Some(align_str) = lin_db.output_align_str.as_ref() {
+            let align_loc = lin_db.output_align_loc.as_ref().unwrap();
+            let align = match parse::<u64>(align_str) {
+                Ok(align) if align.is_power_of_two() => align,
+                Ok(align) => {
+                    let m = format!("Alignment must be a non-zero power of 2, but found {}",
+                                    align);
+                    diags.err1("IRDB_32", &m, align_loc.clone());
+                    return None;
+                }
+                Err(_) => {
+                    let m = format!("Output align {} must be a positive integer", align_str);
+                    diags.err1("IRDB_16", &m, align_loc.clone());
+                    return None;
+                }
+            };
+            match start_addr.checked_next_multiple_of(align) {
+                Some(addr) => { start_addr = addr; }
+                None => {
+                    let m = format!("Aligning output address {:#X} to {} is out of range",
+                                    start_addr, align_str);
+                    diags.err1("IRDB_17", &m, align_loc.clone());
+                    return None;
+                }
             }
         }
 
         let mut ir_db = IRDb { ir_vec: Vec::new(), parms: Vec::new(),
             sized_locs: HashMap::new(), addressed_locs: HashMap::new(), start_addr,
-            files: HashMap::new() };
+            files: HashMap::new(), relax_types, base_dir: base_dir.to_path_buf(),
+            defines: defines.clone(), define_lops: HashMap::new(),
+            branch_targets: HashMap::new(), once_sections: lin_db.once_sections.clone(),
+            sliced_sections: HashSet::new() };
+
+        if !ir_db.resolve_defines(lin_db, diags) {
+            return None;
+        }
 
         if !ir_db.process_lin_operands(lin_db, diags) {
             return None;
@@ -481,35 +1000,116 @@ impl IRDb {
             for child in &ir.operands {
                 let operand = &self.parms[*child];
                 if !first {
-                    op.push_str(",");
+                    op.push(',');
                 } else {
                     first = false;
                 }
                 if let Some(ir_lid) = operand.is_output_of() {
                     op.push_str(&format!(" ({:?})tmp{}, output of lid {}", operand.data_type, *child, ir_lid));
                 } else {
-                    match operand.data_type {
-                        DataType::U64 => {
-                            // Always display U64 as hex
-                            let v = operand.val.downcast_ref::<u64>().unwrap();
-                            op.push_str(&format!(" ({:?}){:#X}", operand.data_type, v));
-                        }
-                        DataType::Integer |
-                        DataType::I64 => {
-                            let v = operand.val.downcast_ref::<i64>().unwrap();
-                            op.push_str(&format!(" ({:?}){}", operand.data_type, v));
-                        }
-                        // order matters, must be last
-                        _ => {
-                            let v = operand.val.downcast_ref::<String>().unwrap();
-                            op.push_str(&format!(" ({:?}){}", operand.data_type, v));
-                        },
-                    }
+                    op.push_str(&format!(" ({:?}){}", operand.data_type,
+                                         IRDb::operand_val_str(operand)));
                 }
             }
             debug!("IRDb: {}", op);
         }
     }    
+
+    /// Returns the IR as stable, line oriented text suitable for external
+    /// tools.  The grammar is:
+    ///
+    /// ```text
+    /// file    := "brink-ir 1" NL operand* op*
+    /// operand := "operand" INDEX TYPE ("const" VALUE | "output" LID) SPAN NL
+    /// op      := "op" LID KIND INDEX* SPAN NL
+    /// SPAN    := "@" START ".." END
+    /// ```
+    ///
+    /// TYPE and KIND are the DataType and IRKind variant names.  A U64 VALUE
+    /// is hex with a 0x prefix, I64 and Integer values are decimal, and
+    /// strings and identifiers are double quoted with '\\', '\"', '\n',
+    /// '\r', '\t' and '\xNN' escapes.  Spans are byte offsets into the
+    /// preprocessed source.
+    pub fn emit_ir(&self) -> String {
+        let mut out = String::from("brink-ir 1\n");
+        for (idx, operand) in self.parms.iter().enumerate() {
+            let val = if let Some(ir_lid) = operand.is_output_of() {
+                format!("output {}", ir_lid)
+            } else {
+                let v = IRDb::operand_val_str(operand);
+                match operand.data_type {
+                    DataType::QuotedString |
+                    DataType::Identifier => format!("const {}", IRDb::quote_ir_str(&v)),
+                    _ => format!("const {}", v),
+                }
+            };
+            out.push_str(&format!("operand {} {:?} {} @{}..{}\n", idx, operand.data_type,
+                                  val, operand.src_loc.start, operand.src_loc.end));
+        }
+        for (lid, ir) in self.ir_vec.iter().enumerate() {
+            out.push_str(&format!("op {} {:?}", lid, ir.kind));
+            for opnd in &ir.operands {
+                out.push_str(&format!(" {}", opnd));
+            }
+            out.push_str(&format!(" @{}..{}\n", ir.src_loc.start, ir.src_loc.end));
+        }
+        out
+    }
+
+    /// Double quotes and escapes a string for emit_ir.
+    fn quote_ir_str(s: &str) -> String {
+        let mut out = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '"' => out.push_str("\\\""),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 || c == '\x7F' => {
+                    out.push_str(&format!("\\x{:02X}", c as u32));
+                }
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    /// Returns the value of a constant operand as a display string.
+    fn operand_val_str(operand: &IROperand) -> String {
+        match operand.data_type {
+            DataType::U64 => {
+                // Always display U64 as hex
+                let v = operand.val.downcast_ref::<u64>().unwrap();
+                format!("{:#X}", v)
+            }
+            DataType::Integer |
+            DataType::I64 => {
+                let v = operand.val.downcast_ref::<i64>().unwrap();
+                format!("{}", v)
+            }
+            // order matters, must be last
+            _ => {
+                let v = operand.val.downcast_ref::<String>().unwrap();
+                v.to_string()
+            },
+        }
+    }
+
+    /// Prints the operand table with the data type, the value or source
+    /// IR, and the source code span of each operand.
+    pub fn dump_operands(&self) {
+        for (idx, operand) in self.parms.iter().enumerate() {
+            let val = if let Some(ir_lid) = operand.is_output_of() {
+                format!("variable, output of lid {}", ir_lid)
+            } else {
+                format!("constant {}", IRDb::operand_val_str(operand))
+            };
+            println!("operand {}: {:?} {}, span {}..{}", idx, operand.data_type, val,
+                     operand.src_loc.start, operand.src_loc.end);
+        }
+    }
 }
 
 
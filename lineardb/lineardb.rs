@@ -5,9 +5,10 @@ use diags::Diags;
 #[allow(unused_imports)]
 use log::{error, warn, info, debug, trace};
 
-use ast::{Ast, AstDb, LexToken, TokenInfo};
+use ast::{Ast, AstDb, Const, LexToken, TokenInfo, suggest_name};
 use ir::{IRKind};
-use std::{collections::{HashMap}, ops::Range};
+use parse_int::parse;
+use std::{collections::{HashMap, HashSet}, convert::TryFrom, ops::Range};
 
 /// The operand type for linear IRs.  This operand type is very similar to the
 /// IROperand type, with the critical distinction that LinOperand creation
@@ -21,7 +22,7 @@ pub struct LinOperand {
     pub sval: String,
 }
 
-impl<'toks> LinOperand {
+impl LinOperand {
 
     /// Create a new linear operand.  If the ir_lid exists, then this
     /// operand is the output of the specified lid.
@@ -32,7 +33,7 @@ impl<'toks> LinOperand {
     }
 
     pub fn is_output_of(&self) -> Option<usize> {
-        return self.ir_lid;
+        self.ir_lid
     }
 }
 
@@ -69,17 +70,29 @@ fn tok_to_irkind(tok: LexToken) -> IRKind {
         LexToken::Wr48 => { IRKind::Wr48 }
         LexToken::Wr56 => { IRKind::Wr56 }
         LexToken::Wr64 => { IRKind::Wr64 }
+        LexToken::Wr16be => { IRKind::Wr16be }
+        LexToken::Wr24be => { IRKind::Wr24be }
+        LexToken::Wr32be => { IRKind::Wr32be }
+        LexToken::Wr40be => { IRKind::Wr40be }
+        LexToken::Wr48be => { IRKind::Wr48be }
+        LexToken::Wr56be => { IRKind::Wr56be }
+        LexToken::Wr64be => { IRKind::Wr64be }
         LexToken::Assert => { IRKind::Assert }
+        LexToken::At => { IRKind::At }
         LexToken::Align => { IRKind::Align }
         LexToken::SetSec => { IRKind::SetSec }
         LexToken::SetImg => { IRKind::SetImg }
         LexToken::SetAbs => { IRKind::SetAbs }
         LexToken::Wrs => { IRKind::Wrs }
+        LexToken::Header => { IRKind::Header }
         LexToken::Wrf => { IRKind::Wrf }
+        LexToken::Fill => { IRKind::Fill }
         LexToken::NEq => { IRKind::NEq }
         LexToken::DoubleEq => { IRKind::DoubleEq }
         LexToken::GEq => { IRKind::GEq }
         LexToken::LEq => { IRKind::LEq }
+        LexToken::Less => { IRKind::Less }
+        LexToken::Greater => { IRKind::Greater }
         LexToken::DoubleGreater => { IRKind::RightShift }
         LexToken::DoubleLess => { IRKind::LeftShift }
         LexToken::Plus => { IRKind::Add }
@@ -92,12 +105,29 @@ fn tok_to_irkind(tok: LexToken) -> IRKind {
         LexToken::Pipe => { IRKind::BitOr }
         LexToken::DoublePipe => { IRKind::LogicalOr }
         LexToken::Sizeof => { IRKind::Sizeof }
+        LexToken::Crc32 => { IRKind::Crc32 }
         LexToken::ToU64 => { IRKind::ToU64 }
         LexToken::ToI64 => { IRKind::ToI64 }
+        LexToken::Negate => { IRKind::Negate }
+        LexToken::Tilde => { IRKind::BitNot }
+        LexToken::Bang => { IRKind::LogicalNot }
+        LexToken::IsPow2 => { IRKind::IsPow2 }
+        LexToken::Clamp => { IRKind::Clamp }
+        LexToken::AlignUp => { IRKind::AlignUp }
+        LexToken::AlignDown => { IRKind::AlignDown }
+        LexToken::BitReverse => { IRKind::BitReverse }
+        LexToken::Pow => { IRKind::Pow }
+        LexToken::Min => { IRKind::Min }
+        LexToken::Max => { IRKind::Max }
+        LexToken::Join => { IRKind::Join }
+        LexToken::AbsValue => { IRKind::AbsValue }
+        LexToken::CountOnes => { IRKind::CountOnes }
+        LexToken::CountZeros => { IRKind::CountZeros }
         LexToken::Abs => { IRKind::Abs }
         LexToken::Img => { IRKind::Img }
         LexToken::Sec => { IRKind::Sec }
         LexToken::Print => { IRKind::Print }
+        LexToken::Printf => { IRKind::Printf }
         bug => {
             panic!("Failed to convert LexToken to IRKind for {:?}", bug);
         }
@@ -111,6 +141,16 @@ pub struct LinearDb {
     pub output_sec_loc: Range<usize>,
     pub output_addr_str: Option<String>,
     pub output_addr_loc: Option<Range<usize>>,
+    pub output_align_str: Option<String>,
+    pub output_align_loc: Option<Range<usize>>,
+    /// Sections with the @once attribute
+    pub once_sections: HashSet<String>,
+    /// Source location of the name of every section, written or not
+    pub section_locs: HashMap<String, Range<usize>>,
+    /// Names of the constants referenced by the linearized output
+    pub used_consts: HashSet<String>,
+    /// Maximum number of operations that repeat blocks may expand to
+    max_irs: usize,
 }
 
 /**
@@ -143,6 +183,18 @@ impl<'toks> LinearDb {
         lid
     }
 
+    /// Records a synthesized binary operation on existing operands.
+    /// Returns the linear operand index of the new output operand.
+    fn new_binary_ir(&mut self, nid: NodeId, ast: &'toks Ast, tinfo: &TokenInfo<'toks>,
+                     tok: LexToken, lhs: usize, rhs: usize) -> usize {
+        let ir_lid = self.new_ir(nid, ast, tok_to_irkind(tok));
+        self.add_existing_operand_to_ir(ir_lid, lhs);
+        self.add_existing_operand_to_ir(ir_lid, rhs);
+        let mut out_tinfo = tinfo.clone();
+        out_tinfo.tok = tok;
+        self.add_new_operand_to_ir(ir_lid, LinOperand::new(Some(ir_lid), &out_tinfo))
+    }
+
     // Control recursion to some safe level.  100 is just a guesstimate.
     const MAX_RECURSION_DEPTH:usize = 100;
     
@@ -170,7 +222,7 @@ impl<'toks> LinearDb {
         result
     }
 
-    fn operand_count_is_valid(&self, expected: usize, lops: &Vec<usize>, diags: &mut Diags, tinfo: &TokenInfo) -> bool {
+    fn operand_count_is_valid(&self, expected: usize, lops: &[usize], diags: &mut Diags, tinfo: &TokenInfo) -> bool {
         let found = lops.len();
         if found != expected {
             let m = format!("Expected {} operand(s), but found {} for '{}' expression",
@@ -210,6 +262,192 @@ impl<'toks> LinearDb {
         self.process_operands(expected, lops, ir_lid, diags, tinfo)
     }
 
+    /// Returns the named constant if the identifier node refers to one.
+    /// Identifiers that name a section, label or byte order are never
+    /// constant references.
+    fn const_ref<'a>(&self, nid: NodeId, ast: &'toks Ast, ast_db: &'a AstDb) -> Option<&'a Const> {
+        let parent_tok = ast.parent(nid).map(|pnid| ast.get_tinfo(pnid).tok);
+        if parent_tok.is_some_and(|tok| [LexToken::Sizeof, LexToken::Crc32, LexToken::Abs,
+                                         LexToken::Img, LexToken::Sec, LexToken::Endian]
+                                         .contains(&tok)) {
+            return None;
+        }
+        ast_db.consts.get(ast.get_tinfo(nid).val)
+    }
+
+    // Limits on repeat blocks to avoid exhausting memory
+    const MAX_REPEAT_COUNT:i128 = 1_000_000;
+    const DEFAULT_MAX_IRS:usize = 10_000_000;
+
+    /// Evaluates a constant expression, such as a repeat count, while
+    /// linearizing.  Only literals, named constants and integer arithmetic
+    /// are allowed, since the value must be known before the engine runs.
+    fn const_eval_r(&mut self, rdepth: usize, nid: NodeId, diags: &mut Diags,
+                    ast: &'toks Ast, ast_db: &AstDb) -> Option<i128> {
+        if !self.depth_sanity(rdepth, nid, diags, ast) {
+            return None;
+        }
+        let tinfo = ast.get_tinfo(nid);
+        let is_operator = [LexToken::Negate, LexToken::Plus, LexToken::Minus, LexToken::Asterisk,
+                           LexToken::FSlash, LexToken::Percent, LexToken::Ampersand,
+                           LexToken::Pipe, LexToken::DoubleLess, LexToken::DoubleGreater]
+                           .contains(&tinfo.tok);
+        let mut args = Vec::new();
+        if is_operator {
+            for child in ast.children(nid) {
+                args.push(self.const_eval_r(rdepth + 1, child, diags, ast, ast_db)?);
+            }
+        }
+        let val = match tinfo.tok {
+            LexToken::Integer |
+            LexToken::U64 |
+            LexToken::I64 => {
+                let sval = tinfo.val.trim_end_matches(['u', 'i']);
+                parse::<i128>(sval).ok()
+            }
+            LexToken::Identifier if self.const_ref(nid, ast, ast_db).is_some() => {
+                let cnst = self.const_ref(nid, ast, ast_db).unwrap();
+                if tinfo.loc.start < cnst.end {
+                    let msg = format!("Constant '{}' is used before its definition", tinfo.val);
+                    diags.err2("LINEAR_20", &msg, tinfo.span(), cnst.loc.clone());
+                    return None;
+                }
+                self.used_consts.insert(tinfo.val.to_string());
+                return self.const_eval_r(rdepth + 1, cnst.expr_nid, diags, ast, ast_db);
+            }
+            LexToken::Negate => args[0].checked_neg(),
+            LexToken::Plus => args[0].checked_add(args[1]),
+            LexToken::Minus => args[0].checked_sub(args[1]),
+            LexToken::Asterisk => args[0].checked_mul(args[1]),
+            LexToken::FSlash => args[0].checked_div(args[1]),
+            LexToken::Percent => args[0].checked_rem(args[1]),
+            LexToken::Ampersand => Some(args[0] & args[1]),
+            LexToken::Pipe => Some(args[0] | args[1]),
+            LexToken::DoubleLess => u32::try_from(args[1]).ok()
+                                        .and_then(|shift| args[0].checked_shl(shift)),
+            LexToken::DoubleGreater => u32::try_from(args[1]).ok()
+                                        .and_then(|shift| args[0].checked_shr(shift)),
+            _ => {
+                let msg = format!("'{}' is not allowed in a constant expression.  Use \
+                                   only numbers, named constants and arithmetic", tinfo.val);
+                diags.err1("LINEAR_13", &msg, tinfo.span());
+                return None;
+            }
+        };
+        if val.is_none() {
+            let msg = format!("Constant expression '{}' overflows or divides by zero", tinfo.val);
+            diags.err1("LINEAR_14", &msg, tinfo.span());
+        }
+        val
+    }
+
+    /// Record the statements of the repeat block the specified number of
+    /// times.  The linear IR simply contains each copy in order, just as
+    /// writing a section more than once duplicates the section.
+    fn record_repeat(&mut self, rdepth: usize, parent_nid: NodeId, diags: &mut Diags,
+                     ast: &'toks Ast, ast_db: &AstDb) -> bool {
+        let tinfo = ast.get_tinfo(parent_nid);
+        let mut children = ast.children(parent_nid);
+        // The parser guarantees the count is the first child
+        let count_nid = children.next().unwrap();
+        let count = match self.const_eval_r(rdepth + 1, count_nid, diags, ast, ast_db) {
+            Some(count) => count,
+            None => { return false; }
+        };
+        if !(0..=LinearDb::MAX_REPEAT_COUNT).contains(&count) {
+            let msg = format!("Repeat count {} is outside the range 0 to {}",
+                              count, LinearDb::MAX_REPEAT_COUNT);
+            diags.err1("LINEAR_15", &msg, tinfo.span());
+            return false;
+        }
+
+        let body: Vec<NodeId> = children.collect();
+        // Statements do not return operands
+        let mut lops = Vec::new();
+        for _ in 0..count {
+            for &nid in &body {
+                if !self.record_r(rdepth + 1, nid, &mut lops, diags, ast, ast_db) {
+                    // Report errors in the block only once
+                    return false;
+                }
+                // Check the running total after each statement, since a
+                // nested repeat may check only its own iterations
+                if self.ir_vec.len() > self.max_irs {
+                    let msg = format!("Repeat block expands to more than {} operations",
+                                      self.max_irs);
+                    diags.err1("LINEAR_16", &msg, tinfo.span());
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Record a pack32 statement as follows:
+    /// pack32(v0:w0, v1:w1); ==> pack32 v0, w0, v1, w1, packed; wr32 packed;
+    /// The field widths must be constant so we can verify that they fill
+    /// exactly 32 bits before the engine runs.
+    fn record_pack32(&mut self, rdepth: usize, parent_nid: NodeId, diags: &mut Diags,
+                     ast: &'toks Ast, ast_db: &AstDb) -> bool {
+        let tinfo = ast.get_tinfo(parent_nid);
+        // The parser guarantees alternating value and width children
+        let mut total = 0;
+        for width_nid in ast.children(parent_nid).skip(1).step_by(2) {
+            let width = match self.const_eval_r(rdepth + 1, width_nid, diags, ast, ast_db) {
+                Some(width) => width,
+                None => { return false; }
+            };
+            if !(1..=32).contains(&width) {
+                let msg = format!("Field width {} is outside the range 1 to 32", width);
+                diags.err1("LINEAR_17", &msg, ast.get_tinfo(width_nid).span());
+                return false;
+            }
+            total += width;
+        }
+        if total != 32 {
+            let msg = format!("Field widths total {} bits, but pack32 requires exactly 32", total);
+            diags.err1("LINEAR_18", &msg, tinfo.span());
+            return false;
+        }
+
+        let mut lops = Vec::new();
+        let result = self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+        let ir_lid = self.new_ir(parent_nid, ast, IRKind::Pack32);
+        for idx in lops {
+            self.add_existing_operand_to_ir(ir_lid, idx);
+        }
+        // Add the destination operand to hold the packed value
+        let packed = self.add_new_operand_to_ir(ir_lid, LinOperand::new(Some(ir_lid), tinfo));
+
+        let wr32_lid = self.new_ir(parent_nid, ast, IRKind::Wr32);
+        self.add_existing_operand_to_ir(wr32_lid, packed);
+        result
+    }
+
+    /// Record the write of the named section in-place.
+    fn record_section_write(&mut self, sec_name_str: &str, tinfo: &TokenInfo,
+                            diags: &mut Diags, ast: &'toks Ast, ast_db: &AstDb) -> bool {
+        // A vector to track the operands of this expression.
+        let mut lops = Vec::new();
+        debug!("LinearDb::record_r: recursing into section {}", sec_name_str);
+
+        // Using the name of the section, use the AST database to get a reference
+        // to the section object.  ast_db processing has already guaranteed
+        // that the section name is legitimate, so unwrap().
+        let section = ast_db.sections.get(sec_name_str).unwrap();
+        let sec_nid = section.nid;
+
+        // Recurse into the referenced section.  AstDb already limits the
+        // depth of nested section writes, so the section contents start
+        // over at expression depth zero.
+        let mut result = self.record_r(0, sec_nid, &mut lops, diags, ast, ast_db);
+        // The write does not produce an IR of its own,
+        // but inserts an entire section in-place.  So, we don't have a
+        // linear ID for the write and expect no operands.
+        result &= self.operand_count_is_valid(0, &lops, diags, tinfo);
+        result
+    }
+
     /// Recursively record information about the children of an AST object. The
     /// main purpose of this function is to flatten the AST into linear form.
     /// Type and and operand checking is minimal to reduce complexity during
@@ -232,37 +470,67 @@ impl<'toks> LinearDb {
         let mut result = true;
         match tok {
             LexToken::Wr => {
-                // A vector to track the operands of this expression.
-                let mut lops = Vec::new();
                 // Write the contents of a section.  This isn't a simple recursion
                 // into the children.  Instead, we redirect to the specified section.
                 let sec_name_str = ast.get_child_str(parent_nid, 0).unwrap();
-                debug!("LinearDb::record_r: recursing into section {}", sec_name_str);
-
-                // Using the name of the section, use the AST database to get a reference
-                // to the section object.  ast_db processing has already guaranteed
-                // that the section name is legitimate, so unwrap().
-                let section = ast_db.sections.get(sec_name_str).unwrap();
-                let sec_nid = section.nid;
-
-                // Recurse into the referenced section.
-                result &= self.record_r(rdepth + 1, sec_nid, 
-                &mut lops, diags, ast, ast_db);
-                // The 'wr' expression does not produce an IR of its own,
-                // but inserts an entire section in-place.  So, we don't have a
-                // linear ID for the 'wr' and expect no operands.
-                result &= self.operand_count_is_valid(0, &lops, diags, tinfo);
+                result &= self.record_section_write(sec_name_str, tinfo, diags, ast, ast_db);
+            }
+            LexToken::WrXor => {
+                // wr_xor sec, key; ==> xor_start; wr sec; xor_end key;
+                // The engine XORs the bytes written between start and end.
+                let sec_name_str = ast.get_child_str(parent_nid, 0).unwrap();
+                self.new_ir(parent_nid, ast, IRKind::XorStart);
+                result &= self.record_section_write(sec_name_str, tinfo, diags, ast, ast_db);
+                let mut lops = Vec::new();
+                // Skip the section name and record the key expression
+                for nid in ast.children(parent_nid).skip(1) {
+                    result &= self.record_r(rdepth + 1, nid, &mut lops, diags, ast, ast_db);
+                }
+                let end_lid = self.new_ir(parent_nid, ast, IRKind::XorEnd);
+                result &= self.process_operands(1, &mut lops, end_lid, diags, tinfo);
+            }
+            LexToken::WrSlice => {
+                // wr_slice sec, offset, length; ==> slice_start; wr sec; slice_end offset, length;
+                // The engine keeps only the specified range of the bytes
+                // written between start and end.
+                let sec_name_str = ast.get_child_str(parent_nid, 0).unwrap();
+                self.new_ir(parent_nid, ast, IRKind::SliceStart);
+                result &= self.record_section_write(sec_name_str, tinfo, diags, ast, ast_db);
+                let mut lops = Vec::new();
+                // Skip the section name and record the offset and length
+                for nid in ast.children(parent_nid).skip(1) {
+                    result &= self.record_r(rdepth + 1, nid, &mut lops, diags, ast, ast_db);
+                }
+                let end_lid = self.new_ir(parent_nid, ast, IRKind::SliceEnd);
+                result &= self.process_operands(2, &mut lops, end_lid, diags, tinfo);
+            }
+            LexToken::Equal => {
+                // A concatenated section writes each named section in order
+                // as if by 'wr a; wr b;'
+                for nid in ast.children(parent_nid) {
+                    let sec_name_str = ast.get_tinfo(nid).val;
+                    result &= self.record_section_write(sec_name_str, tinfo, diags, ast, ast_db);
+                }
+            }
+            LexToken::Endian => {
+                // The byte order identifier is the only operand
+                let mut lops = Vec::new();
+                let ir_lid = self.new_ir(parent_nid, ast, IRKind::Endian);
+                result &= self.record_children_r(rdepth + 1, parent_nid,
+                                        &mut lops, diags, ast, ast_db);
+                result &= self.process_operands(1, &mut lops, ir_lid, diags, tinfo);
             }
             LexToken::Sizeof => {
                 // A vector to track the operands of this expression.
                 let mut lops = Vec::new();
                 // Get the size of the section.  Section name is an identifier operand.
                 let ir_lid = self.new_ir(parent_nid, ast, IRKind::Sizeof);
-                // There is child, which is the identifier
+                // There is a section identifier child, or two identifiers
+                // for the distance from the first to the second
                 result &= self.record_children_r(rdepth + 1, parent_nid,
                                         &mut lops, diags, ast, ast_db);
-                // 1 operand expected
-                result &= self.process_operands(1, &mut lops, ir_lid, diags, tinfo);
+                let expected = if lops.len() == 2 { 2 } else { 1 };
+                result &= self.process_operands(expected, &mut lops, ir_lid, diags, tinfo);
 
                 // Add a destination operand to the operation to hold the result
                 let idx = self.add_new_operand_to_ir(ir_lid, LinOperand::new(
@@ -271,6 +539,19 @@ impl<'toks> LinearDb {
                 // The destination operand is presumably an input operand in the parent.
                 returned_operands.push(idx);
             }
+            LexToken::Crc32 => {
+                // The section identifier is the only input operand
+                let mut lops = Vec::new();
+                let ir_lid = self.new_ir(parent_nid, ast, IRKind::Crc32);
+                result &= self.record_children_r(rdepth + 1, parent_nid,
+                                        &mut lops, diags, ast, ast_db);
+                result &= self.process_operands(1, &mut lops, ir_lid, diags, tinfo);
+
+                // Add a destination operand to hold the CRC
+                let idx = self.add_new_operand_to_ir(ir_lid, LinOperand::new(
+                        Some(ir_lid), tinfo));
+                returned_operands.push(idx);
+            }
             LexToken::Abs |
             LexToken::Img |
             LexToken::Sec => {
@@ -293,6 +574,54 @@ impl<'toks> LinearDb {
                 // The destination operand is presumably an input operand in the parent.
                 returned_operands.push(idx);
             }
+            LexToken::Identifier if self.const_ref(parent_nid, ast, ast_db).is_some() => {
+                // A reference to a named constant records the constant's
+                // expression in place of the name.
+                let cnst = self.const_ref(parent_nid, ast, ast_db).unwrap();
+                if tinfo.loc.start < cnst.end {
+                    let msg = format!("Constant '{}' is used before its definition", tinfo.val);
+                    diags.err2("LINEAR_12", &msg, tinfo.span(), cnst.loc.clone());
+                    return false;
+                }
+                self.used_consts.insert(tinfo.val.to_string());
+                result &= self.record_r(rdepth + 1, cnst.expr_nid, returned_operands,
+                                        diags, ast, ast_db);
+            }
+            LexToken::If => {
+                // if (c) { a } else { b } ==> if c; a; else; b; end_if;
+                // The engine skips the IR of the block not taken.
+                let mut lops = Vec::new();
+                let mut children = ast.children(parent_nid);
+                // The parser guarantees the condition is the first child
+                let cond_nid = children.next().unwrap();
+                result &= self.record_r(rdepth + 1, cond_nid, &mut lops, diags, ast, ast_db);
+                let ir_lid = self.new_ir(parent_nid, ast, IRKind::If);
+                result &= self.process_operands(1, &mut lops, ir_lid, diags, tinfo);
+
+                // The statements of the block and the optional else.
+                // Statements do not return operands.
+                let mut stmt_lops = Vec::new();
+                for nid in children {
+                    result &= self.record_r(rdepth + 1, nid, &mut stmt_lops, diags, ast, ast_db);
+                }
+                self.new_ir(parent_nid, ast, IRKind::EndIf);
+            }
+            LexToken::Else => {
+                // The else block, or a nested if for 'else if'
+                self.new_ir(parent_nid, ast, IRKind::Else);
+                let mut lops = Vec::new();
+                result &= self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+            }
+            LexToken::Repeat => {
+                result &= self.record_repeat(rdepth, parent_nid, diags, ast, ast_db);
+            }
+            LexToken::Pack32 => {
+                result &= self.record_pack32(rdepth, parent_nid, diags, ast, ast_db);
+            }
+            LexToken::Const => {
+                // Each reference records the constant's expression, so the
+                // definition itself produces no IR.
+            }
             LexToken::Identifier |
             LexToken::U64 |
             LexToken::I64 |
@@ -357,7 +686,33 @@ impl<'toks> LinearDb {
 
             }
 
+            LexToken::RoundupPow2 => {
+                // To implement roundup_pow2, we map to IR as follows:
+                // roundup_pow2(); ==> roundup_pow2 count; wr8 0, count;
+                let ir_lid = self.new_ir(parent_nid, ast, IRKind::RoundupPow2);
+
+                // Add the destination operand to store the calculated count
+                let count_output = self.add_new_operand_to_ir(ir_lid, LinOperand::new(
+                    Some(ir_lid), tinfo));
+
+                // Create a wr8_tinfo copied from the roundup_pow2 tinfo
+                let mut wr8_tinfo = tinfo.clone();
+                wr8_tinfo.tok = LexToken::Wr8;
+                let wr8_lid = self.new_ir(parent_nid, ast, tok_to_irkind(wr8_tinfo.tok));
+
+                // Pad with zero
+                let mut pad_byte_tinfo = tinfo.clone();
+                pad_byte_tinfo.tok = LexToken::Integer;
+                pad_byte_tinfo.val = "0";
+                self.add_new_operand_to_ir(wr8_lid, LinOperand::new(
+                    None, &pad_byte_tinfo));
+
+                // The roundup_pow2 result as the number of bytes to write in wr8
+                self.add_existing_operand_to_ir(wr8_lid, count_output);
+            }
+
             LexToken::Assert |
+            LexToken::At |
             LexToken::Wr8  |
             LexToken::Wr16 |
             LexToken::Wr24 |
@@ -366,8 +721,18 @@ impl<'toks> LinearDb {
             LexToken::Wr48 |
             LexToken::Wr56 |
             LexToken::Wr64 |
+            LexToken::Wr16be |
+            LexToken::Wr24be |
+            LexToken::Wr32be |
+            LexToken::Wr40be |
+            LexToken::Wr48be |
+            LexToken::Wr56be |
+            LexToken::Wr64be |
             LexToken::Wrs |
+            LexToken::Header |
             LexToken::Wrf |
+            LexToken::Fill |
+            LexToken::Printf |
             LexToken::Print => {
                 // A vector to track the operands of this expression.
                 let mut lops = Vec::new();
@@ -380,6 +745,13 @@ impl<'toks> LinearDb {
                     self.add_existing_operand_to_ir(ir_lid, idx);
                 }
             }
+            LexToken::IsPow2 |
+            LexToken::Negate |
+            LexToken::Tilde |
+            LexToken::Bang |
+            LexToken::AbsValue |
+            LexToken::CountOnes |
+            LexToken::CountZeros |
             LexToken::ToI64 |
             LexToken::ToU64 => {
                 // A vector to track the operands of this expression.
@@ -395,9 +767,55 @@ impl<'toks> LinearDb {
                 // The destination operand is presumably an input operand in the parent.
                 returned_operands.push(idx);
             }
+            LexToken::Clamp => {
+                // A vector to track the operands of this expression.
+                let mut lops = Vec::new();
+                result &= self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+                let ir_lid = self.new_ir(parent_nid, ast, tok_to_irkind(tinfo.tok));
+                // value, lo and hi operands expected
+                result &= self.process_operands(3, &mut lops, ir_lid, diags, tinfo);
+                // Add a destination operand to the operation to hold the result
+                let idx = self.add_new_operand_to_ir(ir_lid, LinOperand::new(
+                    Some(ir_lid), tinfo));
+                returned_operands.push(idx);
+            }
+            LexToken::AlignUp |
+            LexToken::AlignDown |
+            LexToken::BitReverse |
+            LexToken::Pow |
+            LexToken::Min |
+            LexToken::Max => {
+                // A vector to track the operands of this expression.
+                let mut lops = Vec::new();
+                result &= self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+                let ir_lid = self.new_ir(parent_nid, ast, tok_to_irkind(tinfo.tok));
+                // two value operands expected for all of these
+                result &= self.process_operands(2, &mut lops, ir_lid, diags, tinfo);
+                // Add a destination operand to the operation to hold the result
+                let idx = self.add_new_operand_to_ir(ir_lid, LinOperand::new(
+                    Some(ir_lid), tinfo));
+                returned_operands.push(idx);
+            }
+            LexToken::Join => {
+                // A vector to track the operands of this expression.
+                let mut lops = Vec::new();
+                result &= self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+                let ir_lid = self.new_ir(parent_nid, ast, tok_to_irkind(tinfo.tok));
+                // The separator and any number of values.  The parser
+                // already required at least one value.
+                for idx in lops {
+                    self.add_existing_operand_to_ir(ir_lid, idx);
+                }
+                // Add a destination operand to the operation to hold the result
+                let idx = self.add_new_operand_to_ir(ir_lid, LinOperand::new(
+                    Some(ir_lid), tinfo));
+                returned_operands.push(idx);
+            }
             LexToken::NEq |
             LexToken::LEq |
             LexToken::GEq |
+            LexToken::Less |
+            LexToken::Greater |
             LexToken::DoubleEq |
             LexToken::DoubleGreater |
             LexToken::DoubleLess |
@@ -428,18 +846,117 @@ impl<'toks> LinearDb {
             LexToken::Section => {
                 // Record the linear start of this section.
                 let mut lops = Vec::new();
+                let mut base_lops = Vec::new();
+                let mut ensure_nonempty = false;
                 let start_lid = self.new_ir(parent_nid, ast, IRKind::SectionStart);
-                result &= self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+                for nid in ast.children(parent_nid) {
+                    let child_tinfo = ast.get_tinfo(nid);
+                    if child_tinfo.tok == LexToken::Attribute {
+                        match child_tinfo.val {
+                            "@ensure_nonempty" => { ensure_nonempty = true; }
+                            "@once" => {
+                                let sec_name_str = ast.get_child_str(parent_nid, 0).unwrap();
+                                self.once_sections.insert(sec_name_str.to_string());
+                            }
+                            _ => {
+                                // The @base value becomes an optional operand
+                                // of the section start.
+                                result &= self.record_children_r(rdepth + 1, nid, &mut base_lops,
+                                                                 diags, ast, ast_db);
+                            }
+                        }
+                    } else {
+                        result &= self.record_r(rdepth + 1, nid, &mut lops, diags, ast, ast_db);
+                    }
+                }
+                // Check the section size just before the section ends
+                let ensure_lid = if ensure_nonempty {
+                    Some(self.new_ir(parent_nid, ast, IRKind::EnsureNonempty))
+                } else {
+                    None
+                };
                 let end_lid = self.new_ir(parent_nid, ast, IRKind::SectionEnd);
                 // 1 operand expected, which is the name of the section.
                 if self.operand_count_is_valid(1, &lops, diags, tinfo) {
                     let sec_id_lid = lops.pop().unwrap();
                     self.add_existing_operand_to_ir(start_lid, sec_id_lid);
+                    if let Some(ensure_lid) = ensure_lid {
+                        self.add_existing_operand_to_ir(ensure_lid, sec_id_lid);
+                    }
                     self.add_existing_operand_to_ir(end_lid, sec_id_lid);
+                    // section start: name [, base]
+                    if let Some(&base_lid) = base_lops.first() {
+                        self.add_existing_operand_to_ir(start_lid, base_lid);
+                    }
                 } else {
                     result = false;
                 }
             }
+            LexToken::Tee => {
+                // tee expr [, expr]; ==> print expr [, expr]; wrs expr [, expr];
+                // Both IRs share the same operands.
+                let mut lops = Vec::new();
+                result &= self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+                let print_lid = self.new_ir(parent_nid, ast, IRKind::Print);
+                let wrs_lid = self.new_ir(parent_nid, ast, IRKind::Wrs);
+                for idx in lops {
+                    self.add_existing_operand_to_ir(print_lid, idx);
+                    self.add_existing_operand_to_ir(wrs_lid, idx);
+                }
+            }
+            LexToken::AssertIfWritten => {
+                // assert_if_written(sec, expr); ==> assert_if_written expr, sec;
+                // The engine skips the assert unless a write of the section
+                // is in a block taken.  A section without any write in the
+                // source can never be written, so the statement produces
+                // no IR at all.
+                let sec_name_str = ast.get_child_str(parent_nid, 0).unwrap();
+                if ast_db.written_sections.contains(sec_name_str) {
+                    let mut lops = Vec::new();
+                    let mut children = ast.children(parent_nid);
+                    let sec_nid = children.next().unwrap();
+                    // Record the expression, then the section name
+                    for nid in children {
+                        result &= self.record_r(rdepth + 1, nid, &mut lops, diags, ast, ast_db);
+                    }
+                    let mut sec_lops = Vec::new();
+                    result &= self.record_r(rdepth + 1, sec_nid, &mut sec_lops, diags, ast, ast_db);
+                    let ir_lid = self.new_ir(parent_nid, ast, IRKind::AssertIfWritten);
+                    for idx in lops.into_iter().chain(sec_lops) {
+                        self.add_existing_operand_to_ir(ir_lid, idx);
+                    }
+                } else {
+                    debug!("LinearDb::record_r: skipping assert for unwritten section {}", sec_name_str);
+                }
+            }
+            LexToken::AssertInRange => {
+                // assert_in_range(v, lo, hi); ==> t0 = v >= lo; t1 = v <= hi;
+                // t2 = t0 && t1; assert_in_range t2, v, lo, hi;
+                // The extra operands let the engine report all three values.
+                let mut lops = Vec::new();
+                result &= self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+                if !self.operand_count_is_valid(3, &lops, diags, tinfo) {
+                    return false;
+                }
+                let ge = self.new_binary_ir(parent_nid, ast, tinfo, LexToken::GEq, lops[0], lops[1]);
+                let le = self.new_binary_ir(parent_nid, ast, tinfo, LexToken::LEq, lops[0], lops[2]);
+                let both = self.new_binary_ir(parent_nid, ast, tinfo, LexToken::DoubleAmpersand, ge, le);
+                let ir_lid = self.new_ir(parent_nid, ast, IRKind::AssertInRange);
+                self.add_existing_operand_to_ir(ir_lid, both);
+                for idx in lops {
+                    self.add_existing_operand_to_ir(ir_lid, idx);
+                }
+            }
+            LexToken::Wrbytes => {
+                // wrbytes [a, b, c]; ==> wrbyte a; wrbyte b; wrbyte c;
+                // Each wrbyte is a wr8 that verifies the value fits in a byte.
+                let mut lops = Vec::new();
+                result &= self.record_children_r(rdepth + 1, parent_nid, &mut lops, diags, ast, ast_db);
+                for idx in lops {
+                    let ir_lid = self.new_ir(parent_nid, ast, IRKind::Wrbyte);
+                    self.add_existing_operand_to_ir(ir_lid, idx);
+                }
+            }
             LexToken::Label => {
                 // A label marking an addressable location in the output.
                 // Labels have no children in the AST since they are their own identifier.
@@ -457,17 +974,24 @@ impl<'toks> LinearDb {
 
             LexToken::Semicolon |
             LexToken::Comma |
+            LexToken::Colon |
             LexToken::OpenParen |
             LexToken::CloseParen |
+            LexToken::OpenBracket |
+            LexToken::CloseBracket |
             LexToken::OpenBrace |
             LexToken::CloseBrace => {
                 // Uninteresting syntactical elements that do not appear in the IR.
             }
             LexToken::Unknown => {
                 let m = "Unexpected character.";
-                diags.err1("LINEAR_3", &m, tinfo.span());
+                diags.err1("LINEAR_3", m, tinfo.span());
                 result = false;
             }
+            LexToken::Include |
+            LexToken::IncludeOnce |
+            LexToken::Attribute |
+            LexToken::DoublePlus |
             LexToken::Output => {
                 let m = format!("Unexpected '{}' expression not allowed here.", tinfo.val);
                 diags.err1("LINEAR_4", &m, tinfo.span());
@@ -483,8 +1007,10 @@ impl<'toks> LinearDb {
     /// The LinearDb object must start with an output statement.
     /// If the output doesn't exist, then return None.  The linear_db
     /// records only elements with size > 0.
+    /// max_irs: Bounds the operations that repeat blocks expand to.  None
+    /// is the default bound.
     pub fn new(diags: &mut Diags, ast: &'toks Ast,
-               ast_db: &'toks AstDb) -> Option<LinearDb> {
+               ast_db: &'toks AstDb, max_irs: Option<usize>) -> Option<LinearDb> {
         debug!("LinearDb::new: ENTER");
 
         // AstDb already validated output exists
@@ -498,20 +1024,31 @@ impl<'toks> LinearDb {
         let mut output_addr_str = None;
         let mut output_addr_loc = None;
 
-        if output_addr_nid.is_some() {
-            let output_addr_tinfo = ast.get_tinfo(ast_db.output.addr_nid.unwrap());
-            if [LexToken::U64, LexToken::Integer].contains(&output_addr_tinfo.tok) {
-                output_addr_str = Some(output_addr_tinfo.val.to_string());
-                output_addr_loc = Some(output_addr_tinfo.loc.clone());
-                debug!("LinearDb::new: Output address is {}", output_addr_str.as_ref().unwrap());
-            } else {
-                // If not a u64, then trailing semicolon
-                assert!(output_addr_tinfo.tok == LexToken::Semicolon);
-            }
+        if let Some(output_addr_nid) = output_addr_nid {
+            let output_addr_tinfo = ast.get_tinfo(output_addr_nid);
+            output_addr_str = Some(output_addr_tinfo.val.to_string());
+            output_addr_loc = Some(output_addr_tinfo.loc.clone());
+            debug!("LinearDb::new: Output address is {}", output_addr_str.as_ref().unwrap());
+        }
+
+        let mut output_align_str = None;
+        let mut output_align_loc = None;
+        if let Some(output_align_nid) = ast_db.output.align_nid {
+            let output_align_tinfo = ast.get_tinfo(output_align_nid);
+            output_align_str = Some(output_align_tinfo.val.to_string());
+            output_align_loc = Some(output_align_tinfo.loc.clone());
+            debug!("LinearDb::new: Output align is {}", output_align_str.as_ref().unwrap());
         }
 
         let mut linear_db = LinearDb { ir_vec: Vec::new(), operand_vec: Vec::new(),
-                    output_sec_str, output_sec_loc, output_addr_str, output_addr_loc };
+                    output_sec_str, output_sec_loc, output_addr_str, output_addr_loc,
+                    output_align_str, output_align_loc, once_sections: HashSet::new(),
+                    section_locs: HashMap::new(), used_consts: HashSet::new(),
+                    max_irs: max_irs.unwrap_or(LinearDb::DEFAULT_MAX_IRS) };
+        for (name, section) in &ast_db.sections {
+            let name_nid = ast.children(section.nid).next().unwrap();
+            linear_db.section_locs.insert(name.to_string(), ast.get_tinfo(name_nid).loc.clone());
+        }
 
         // Using the name of the section, use the AST database to get a reference
         // to the section object.  ast_db processing has already guaranteed
@@ -524,6 +1061,14 @@ impl<'toks> LinearDb {
         // of instructions.  We're not calculating sizes and addresses yet.
         let mut lops = Vec::new();
 
+        // A top-level endian directive sets the byte order before the
+        // output section starts.
+        if let Some(endian_nid) = ast_db.endian {
+            if !linear_db.record_r(1, endian_nid, &mut lops, diags, ast, ast_db) {
+                return None;
+            }
+        }
+
         // If an error occurs, result gets stuck at false.
         if !linear_db.record_r(1, sec_nid, &mut lops,
                             diags, ast, ast_db) {
@@ -545,7 +1090,30 @@ impl<'toks> LinearDb {
         Some(linear_db)
     }
 
+    /// Warns about each constant that the linearized output never
+    /// references, in source order.  Constants used only in sections that
+    /// are never written count as unused.
+    pub fn check_unused_consts(&self, ast_db: &AstDb, diags: &mut Diags) {
+        let mut unused: Vec<(&str, &Const)> = ast_db.consts.iter()
+                .filter(|(name, _)| !self.used_consts.contains(**name))
+                .map(|(name, cnst)| (*name, cnst))
+                .collect();
+        unused.sort_by_key(|(_, cnst)| cnst.loc.start);
+        for (name, cnst) in unused {
+            let msg = format!("Constant '{}' is never used", name);
+            diags.warn1("LINEAR_19", &msg, cnst.loc.clone());
+        }
+    }
+
     pub fn dump(&self) {
+        for line in self.dump_text().lines() {
+            debug!("LinearDb: {}", line);
+        }
+    }
+
+    /// Returns the linear IR as text with one operation per line.
+    pub fn dump_text(&self) -> String {
+        let mut out = String::new();
         for (idx,ir) in self.ir_vec.iter().enumerate() {
             let mut op = format!("lid {}: nid {} is {:?}", idx, ir.nid, ir.op);
             // display the operand for this LinIR
@@ -553,7 +1121,7 @@ impl<'toks> LinearDb {
             for child in &ir.operand_vec {
                 let operand = &self.operand_vec[*child];
                 if !first {
-                    op.push_str(",");
+                    op.push(',');
                 } else {
                     first = false;
                 }
@@ -564,8 +1132,10 @@ impl<'toks> LinearDb {
                 }
                 //op.push_str(&format!(" temp_{}", operand.val));
             }
-            debug!("LinearDb: {}", op);
+            out.push_str(&op);
+            out.push('\n');
         }
+        out
     }
 }
 
@@ -739,7 +1309,7 @@ impl IdentDb {
             }
 
         debug!("IdentDb::inventory_identifiers:");
-        for (name, _) in &self.label_idents {
+        for name in self.label_idents.keys() {
             debug!("    {}", name);
         }
 
@@ -754,7 +1324,8 @@ impl IdentDb {
             result &= match lir.op {
                 IRKind::Abs |
                 IRKind::Img |
-                IRKind::Sizeof => {
+                IRKind::Sizeof |
+                IRKind::Crc32 => {
                     self.verify_operand_refs(lir, lindb, diags)
                 }
                 _ => { true }
@@ -804,21 +1375,33 @@ impl IdentDb {
                     continue;
                 }
                 if self.is_valid_label_ref(lop) {
-                    // labels have no size, so verify the linear operation is not a sizeof()
-                    match lir.op {
-                        IRKind::Sizeof => {
-                            let msg = format!("Sizeof cannot refer to a label name.  Labels have no size.");
-                            diags.err1("LINEAR_9", &msg, lop.src_loc.clone());
-                            // keep processing after error to report other problems
-                            result = false;
-                        }
-
-                        _ => { }
+                    // labels have no size, so verify the linear operation is not a
+                    // sizeof() of a single identifier, which has one input operand
+                    // and one output operand.
+                    if lir.op == IRKind::Sizeof && lir.operand_vec.len() == 2 {
+                        let msg = "Sizeof cannot refer to a label name.  Labels have no size.".to_string();
+                        diags.err1("LINEAR_9", &msg, lop.src_loc.clone());
+                        // keep processing after error to report other problems
+                        result = false;
+                    }
+                    if lir.op == IRKind::Crc32 {
+                        let msg = "Crc32 cannot refer to a label name.  Labels have no size.".to_string();
+                        diags.err1("LINEAR_11", &msg, lop.src_loc.clone());
+                        result = false;
                     }
                     continue;
                 }
 
-                let msg = format!("Unknown or unreachable identifier {}", lop.sval);
+                let mut msg = format!("Unknown or unreachable identifier {}", lop.sval);
+                // Suggest the nearest label or unambiguous section name
+                let candidates = self.label_idents.keys()
+                        .chain(self.section_count.iter()
+                                .filter(|(_, &count)| count == 1)
+                                .map(|(name, _)| name))
+                        .map(|name| name.as_str());
+                if let Some(nearest) = suggest_name(&lop.sval, candidates) {
+                    msg.push_str(&format!(".  Did you mean '{}'?", nearest));
+                }
                 diags.err1("LINEAR_6", &msg, lop.src_loc.clone());
                 // keep processing after error to report other problems
                 result = false;
@@ -0,0 +1,63 @@
+// C array encoding of the output image
+use std::io::{self, Write};
+
+/// Encodes the bytes written to it as a C array definition on the wrapped
+/// writer, e.g. "const unsigned char foo[] = { ... };".  Call finish() to
+/// close the array and write the length macro, which is the upper case
+/// array name with a _LEN suffix.
+pub struct CArrayWriter<W: Write> {
+    out: W,
+    name: String,
+    /// Total bytes written so far
+    len: usize,
+    /// Bytes not yet written as a line of the array
+    pending: Vec<u8>,
+    /// Number of bytes on each line of the array
+    line_len: usize,
+}
+
+impl<W: Write> CArrayWriter<W> {
+    /// Creates the writer and writes the start of the array definition.
+    /// elem_type: The C type of each array element, e.g. "const unsigned char"
+    pub fn new(mut out: W, name: &str, elem_type: &str,
+               line_len: usize) -> io::Result<CArrayWriter<W>> {
+        writeln!(out, "{} {}[] = {{", elem_type, name)?;
+        Ok(CArrayWriter { out, name: name.to_string(), len: 0, pending: Vec::new(), line_len })
+    }
+
+    /// Writes one indented line of comma separated hex byte values
+    fn write_line(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let line: Vec<String> = bytes.iter().map(|b| format!("0x{:02X},", b)).collect();
+        writeln!(self.out, "    {}", line.join(" "))
+    }
+
+    /// Writes any remaining bytes, the end of the array and the length
+    /// macro, then returns the wrapped writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let pending = std::mem::take(&mut self.pending);
+        if !pending.is_empty() {
+            self.write_line(&pending)?;
+        }
+        writeln!(self.out, "}};")?;
+        writeln!(self.out, "#define {}_LEN {}", self.name.to_uppercase(), self.len)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for CArrayWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.len += buf.len();
+        let full_len = self.pending.len() / self.line_len * self.line_len;
+        let full: Vec<u8> = self.pending.drain(..full_len).collect();
+        for line in full.chunks(self.line_len) {
+            self.write_line(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
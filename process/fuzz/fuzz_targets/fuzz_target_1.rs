@@ -1,13 +1,12 @@
 #![no_main]
 use libfuzzer_sys::fuzz_target;
-use process::process;
-use clap::App;
+use process::{FuzzConfig,process_fuzz};
 
+// The fuzzer calls this function repeatedly
 fuzz_target!(|data: &[u8]| {
     if let Ok(str_in) = std::str::from_utf8(data) {
-        // Get matches from a fake arg string, since we don't
-        // want to process the fuzz testers actually command line!
-        let args = App::new("brink").get_matches_from( vec![""]);
-        let _result = process("!! FUZZ TEST !!", str_in, &args, 0, false);
+        // The default config bounds memory and time and prevents
+        // any file I/O during the test.
+        let _ = process_fuzz(str_in, &FuzzConfig::default());
     }
 });
@@ -0,0 +1,24 @@
+// xxd style hex dump of the output image
+use std::io::{self, Write};
+
+/// Writes an xxd style dump of buf with line_len bytes per line.  Each
+/// line has the absolute address of its first byte, the bytes as hex in
+/// groups of two, then the bytes as ASCII with '.' for any unprintable byte.
+/// base_addr: The absolute address of the first byte in buf
+pub fn hexdump<W: Write>(out: &mut W, buf: &[u8], base_addr: u64,
+                         line_len: usize) -> io::Result<()> {
+    // A full line of hex is groups of 4 digits separated by spaces
+    let hex_width = line_len * 2 + line_len.div_ceil(2) - 1;
+    for (line_num, line) in buf.chunks(line_len).enumerate() {
+        let addr = base_addr + (line_num * line_len) as u64;
+        let hex: Vec<String> = line.chunks(2)
+                                   .map(|pair| pair.iter().map(|b| format!("{:02x}", b))
+                                                          .collect())
+                                   .collect();
+        let ascii: String = line.iter()
+                                .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' })
+                                .collect();
+        writeln!(out, "{:08x}: {:<hex_width$}  {}", addr, hex.join(" "), ascii)?;
+    }
+    out.flush()
+}
@@ -0,0 +1,94 @@
+// Intel HEX encoding of the output image
+use std::io::{self, Write};
+
+/// Number of data bytes in each full data record
+const RECORD_LEN: usize = 16;
+
+/// Intel HEX addresses are 32 bits
+const MAX_ADDR: u64 = 0x1_0000_0000;
+
+/// Encodes the bytes written to it as Intel HEX records on the wrapped
+/// writer.  Data records start at the base address and advance with each
+/// byte.  Extended linear address records precede any data above 64KB.
+/// Call finish() to write the end of file record.
+pub struct IHexWriter<W: Write> {
+    out: W,
+    /// Absolute address of the first byte in pending
+    addr: u64,
+    /// Bytes not yet written as a data record
+    pending: Vec<u8>,
+    /// Upper 16 address bits of the last extended linear address record
+    upper: u64,
+}
+
+impl<W: Write> IHexWriter<W> {
+    pub fn new(out: W, base_addr: u64) -> IHexWriter<W> {
+        IHexWriter { out, addr: base_addr, pending: Vec::new(), upper: 0 }
+    }
+
+    /// Writes one record with the byte count, address, record type and
+    /// checksum fields.  The checksum is the two's complement of the sum of
+    /// all other record bytes.
+    fn write_record(&mut self, addr: u16, rec_type: u8, data: &[u8]) -> io::Result<()> {
+        let mut rec = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, rec_type];
+        rec.extend_from_slice(data);
+        let sum = rec.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        rec.push(sum.wrapping_neg());
+
+        let hex: String = rec.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(self.out, ":{}", hex)
+    }
+
+    /// Writes data records for the pending bytes.  Unless flushing all
+    /// bytes, leaves a partial record pending for the next write.  Records
+    /// never cross a 64KB boundary.
+    fn write_pending(&mut self, all: bool) -> io::Result<()> {
+        let mut start = 0;
+        while start < self.pending.len() {
+            let seg_left = 0x10000 - (self.addr & 0xFFFF) as usize;
+            let len = RECORD_LEN.min(seg_left).min(self.pending.len() - start);
+            if len < RECORD_LEN && len < seg_left && !all {
+                break;
+            }
+
+            let upper = self.addr >> 16;
+            if upper != self.upper {
+                self.write_record(0, 4, &(upper as u16).to_be_bytes())?;
+                self.upper = upper;
+            }
+            let data = self.pending[start..start + len].to_vec();
+            self.write_record(self.addr as u16, 0, &data)?;
+            self.addr += len as u64;
+            start += len;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    /// Writes any remaining data and the end of file record, then returns
+    /// the wrapped writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_pending(true)?;
+        self.write_record(0, 1, &[])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for IHexWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.addr + (self.pending.len() + buf.len()) as u64;
+        if end > MAX_ADDR {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Intel HEX output ends at address {:#X}, beyond the 32-bit \
+                             address limit", end)));
+        }
+        self.pending.extend_from_slice(buf);
+        self.write_pending(false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -1,80 +1,805 @@
-use std::fs::File;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::fs::{self,File,OpenOptions};
+use std::io::{BufWriter,Write};
+use std::path::Path;
 use anyhow::{Result,Context,anyhow};
+use parse_int::parse;
 extern crate clap;
 
 // Local libraries
 use diags::Diags;
 use ast::{Ast,AstDb};
 use lineardb::LinearDb;
+use ir::IRKind;
 use irdb::IRDb;
-use engine::Engine;
+use engine::{Engine,EngineOptions};
+
+mod carray;
+mod hexdump;
+mod ihex;
+mod srec;
+use carray::CArrayWriter;
+use hexdump::hexdump;
+use ihex::IHexWriter;
+use srec::SRecWriter;
 
 #[allow(unused_imports)]
 use log::{error, warn, info, debug, trace};
 
+/// Number of bytes on each line of the text output formats
+const DEFAULT_BYTES_PER_LINE: usize = 16;
+
+/// Reads a character encoding table for wrs.  Each non-empty line of the
+/// file maps one character to a byte value.  The first character of the
+/// line is the mapped character, and the rest of the line is the byte value,
+/// e.g. "A 0x01".
+fn read_encoding_table(table_name: &str) -> Result<HashMap<char, u8>> {
+    let table_str = fs::read_to_string(table_name)
+            .context(format!("Unable to read encoding table {}", table_name))?;
+
+    let mut table = HashMap::new();
+    for (line_num, line) in table_str.lines().enumerate() {
+        let mut chars = line.chars();
+        let ch = match chars.next() {
+            Some(ch) => ch,
+            None => { continue; } // skip empty lines
+        };
+        let sval = chars.as_str().trim();
+        let byte = parse::<u8>(sval)
+                .map_err(|_| anyhow!("[PROC_10]: Invalid byte value '{}' for character {:?} \
+                                      at line {} of encoding table {}", sval, ch,
+                                      line_num + 1, table_name))?;
+        if table.insert(ch, byte).is_some() {
+            return Err(anyhow!("[PROC_11]: Duplicate character {:?} at line {} of \
+                                encoding table {}", ch, line_num + 1, table_name));
+        }
+    }
+    Ok(table)
+}
+
+/// Returns a hash of everything that affects the output: the preprocessed
+/// source, the contents of written files, and output-affecting options.
+/// Options that change the output must be added here.
+fn compute_cache_key(fstr: &str, ir_db: &IRDb,
+                     encoding_table: &Option<HashMap<char, u8>>,
+                     deterministic: bool, round_to: Option<u64>,
+                     info: &ImageInfo<'_>) -> Result<String> {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    fstr.hash(&mut hasher);
+    ir_db.relax_types.hash(&mut hasher);
+    deterministic.hash(&mut hasher);
+    round_to.hash(&mut hasher);
+    info.format.hash(&mut hasher);
+    if info.format == "carray" {
+        info.array_name.hash(&mut hasher);
+        info.array_type.hash(&mut hasher);
+        info.bytes_per_line.hash(&mut hasher);
+    }
+
+    // Sort for a stable hash
+    let mut paths: Vec<&String> = ir_db.files.keys().collect();
+    paths.sort();
+    for path in paths {
+        let full_path = &ir_db.files[path].path;
+        let contents = fs::read(full_path)
+                .context(format!("Unable to read file {} for the cache", full_path))?;
+        path.hash(&mut hasher);
+        contents.hash(&mut hasher);
+    }
+
+    if let Some(table) = encoding_table {
+        let mut entries: Vec<(&char, &u8)> = table.iter().collect();
+        entries.sort();
+        entries.hash(&mut hasher);
+    }
+
+    let mut defines: Vec<(&String, &String)> = ir_db.defines.iter().collect();
+    defines.sort();
+    defines.hash(&mut hasher);
+
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Returns a side-by-side hex dump of the output and expected bytes in
+/// the rows around the offset.  Rows with a difference are marked '*'
+/// and bytes past the end of either buffer are blank.
+fn verify_window(buf: &[u8], expected: &[u8], offset: usize) -> String {
+    const ROW_LEN: usize = 8;
+    const CONTEXT_ROWS: usize = 1;
+
+    let max_len = buf.len().max(expected.len());
+    let first_row = (offset / ROW_LEN).saturating_sub(CONTEXT_ROWS);
+    let last_row = (offset / ROW_LEN + CONTEXT_ROWS).min((max_len - 1) / ROW_LEN);
+
+    let hex_row = |bytes: &[u8], start: usize| -> String {
+        (start..start + ROW_LEN)
+            .map(|i| bytes.get(i).map_or("  ".to_string(), |b| format!("{:02X}", b)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let mut window = format!("Bytes around the first difference:\n  {:<10}  {:<23}   {}",
+                             "Offset", "Output", "Expected");
+    for row in first_row..=last_row {
+        let start = row * ROW_LEN;
+        let differs = (start..start + ROW_LEN).any(|i| buf.get(i) != expected.get(i));
+        window.push_str(&format!("\n{} {:#010X}  {}   {}", if differs { '*' } else { ' ' },
+                                 start, hex_row(buf, start), hex_row(expected, start)));
+    }
+    window
+}
+
+/// Compares the output buffer byte-for-byte against the expected file
+/// and reports the first difference.
+fn verify_output(buf: &[u8], expected_name: &str, diags: &Diags) -> Result<()> {
+    let expected = fs::read(expected_name)
+            .context(format!("Unable to read verify file {}", expected_name))?;
+
+    let mismatch = buf.iter().zip(expected.iter()).position(|(out, exp)| out != exp);
+    if let Some(offset) = mismatch {
+        let msg = format!("Output differs from {} at offset {:#X}: output byte {:#04X}, \
+                           expected byte {:#04X}", expected_name, offset, buf[offset],
+                           expected[offset]);
+        diags.err0("PROC_17", &msg);
+        diags.note0("PROC_13", &verify_window(buf, &expected, offset));
+        return Err(anyhow!("[PROC_17]: Verify failed."));
+    }
+
+    if buf.len() != expected.len() {
+        let msg = format!("Output size {} differs from {} size {}, first difference \
+                           at offset {:#X}", buf.len(), expected_name, expected.len(),
+                           buf.len().min(expected.len()));
+        diags.err0("PROC_18", &msg);
+        diags.note0("PROC_22", &verify_window(buf, &expected, buf.len().min(expected.len())));
+        return Err(anyhow!("[PROC_18]: Verify failed."));
+    }
+
+    info!("Output matches {}", expected_name);
+    Ok(())
+}
+
+/// Describes the output image for the output format encoders
+struct ImageInfo<'a> {
+    /// The output file format, e.g. "bin" or "ihex"
+    format: &'a str,
+    /// The input file name
+    name: &'a str,
+    /// The absolute address of the first byte of the image
+    start_addr: u64,
+    /// The array name for the carray format
+    array_name: &'a str,
+    /// The array element type for the carray format
+    array_type: &'a str,
+    /// The number of bytes on each line of the carray format
+    bytes_per_line: usize,
+}
+
+/// Writes the output image to out in the output format.
+fn write_image<W: Write>(out: W, buf: &[u8], info: &ImageInfo<'_>) -> Result<()> {
+    match info.format {
+        "ihex" => {
+            let mut writer = IHexWriter::new(BufWriter::new(out), info.start_addr);
+            writer.write_all(buf)?;
+            writer.finish()?;
+        }
+        "srec" => {
+            // The header record holds the input file name without the path
+            let file_name = Path::new(info.name).file_name().unwrap_or_default()
+                                                .to_string_lossy();
+            let end_addr = info.start_addr.saturating_add(buf.len() as u64);
+            let mut writer = SRecWriter::new(BufWriter::new(out), &file_name,
+                                             info.start_addr, end_addr)?;
+            writer.write_all(buf)?;
+            writer.finish()?;
+        }
+        "carray" => {
+            let mut writer = CArrayWriter::new(BufWriter::new(out), info.array_name,
+                                               info.array_type, info.bytes_per_line)?;
+            writer.write_all(buf)?;
+            writer.finish()?;
+        }
+        _ => {
+            let mut out = out;
+            out.write_all(buf)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the bytes of each top level section to <dir>/<name>.bin.
+/// A section written more than once gets a numeric suffix on each
+/// subsequent instance, e.g. <name>_2.bin.
+fn write_split_sections(engine: &Engine, ir_db: &IRDb, buf: &[u8], dir: &str) -> Result<()> {
+    fs::create_dir_all(dir)
+            .context(format!("Unable to create split sections directory {}", dir))?;
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for (name, rng) in engine.top_level_sections(ir_db) {
+        let count = counts.entry(name.clone()).or_insert(0);
+        *count += 1;
+        let fname = if *count == 1 {
+            format!("{}.bin", name)
+        } else {
+            format!("{}_{}.bin", name, count)
+        };
+        let path = Path::new(dir).join(fname);
+        fs::write(&path, &buf[rng])
+                .context(format!("Unable to write split section file {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Resource bounds for assembling untrusted source, e.g. from a fuzzer.
+/// Recursion depth is already bounded by fixed limits in AstDb and
+/// LinearDb.  There is no bound on the number of errors, since fuzzing
+/// prints no diagnostics and the pipeline halts after any stage that
+/// reports an error, so the source length already bounds the errors.
+pub struct FuzzConfig {
+    /// Maximum source length in bytes, which also bounds string length
+    pub max_source_len: usize,
+    /// Maximum size of the output image in bytes
+    pub max_image_size: u64,
+    /// Maximum number of fixed point iterations in the engine
+    pub max_iterations: usize,
+    /// Maximum number of operations, which bounds repeat expansion
+    pub max_irs: usize,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        Self {
+            max_source_len: 64 * 1024,
+            max_image_size: 1024 * 1024,
+            max_iterations: 100,
+            max_irs: 100_000,
+        }
+    }
+}
+
+/// Since the fuzzer must not touch the file system, stops the pipeline
+/// at any file write before IRDb opens the file.
+struct FuzzHooks;
+
+impl StageHooks for FuzzHooks {
+    fn after_linear(&mut self, linear_db: &LinearDb, _diags: &mut Diags<'_>) -> Result<bool> {
+        Ok(!linear_db.ir_vec.iter().any(|lir| lir.op == IRKind::Wrf))
+    }
+}
+
+/// Assembles the source in memory within the bounds of the config and
+/// returns the output image, or None on any error.  Nothing is printed.
+/// Since the fuzzer must not touch the file system, include directives
+/// and file writes are rejected.
+pub fn process_fuzz(fstr: &str, config: &FuzzConfig) -> Option<Vec<u8>> {
+    if fstr.len() > config.max_source_len {
+        return None;
+    }
+
+    let opts = Options {
+        noprint: true,
+        deterministic: true,
+        no_truncation_warnings: true,
+        max_irs: Some(config.max_irs),
+        max_image_size: Some(config.max_image_size),
+        max_iterations: Some(config.max_iterations),
+        ..Options::default()
+    };
+
+    // Skip include preprocessing, so any include is a syntax error
+    let mut diags = Diags::new("fuzz", fstr, 0, true);
+    let assembly = run_pipeline(fstr, Path::new(""), &opts, &mut FuzzHooks, &mut diags).ok()??;
+    assembly.exec_ok.then_some(assembly.image)
+}
+
+/// Options for assembling source in memory with assemble()
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Diagnostic verbosity.  Zero prints no diagnostics.
+    pub verbosity: u64,
+    /// Suppress print statements
+    pub noprint: bool,
+    /// Allow mixed signed and unsigned operands, same as --relax-types
+    pub relax_types: bool,
+    /// Omit the build date from header blocks so the output is reproducible
+    pub deterministic: bool,
+    /// Custom character to byte mapping for strings written with wrs
+    pub encoding_table: Option<HashMap<char, u8>>,
+    /// Symbols visible to the source, same as -D name=value
+    pub defines: HashMap<String, String>,
+    /// Require each section to be defined before any write of it, same
+    /// as --strict-section-order
+    pub strict_section_order: bool,
+    /// Warn about constants the output never uses, same as --warn-unused-const
+    pub warn_unused_const: bool,
+    /// Warn about any section with a final size larger than this threshold
+    pub warn_section_size: Option<u64>,
+    /// Do not warn when a wrx value does not fit the write width
+    pub no_truncation_warnings: bool,
+    /// Flush print output only at each newline rather than after every
+    /// print statement
+    pub line_buffered: bool,
+    /// Print the changed IR locations after each fixed point iteration
+    pub dump_fixedpoint: bool,
+    /// Fail if repeat blocks expand to more than this many operations.
+    /// None uses a generous default limit.
+    pub max_irs: Option<usize>,
+    /// Fail if the output image would grow larger than this many bytes
+    pub max_image_size: Option<u64>,
+    /// Fail if the fixed point iteration needs more passes than this.
+    /// None uses a generous default limit.
+    pub max_iterations: Option<usize>,
+}
+
+/// The products of running source through the whole pipeline
+struct Assembly {
+    ir_db: IRDb,
+    engine: Engine,
+    /// The name of the output section
+    output_sec: String,
+    /// The output image, or only the bytes written before the first
+    /// error if execution failed
+    image: Vec<u8>,
+    /// True if execution succeeded, so the image is complete
+    exec_ok: bool,
+}
+
+/// Inspection points between the stages of run_pipeline().  Each returns
+/// Ok(false) to stop the pipeline after that stage without an error.
+trait StageHooks {
+    fn after_ast(&mut self, _ast: &Ast, _diags: &mut Diags<'_>) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn after_linear(&mut self, _linear_db: &LinearDb, _diags: &mut Diags<'_>) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn after_ir(&mut self, _ir_db: &IRDb, _linear_db: &LinearDb,
+                _diags: &mut Diags<'_>) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn after_engine(&mut self, _engine: &Engine, _ir_db: &IRDb,
+                    _diags: &mut Diags<'_>) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Runs every stage without inspection
+struct NoHooks;
+
+impl StageHooks for NoHooks {}
+
+/// Runs the preprocessed source through every stage of the pipeline and
+/// executes the result into an in-memory image.  Returns None if a hook
+/// stopped the pipeline early.
+/// base_dir: Relative wrf paths are relative to this directory
+fn run_pipeline(fstr: &str, base_dir: &Path, opts: &Options, hooks: &mut dyn StageHooks,
+                diags: &mut Diags<'_>) -> Result<Option<Assembly>> {
+    let ast = Ast::new(fstr, diags)
+            .ok_or_else(|| anyhow!("[PROC_1]: Error detected, halting."))?;
+    if opts.verbosity > 2 {
+        ast.dump("ast.dot")?;
+    }
+    if !hooks.after_ast(&ast, diags)? {
+        return Ok(None);
+    }
+
+    let ast_db = AstDb::new(diags, &ast)?;
+    if opts.strict_section_order && !ast_db.check_section_order(&ast, diags) {
+        return Err(anyhow!("[PROC_1]: Error detected, halting."));
+    }
+    let linear_db = LinearDb::new(diags, &ast, &ast_db, opts.max_irs)
+            .ok_or_else(|| anyhow!("[PROC_2]: Error detected, halting."))?;
+    if opts.warn_unused_const {
+        linear_db.check_unused_consts(&ast_db, diags);
+    }
+    if opts.verbosity > 2 {
+        linear_db.dump();
+    }
+    if !hooks.after_linear(&linear_db, diags)? {
+        return Ok(None);
+    }
+
+    let ir_db = IRDb::new(&linear_db, diags, opts.relax_types, base_dir, &opts.defines)
+            .ok_or_else(|| anyhow!("[PROC_3]: Error detected, halting."))?;
+    debug!("Dumping ir_db");
+    if opts.verbosity > 2 {
+        ir_db.dump();
+    }
+    if !hooks.after_ir(&ir_db, &linear_db, diags)? {
+        return Ok(None);
+    }
+
+    let engine_options = EngineOptions {
+        dump_fixedpoint: opts.dump_fixedpoint,
+        warn_section_size: opts.warn_section_size,
+        encoding_table: opts.encoding_table.clone(),
+        max_image_size: opts.max_image_size,
+        max_iterations: opts.max_iterations,
+        deterministic: opts.deterministic,
+        line_buffered: opts.line_buffered,
+        no_truncation_warnings: opts.no_truncation_warnings,
+    };
+    let engine = Engine::new(&ir_db, diags, 0, engine_options)
+            .ok_or_else(|| anyhow!("[PROC_5]: Error detected, halting."))?;
+    info!("Location counters converged after {} passes", engine.iteration_count());
+    if opts.verbosity > 2 {
+        engine.dump_locations();
+    }
+    if !hooks.after_engine(&engine, &ir_db, diags)? {
+        return Ok(None);
+    }
+
+    // Buffer the output so that the caller decides what to do with a
+    // partial image after an error.
+    let mut image = Vec::new();
+    let exec_ok = engine.execute(&ir_db, diags, &mut image).is_ok();
+    Ok(Some(Assembly { ir_db, engine, output_sec: linear_db.output_sec_str.clone(), image,
+                       exec_ok }))
+}
+
+/// Assembles the source through the full pipeline and returns the output
+/// image instead of writing a file.  Include directives and relative wrf
+/// paths resolve relative to the name, as for a file on the command line.
+/// name: The name of the source, e.g. the file name
+/// fstr: A string containing the source
+pub fn assemble(name: &str, fstr: &str, opts: &Options) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    assemble_to(name, fstr, opts, &mut buf)?;
+    Ok(buf)
+}
+
+/// Same as assemble(), but writes the output image to out, e.g. stdout
+/// or a pipe.  On error, out receives only the output produced before the
+/// first failing operation.
+pub fn assemble_to<W: Write>(name: &str, fstr: &str, opts: &Options, out: &mut W)
+                             -> Result<()> {
+    let pre = ast::preprocess(name, fstr)?;
+    let mut diags = Diags::new(name, fstr, opts.verbosity, opts.noprint);
+    for (inc_name, inc_str) in &pre.files {
+        diags.add_file(inc_name, inc_str);
+    }
+    diags.set_segments(pre.segments.clone());
+    let fstr = &pre.text;
+
+    let base_dir = Path::new(name).parent().unwrap_or_else(|| Path::new(""));
+    // Without hooks, the pipeline always runs to the end
+    let assembly = run_pipeline(fstr, base_dir, opts, &mut NoHooks, &mut diags)?.unwrap();
+    out.write_all(&assembly.image)?;
+    if !assembly.exec_ok {
+        return Err(anyhow!("[PROC_4]: Error detected, halting."));
+    }
+    Ok(())
+}
+
+/// Returns the language reference for --help-lang with one statement or
+/// function per line.
+pub fn lang_help() -> String {
+    let width = ast::LANG_REFERENCE.iter().map(|(syntax, _)| syntax.len()).max().unwrap_or(0);
+    ast::LANG_REFERENCE.iter()
+            .map(|(syntax, desc)| format!("{:<width$}  {}\n", syntax, desc, width = width))
+            .collect()
+}
+
+/// Parses a command line symbol definition of the form name=value.  The
+/// name must be a valid identifier that is not a keyword.
+pub fn parse_define(arg: &str) -> Result<(String, String)> {
+    let (name, value) = arg.split_once('=')
+            .ok_or_else(|| anyhow!("[PROC_14]: Symbol definition '{}' must have the form \
+                                    name=value", arg))?;
+    let name = name.trim();
+    if ast::single_token(name) != Some(ast::LexToken::Identifier) {
+        return Err(anyhow!("[PROC_24]: Symbol name '{}' is not a valid identifier", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
 /// Entry point for all processing on the input source file
 /// name: The name of the file
 /// fstr: A string containing the file
+/// defines: Symbols defined on the command line, see parse_define()
 pub fn process(name: &str, fstr: &str, args: &clap::ArgMatches, verbosity: u64,
-                noprint: bool)
+                noprint: bool, defines: &HashMap<String, String>)
                -> Result<()> {
     info!("Processing {}", name);
     debug!("File contains: {}", fstr);
 
+    // Splice in all included files before parsing
+    let pre = ast::preprocess(name, fstr)?;
+
+    // Diagnostics report locations in the original files
     let mut diags = Diags::new(name,fstr,verbosity,noprint);
+    for (inc_name, inc_str) in &pre.files {
+        diags.add_file(inc_name, inc_str);
+    }
+    diags.set_segments(pre.segments.clone());
+    let fstr = &pre.text;
 
-    let ast = Ast::new(fstr, &mut diags);
-    if ast.is_none() {
-        return Err(anyhow!("[PROC_1]: Error detected, halting."));
+    // With quiet-success, print diagnostics only if processing fails
+    if args.is_present("quiet-success") {
+        diags.defer();
     }
+    let result = process_source(name, fstr, args, verbosity, defines, &mut diags);
+    if result.is_err() {
+        diags.flush_deferred();
+    }
+    result
+}
 
-    let ast = ast.unwrap();
+/// Describes the output image in the output format chosen on the
+/// command line.  The C array name defaults to the output section name.
+fn cli_image_info<'a>(args: &'a clap::ArgMatches, name: &'a str, start_addr: u64,
+                      output_sec: &'a str, bytes_per_line: usize) -> ImageInfo<'a> {
+    ImageInfo {
+        format: args.value_of("format").unwrap_or("bin"),
+        name,
+        start_addr,
+        array_name: args.value_of("array-name").unwrap_or(output_sec),
+        array_type: args.value_of("array-type").unwrap_or("const unsigned char"),
+        bytes_per_line,
+    }
+}
 
-    if verbosity > 2 {
-        ast.dump("ast.dot")?;
+/// The command line dump, stage and inspection options, applied between
+/// the stages of the pipeline
+struct CliHooks<'a> {
+    args: &'a clap::ArgMatches<'a>,
+    name: &'a str,
+    fstr: &'a str,
+    opts: &'a Options,
+    /// Stop after this stage and print that stage's dump
+    stage: &'a str,
+    round_to: Option<u64>,
+    bytes_per_line: usize,
+    fname_str: &'a str,
+    cache_name: &'a str,
+    /// The key to record in the cache file after writing the output
+    cache_key: Option<String>,
+}
+
+impl StageHooks for CliHooks<'_> {
+    fn after_ast(&mut self, ast: &Ast, diags: &mut Diags<'_>) -> Result<bool> {
+        if self.args.is_present("dump-tokens") {
+            ast.dump_tokens(diags);
+        }
+        if self.args.is_present("dump-ast-text") || self.stage == "ast" {
+            ast.dump_text(diags);
+        }
+        Ok(self.stage != "ast")
     }
 
-    let ast_db = AstDb::new(&mut diags, &ast)?;
-    let linear_db = LinearDb::new(&mut diags, &ast, &ast_db);
-    if linear_db.is_none() {
-        return Err(anyhow!("[PROC_2]: Error detected, halting."));
+    fn after_linear(&mut self, linear_db: &LinearDb, _diags: &mut Diags<'_>) -> Result<bool> {
+        if self.stage == "linear" {
+            print!("{}", linear_db.dump_text());
+            return Ok(false);
+        }
+        Ok(true)
     }
-    let linear_db = linear_db.unwrap();
-    if verbosity > 2 {
-        linear_db.dump();
+
+    fn after_ir(&mut self, ir_db: &IRDb, linear_db: &LinearDb,
+                diags: &mut Diags<'_>) -> Result<bool> {
+        if self.args.is_present("dump-operands") {
+            ir_db.dump_operands();
+        }
+
+        if let Some(ir_name) = self.args.value_of("emit-ir") {
+            fs::write(ir_name, ir_db.emit_ir())
+                    .context(format!("Unable to write IR file {}", ir_name))?;
+        }
+
+        if self.stage == "ir" {
+            print!("{}", ir_db.emit_ir());
+            return Ok(false);
+        }
+
+        // With caching, skip assembly if the output already exists and
+        // the inputs are unchanged since the output was written.
+        // The cache only vouches for the combined output file, not split sections
+        // or the internal state file
+        let args = self.args;
+        if args.is_present("cache") && !args.is_present("verify") &&
+           !args.is_present("split-sections") && !args.is_present("emit-internal-state") {
+            let image_info = cli_image_info(args, self.name, ir_db.start_addr,
+                                            &linear_db.output_sec_str, self.bytes_per_line);
+            let key = compute_cache_key(self.fstr, ir_db, &self.opts.encoding_table,
+                                        self.opts.deterministic, self.round_to, &image_info)?;
+            let cached_key = fs::read_to_string(self.cache_name).ok();
+            if fs::metadata(self.fname_str).is_ok() && cached_key.as_deref() == Some(key.as_str()) {
+                let msg = format!("Cache hit, output file {} is up to date.", self.fname_str);
+                diags.note0("PROC_12", &msg);
+                return Ok(false);
+            }
+            self.cache_key = Some(key);
+        }
+        Ok(true)
     }
-    let ir_db = IRDb::new(&linear_db, &mut diags);
-    if ir_db.is_none() {
-        return Err(anyhow!("[PROC_3]: Error detected, halting."));
+
+    fn after_engine(&mut self, engine: &Engine, ir_db: &IRDb,
+                    diags: &mut Diags<'_>) -> Result<bool> {
+        if let Some(state_name) = self.args.value_of("emit-internal-state") {
+            fs::write(state_name.trim(), engine.emit_state(ir_db))
+                    .context(format!("Unable to write internal state file {}", state_name))?;
+        }
+
+        // Listing labels is an inspection command that writes no output
+        if self.args.is_present("list-labels") {
+            engine.list_labels(ir_db, diags);
+            return Ok(false);
+        }
+        Ok(true)
     }
-    let ir_db = ir_db.unwrap();
+}
 
-    debug!("Dumping ir_db");
-    if verbosity > 2 {
-        ir_db.dump();
+/// Processes the preprocessed source through to the output file
+fn process_source(name: &str, fstr: &str, args: &clap::ArgMatches, verbosity: u64,
+                  defines: &HashMap<String, String>,
+                  diags: &mut Diags<'_>) -> Result<()> {
+    let mut warn_section_size = None;
+    if let Some(sval) = args.value_of("warn-section-size") {
+        let threshold = parse::<u64>(sval.trim())
+                .map_err(|_| anyhow!("[PROC_19]: Invalid section size threshold '{}'", sval))?;
+        warn_section_size = Some(threshold);
     }
 
-    let engine = Engine::new(&ir_db, &mut diags, 0);
-    if engine.is_none() {
-        return Err(anyhow!("[PROC_5]: Error detected, halting."));
+    let mut round_to = None;
+    if let Some(sval) = args.value_of("round-to") {
+        match parse::<u64>(sval.trim()) {
+            Ok(multiple) if multiple > 0 => { round_to = Some(multiple); }
+            _ => {
+                return Err(anyhow!("[PROC_15]: Invalid round-to size '{}', expected a \
+                                    positive integer", sval));
+            }
+        }
     }
 
-    let engine = engine.unwrap();
-    if verbosity > 2 {
-        engine.dump_locations();
+    let mut encoding_table = None;
+    if let Some(table_name) = args.value_of("encoding-table") {
+        encoding_table = Some(read_encoding_table(table_name.trim())?);
+    }
+
+    // Shared by the text output formats
+    let mut bytes_per_line = DEFAULT_BYTES_PER_LINE;
+    if let Some(sval) = args.value_of("bytes-per-line") {
+        match parse::<usize>(sval.trim()) {
+            Ok(count) if count > 0 => { bytes_per_line = count; }
+            _ => {
+                return Err(anyhow!("[PROC_20]: Invalid bytes per line '{}', expected a \
+                                    positive integer", sval));
+            }
+        }
+    }
+
+    if let Some(array_name) = args.value_of("array-name") {
+        let mut chars = array_name.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+                    chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(anyhow!("[PROC_21]: Invalid array name '{}', expected a C \
+                                identifier", array_name));
+        }
     }
+
     // Determine if the user specified an output file on the command line
-    // Trim whitespace
-    let fname_str = String::from(args.value_of("output")
-                                            .unwrap_or("output.bin")
-                                            .trim_matches(' '));
+    // Trim whitespace.  With an output directory, the output file name
+    // is the input file stem with a .bin extension.
+    let fname_str = match args.value_of("output-dir") {
+        Some(dir) => {
+            let stem = Path::new(name).file_stem().unwrap_or_default();
+            Path::new(dir.trim()).join(stem).with_extension("bin")
+                                 .to_string_lossy().to_string()
+        }
+        None => String::from(args.value_of("output")
+                                 .unwrap_or("output.bin")
+                                 .trim_matches(' ')),
+    };
     debug!("process: output file name is {}", fname_str);
+    let cache_name = format!("{}.brinkcache", fname_str);
+
+    let opts = Options {
+        verbosity,
+        relax_types: args.is_present("relax-types"),
+        deterministic: args.is_present("deterministic"),
+        encoding_table,
+        defines: defines.clone(),
+        strict_section_order: args.is_present("strict-section-order"),
+        warn_unused_const: args.is_present("warn-unused-const"),
+        warn_section_size,
+        no_truncation_warnings: args.is_present("no-truncation-warnings"),
+        line_buffered: args.is_present("line-buffered"),
+        dump_fixedpoint: args.is_present("dump-fixedpoint"),
+        ..Options::default()
+    };
+
+    let mut hooks = CliHooks {
+        args,
+        name,
+        fstr,
+        opts: &opts,
+        stage: args.value_of("stage").unwrap_or("exec"),
+        round_to,
+        bytes_per_line,
+        fname_str: &fname_str,
+        cache_name: &cache_name,
+        cache_key: None,
+    };
+
+    // Relative wrf paths are relative to the input file
+    let base_dir = Path::new(name).parent().unwrap_or_else(|| Path::new(""));
+    let assembly = match run_pipeline(fstr, base_dir, &opts, &mut hooks, diags)? {
+        Some(assembly) => assembly,
+        None => { return Ok(()); }
+    };
+    let cache_key = hooks.cache_key;
+    let Assembly { ir_db, engine, output_sec, image: mut buf, exec_ok } = assembly;
+    let image_info = cli_image_info(args, name, ir_db.start_addr, &output_sec, bytes_per_line);
+
+    // Pad the complete image, but not partial output after an error
+    if let Some(multiple) = round_to.filter(|_| exec_ok) {
+        let padded_len = (buf.len() as u64).checked_next_multiple_of(multiple)
+                .ok_or_else(|| anyhow!("[PROC_16]: Rounding the {} byte image up to a \
+                                        multiple of {} overflows", buf.len(), multiple))?;
+        buf.resize(padded_len as usize, 0);
+    }
+
+    // The hex dump previews the image regardless of the output file format
+    if exec_ok && args.is_present("hexdump") && verbosity > 0 &&
+       !args.is_present("quiet-success") {
+        hexdump(&mut std::io::stdout().lock(), &buf, ir_db.start_addr, bytes_per_line)
+                .context("Unable to write hex dump")?;
+    }
+
+    // In verify mode, compare against the expected file instead
+    // of writing any output.
+    if let Some(expected_name) = args.value_of("verify") {
+        if !exec_ok {
+            return Err(anyhow!("[PROC_4]: Error detected, halting."));
+        }
+        return verify_output(&buf, expected_name.trim_matches(' '), diags);
+    }
+
+    // We write nothing on error unless the user explicitly asked for
+    // partial output.
+    if !exec_ok && !args.is_present("output-on-error") {
+        return Err(anyhow!("[PROC_4]: Error detected, halting."));
+    }
+
+    // A stale cache file must never vouch for new output
+    let _ = fs::remove_file(&cache_name);
+
+    // Encode the image before touching the output file, so that an encoding
+    // error such as an address out of range for the format writes nothing.
+    let mut image = Vec::new();
+    write_image(&mut image, &buf, &image_info)
+            .context(format!("Unable to encode output file {}", fname_str))?;
 
-    let mut file = File::create(&fname_str)
-            .context(format!("Unable to create output file {}", fname_str))?;
+    // Appending adds the bytes to the end of any existing file without
+    // regard to the starting address in the output statement.
+    let mut file = if args.is_present("append") {
+        OpenOptions::new().append(true).create(true).open(&fname_str)
+                .context(format!("Unable to open output file {} for append", fname_str))?
+    } else {
+        File::create(&fname_str)
+                .context(format!("Unable to create output file {}", fname_str))?
+    };
+    file.write_all(&image)
+            .context(format!("Unable to write output file {}", fname_str))?;
 
-    if engine.execute(&ir_db, &mut diags, &mut file).is_err() {
+    if exec_ok {
+        if let Some(dir) = args.value_of("split-sections") {
+            write_split_sections(&engine, &ir_db, &buf, dir.trim())?;
+        }
+        if let Some(key) = cache_key {
+            fs::write(&cache_name, key)
+                    .context(format!("Unable to write cache file {}", cache_name))?;
+        }
+    } else {
+        let msg = format!("Output file {} is partial, containing only the {} bytes \
+                           written before the first error.", fname_str, buf.len());
+        diags.warn("PROC_6", &msg);
         return Err(anyhow!("[PROC_4]: Error detected, halting."));
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,112 @@
+// Motorola S-record encoding of the output image
+use std::io::{self, Write};
+
+/// Number of data bytes in each full data record
+const RECORD_LEN: usize = 16;
+
+/// S-record addresses are at most 32 bits
+const MAX_ADDR: u64 = 0x1_0000_0000;
+
+/// Encodes the bytes written to it as Motorola S-records on the wrapped
+/// writer.  The address width of the data records is the smallest that
+/// holds the end address: S1 for 16 bits, S2 for 24 bits and S3 for 32
+/// bits.  Call finish() to write the matching S9, S8 or S7 termination
+/// record.
+pub struct SRecWriter<W: Write> {
+    out: W,
+    /// Absolute address of the first byte in pending
+    addr: u64,
+    /// Address of the first byte of the image, for the termination record
+    start_addr: u64,
+    /// Number of address bytes in each record, 2, 3 or 4
+    addr_len: usize,
+    /// Bytes not yet written as a data record
+    pending: Vec<u8>,
+}
+
+impl<W: Write> SRecWriter<W> {
+    /// Creates the writer and writes the S0 header record with the name.
+    /// end_addr: The address just past the last byte of the image
+    pub fn new(out: W, name: &str, start_addr: u64, end_addr: u64)
+               -> io::Result<SRecWriter<W>> {
+        if end_addr > MAX_ADDR {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("S-record output ends at address {:#X}, beyond the 32-bit \
+                             address limit", end_addr)));
+        }
+        let top_addr = end_addr.saturating_sub(1).max(start_addr);
+        let addr_len = if top_addr <= 0xFFFF {
+            2
+        } else if top_addr <= 0xFF_FFFF {
+            3
+        } else {
+            4
+        };
+        let mut writer = SRecWriter { out, addr: start_addr, start_addr, addr_len,
+                                      pending: Vec::new() };
+        // The header data is limited by the one byte record length
+        let name = &name.as_bytes()[..name.len().min(252)];
+        writer.write_record(0, 2, 0, name)?;
+        Ok(writer)
+    }
+
+    /// Writes one record with the type, byte count, address, data and
+    /// checksum fields.  The checksum is the one's complement of the sum
+    /// of the count, address and data bytes.
+    fn write_record(&mut self, rec_type: u8, addr_len: usize, addr: u64, data: &[u8])
+                    -> io::Result<()> {
+        let mut rec = vec![(addr_len + data.len() + 1) as u8];
+        rec.extend_from_slice(&addr.to_be_bytes()[8 - addr_len..]);
+        rec.extend_from_slice(data);
+        let sum = rec.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        rec.push(!sum);
+
+        let hex: String = rec.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(self.out, "S{}{}", rec_type, hex)
+    }
+
+    /// Writes data records for the pending bytes.  Unless flushing all
+    /// bytes, leaves a partial record pending for the next write.
+    fn write_pending(&mut self, all: bool) -> io::Result<()> {
+        let rec_type = (self.addr_len - 1) as u8;
+        let mut start = 0;
+        while self.pending.len() - start >= RECORD_LEN ||
+              (all && start < self.pending.len()) {
+            let len = RECORD_LEN.min(self.pending.len() - start);
+            let data = self.pending[start..start + len].to_vec();
+            self.write_record(rec_type, self.addr_len, self.addr, &data)?;
+            self.addr += len as u64;
+            start += len;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    /// Writes any remaining data and the termination record with the
+    /// starting address, then returns the wrapped writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_pending(true)?;
+        let rec_type = 11 - self.addr_len as u8;
+        self.write_record(rec_type, self.addr_len, self.start_addr, &[])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for SRecWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.addr + (self.pending.len() + buf.len()) as u64;
+        if end > MAX_ADDR {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("S-record output ends at address {:#X}, beyond the 32-bit \
+                             address limit", end)));
+        }
+        self.pending.extend_from_slice(buf);
+        self.write_pending(false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -4,13 +4,14 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 
+use std::collections::HashMap;
 use std::env;
 use std::{io,fs};
-use anyhow::{Result,Context};
-use clap::{Arg, App};
+use anyhow::{Result,Context,anyhow};
+use clap::{Arg, App, Error, ErrorKind};
 
 // Local libraries
-use process::process;
+use process::{process,parse_define,lang_help};
 
 
 // Logging
@@ -42,6 +43,55 @@ fn init_log(verbosity : u64) -> Result<(), fern::InitError>  {
     Ok(())
 }
 
+/// Reads the brink file into a string.
+fn read_source(in_file_name: &str) -> Result<String> {
+    // remove carriage return from line endings for windows platforms
+    let str_in = fs::read_to_string(in_file_name)
+        .with_context(|| format!(
+                "Failed to read from file {}.\nWorking directory is {}",
+                in_file_name, env::current_dir().unwrap().display()))?
+        .replace("\r\n","\n");
+    Ok(str_in)
+}
+
+/// Processes each input file matching the glob pattern.  Unless fail-fast
+/// is set, continues past failures and reports all failed files at the end.
+fn process_glob(pattern: &str, args: &clap::ArgMatches<'_>, verbosity: u64,
+                noprint: bool, defines: &HashMap<String, String>) -> Result<()> {
+    let paths = glob::glob(pattern)
+            .with_context(|| format!("Invalid input glob pattern '{}'", pattern))?;
+
+    let mut total = 0;
+    let mut failed = Vec::new();
+    for path in paths {
+        let path = path.context("Unable to read input glob match")?;
+        let in_file_name = path.to_string_lossy().to_string();
+        total += 1;
+        let result = read_source(&in_file_name)
+                .and_then(|str_in| process(&in_file_name, &str_in, args, verbosity, noprint,
+                                          defines));
+        if let Err(err) = result {
+            if args.is_present("fail-fast") {
+                return Err(err.context(format!("Failed to process {}", in_file_name)));
+            }
+            if verbosity > 0 {
+                eprintln!("Error: Failed to process {}: {}", in_file_name, err);
+            }
+            failed.push(in_file_name);
+        }
+    }
+
+    if total == 0 {
+        return Err(anyhow!("No input files match '{}'", pattern));
+    }
+    if !failed.is_empty() {
+        return Err(anyhow!("{} of {} input files failed: {}", failed.len(), total,
+                           failed.join(", ")));
+    }
+    info!("Processed {} input files", total);
+    Ok(())
+}
+
 fn main() -> Result<()> {
     // clap processes args
     let args = App::new("brink")
@@ -51,13 +101,41 @@ fn main() -> Result<()> {
             .about(env!("CARGO_PKG_DESCRIPTION"))
             .arg(Arg::with_name("INPUT")
             .help("The input source file.")
-            .required(true)
+            .required_unless_one(&["input-glob", "help-lang"])
+            .conflicts_with("input-glob")
             .index(1))
+            .arg(Arg::with_name("input-glob")
+                .long("input-glob")
+                .value_name("pattern")
+                .takes_value(true)
+                .requires("output-dir")
+                .help("Processes every input file matching the glob pattern, e.g. \"src/*.brink\".  Continues past failed files and reports them at the end.  Requires --output-dir."))
+            .arg(Arg::with_name("output-dir")
+                .long("output-dir")
+                .value_name("dir")
+                .takes_value(true)
+                .conflicts_with("output")
+                .help("Writes the output for each input file to <dir>/<input file stem>.bin."))
+            .arg(Arg::with_name("fail-fast")
+                .long("fail-fast")
+                .requires("input-glob")
+                .help("With --input-glob, stops at the first failed input file."))
             .arg(Arg::with_name("verbosity")
                 .short("v")
                 .long("verbose")
                 .multiple(true)
                 .help("Sets the verbosity level. Use up to 4 times."))
+            .arg(Arg::with_name("help-lang")
+                .long("help-lang")
+                .help("Prints a concise reference of the language statements and built-in functions, then exits."))
+            .arg(Arg::with_name("define")
+                .short("D")
+                .long("define")
+                .value_name("name=value")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Defines a symbol that the source can use as a value, e.g. -D VERSION=3 or -D NAME=boot.  An integer value keeps its integer type and any other value is a string.  May be repeated."))
             .arg(Arg::with_name("output")
                 .short("o")
                 .long("output")
@@ -69,12 +147,135 @@ fn main() -> Result<()> {
                 .value_name("noprint")
                 .takes_value(false)
                 .help("Suppresses console print statements in source code.  Default is false."))
+            .arg(Arg::with_name("line-buffered")
+                .long("line-buffered")
+                .help("Flushes console print statements only at each newline.  By default, each print statement flushes so that print output precedes any later diagnostics."))
+            .arg(Arg::with_name("dump-fixedpoint")
+                .long("dump-fixedpoint")
+                .help("Prints the IR locations that change on each layout iteration.  Useful for debugging layouts that converge slowly."))
+            .arg(Arg::with_name("cache")
+                .long("cache")
+                .help("Skips assembly if the output file is up to date with the inputs.  Records a hash of the inputs in a .brinkcache file next to the output."))
+            .arg(Arg::with_name("dump-tokens")
+                .long("dump-tokens")
+                .help("Prints each token with the source line number, e.g. 'L3: Wr8 wr8'."))
+            .arg(Arg::with_name("dump-ast-text")
+                .long("dump-ast-text")
+                .help("Prints the abstract syntax tree as indented text with the source line number of each node."))
+            .arg(Arg::with_name("dump-operands")
+                .long("dump-operands")
+                .help("Prints the operand table with the data type, value or source IR, and source span of each operand.  Useful for debugging type inference."))
+            .arg(Arg::with_name("emit-ir")
+                .long("emit-ir")
+                .takes_value(true)
+                .value_name("ir_file")
+                .help("Writes the intermediate representation to the specified file as stable, line oriented text for external tools."))
+            .arg(Arg::with_name("emit-internal-state")
+                .long("emit-internal-state")
+                .takes_value(true)
+                .value_name("state_file")
+                .help("Writes the final location of each IR to the specified file, one line per IR.  Useful for layout regression tests."))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("format")
+                .possible_values(&["bin", "ihex", "srec", "carray"])
+                .help("Selects the output file format.  bin is the raw binary image.  ihex is Intel HEX records and srec is Motorola S-records starting at the output address.  carray is a C array definition of the image.  Default is bin."))
+            .arg(Arg::with_name("array-name")
+                .long("array-name")
+                .takes_value(true)
+                .value_name("name")
+                .help("With --format carray, names the array, which must be a C identifier.  Default is the output section name."))
+            .arg(Arg::with_name("array-type")
+                .long("array-type")
+                .takes_value(true)
+                .value_name("type")
+                .help("With --format carray, sets the C type of the array elements.  Default is 'const unsigned char'."))
+            .arg(Arg::with_name("bytes-per-line")
+                .long("bytes-per-line")
+                .takes_value(true)
+                .value_name("count")
+                .help("Sets the number of bytes on each line of the carray format and the --hexdump output.  Default is 16."))
+            .arg(Arg::with_name("append")
+                .long("append")
+                .conflicts_with("cache")
+                .help("Appends to the output file instead of replacing it, creating the file if needed.  Appending ignores the starting address in the output statement, so addresses are relative to the start of this program's output, not the existing file.  Requires the bin format."))
+            .arg(Arg::with_name("stage")
+                .long("stage")
+                .takes_value(true)
+                .value_name("stage")
+                .possible_values(&["ast", "linear", "ir", "exec"])
+                .help("Stops after the specified stage and prints that stage's dump without writing an output file.  Default is exec, which runs all stages."))
+            .arg(Arg::with_name("list-labels")
+                .long("list-labels")
+                .help("Lists each label with its address and source location, then exits without writing an output file."))
+            .arg(Arg::with_name("hexdump")
+                .long("hexdump")
+                .help("Prints an xxd style hex and ASCII dump of the output to stdout, with addresses starting at the output starting address.  The output file is still written.  Suppressed by -q and --quiet-success."))
+            .arg(Arg::with_name("output-on-error")
+                .long("output-on-error")
+                .help("On error, still write the partial output produced before the first error.  Default is to write no output file."))
+            .arg(Arg::with_name("encoding-table")
+                .long("encoding-table")
+                .value_name("table_file")
+                .takes_value(true)
+                .help("Translates wrs strings through the character to byte mapping in the table file.  Each line of the file is a character followed by a byte value, e.g. 'A 0x01'."))
+            .arg(Arg::with_name("verify")
+                .long("verify")
+                .value_name("expected_file")
+                .takes_value(true)
+                .help("Compares the output byte-for-byte against the expected file instead of writing an output file.  On a mismatch, prints the bytes around the first difference."))
+            .arg(Arg::with_name("relax-types")
+                .long("relax-types")
+                .help("Promotes expressions that mix I64 and U64 operands to U64 with a warning instead of an error."))
+            .arg(Arg::with_name("strict-section-order")
+                .long("strict-section-order")
+                .help("Requires every section to be defined before any statement that writes it.  Forward references are otherwise valid."))
+            .arg(Arg::with_name("no-truncation-warnings")
+                .long("no-truncation-warnings")
+                .help("Suppresses the warning when a value does not fit the width of a write, e.g. 'wr8 256;'.  The write still keeps only the low bytes of the value."))
+            .arg(Arg::with_name("deterministic")
+                .long("deterministic")
+                .help("Omits the build date from header blocks so that identical inputs produce identical output."))
+            .arg(Arg::with_name("split-sections")
+                .long("split-sections")
+                .value_name("dir")
+                .takes_value(true)
+                .help("In addition to the output file, writes the bytes of each section written by the output section to <dir>/<section name>.bin.  Useful for comparing individual sections across builds."))
+            .arg(Arg::with_name("round-to")
+                .long("round-to")
+                .value_name("bytes")
+                .takes_value(true)
+                .help("Pads the output image with zero bytes up to the next multiple of the specified number of bytes, e.g. 512 for sector-aligned disk images.  An image that is already a multiple is unchanged."))
+            .arg(Arg::with_name("warn-section-size")
+                .long("warn-section-size")
+                .value_name("bytes")
+                .takes_value(true)
+                .help("Warns about any section with a final size larger than the specified number of bytes, e.g. 0x10000."))
+            .arg(Arg::with_name("warn-unused-const")
+                .long("warn-unused-const")
+                .help("Warns about any constant that the output never uses, including constants used only in sections that are never written."))
+            .arg(Arg::with_name("quiet-success")
+                .long("quiet-success")
+                .help("Prints nothing when processing succeeds, including warnings and source print statements, but prints all diagnostics on failure.  Useful for scripting."))
             .arg(Arg::with_name("quiet")
                 .short("q")
                 .long("quiet")
                 .help("Suppress console output, including error messages.  Useful for fuzz testing.  Overrides -v."))
             .get_matches();
 
+    // Appending concatenates complete files, which is valid only for the
+    // raw binary format.  Clap can't express a conflict with a value.
+    if args.is_present("append") && args.value_of("format").is_some_and(|fmt| fmt != "bin") {
+        Error::with_description("The argument '--append' cannot be used with '--format' \
+                                 other than bin", ErrorKind::ArgumentConflict).exit();
+    }
+
+    if args.is_present("help-lang") {
+        print!("{}", lang_help());
+        return Ok(());
+    }
+
     // Default verbosity
     let verbosity = if args.is_present("quiet") {
         0
@@ -82,23 +283,37 @@ fn main() -> Result<()> {
         1 + args.occurrences_of("verbosity")
     };
 
-    init_log(verbosity).expect("Unknown error initializing logging.");
+    // With quiet-success, only errors reach the log
+    let log_verbosity = if args.is_present("quiet-success") { 0 } else { verbosity };
+    init_log(log_verbosity).expect("Unknown error initializing logging.");
 
     info!("brink version {}", env!("CARGO_PKG_VERSION"));
 
+    let noprint = args.is_present("noprint") || args.is_present("quiet-success");
+
+    if let Some(dir) = args.value_of("output-dir") {
+        fs::create_dir_all(dir.trim())
+                .with_context(|| format!("Unable to create output directory {}", dir))?;
+    }
+
+    // Later definitions of the same name replace earlier ones
+    let mut defines = HashMap::new();
+    for arg in args.values_of("define").into_iter().flatten() {
+        let (name, value) = parse_define(arg)?;
+        defines.insert(name, value);
+    }
+
+    if let Some(pattern) = args.value_of("input-glob") {
+        return process_glob(pattern.trim(), &args, verbosity, noprint, &defines);
+    }
+
     // Read the brink file into a string and pass to parser.
     // A bland error message here is fine since clap already
     // provides nice error messages.
     let in_file_name = args.value_of("INPUT")
             .context("Unknown input file argument error.")?;
 
-    // remove carriage return from line endings for windows platforms
-    let str_in = fs::read_to_string(&in_file_name)
-        .with_context(|| format!(
-                "Failed to read from file {}.\nWorking directory is {}",
-                in_file_name, env::current_dir().unwrap().display()))?
-        .replace("\r\n","\n");
+    let str_in = read_source(in_file_name)?;
 
-    process(&in_file_name, &str_in, &args, verbosity,
-             args.is_present("noprint"))
+    process(in_file_name, &str_in, &args, verbosity, noprint, &defines)
 }
@@ -3,6 +3,7 @@ mod tests {
 use assert_cmd::{Command};
 use std::fs;
 use serial_test::serial;
+use predicates::prelude::PredicateBooleanExt;
 
 // Many tests just use the default output file "output.bin".
 // This creates a race condition since each test deletes this
@@ -112,7 +113,7 @@ fn empty_section_1() {
                 .success();
 
     // Verify file is empty.  If so, then clean up.
-    assert!(fs::read_to_string("empty_section_1.bin").unwrap().len() == 0);
+    assert!(fs::read_to_string("empty_section_1.bin").unwrap().is_empty());
     fs::remove_file("empty_section_1.bin").unwrap();
 
 }
@@ -432,7 +433,7 @@ fn fuzz_found_8() {
     .arg("tests/fuzz_found_8.brink")
     .assert()
     .failure()
-    .stderr(predicates::str::contains("[AST_20]"));
+    .stderr(predicates::str::contains("[AST_54]"));
 }
 
 #[test]
@@ -442,7 +443,7 @@ fn fuzz_found_9() {
     .arg("tests/fuzz_found_9.brink")
     .assert()
     .failure()
-    .stderr(predicates::str::contains("[AST_19]"));
+    .stderr(predicates::str::contains("[IRDB_29]"));
 }
 
 #[test]
@@ -532,9 +533,11 @@ fn fuzz_found_18() {
     let _cmd = Command::cargo_bin("brink")
     .unwrap()
     .arg("tests/fuzz_found_18.brink")
+    .arg("-o fuzz_found_18.bin")
     .assert()
-    .failure()
-    .stderr(predicates::str::contains("[IRDB_2]"));
+    .success()
+    .stdout(predicates::str::contains("f \n"));
+    fs::remove_file("fuzz_found_18.bin").unwrap();
 }
 
 #[test]
@@ -688,12 +691,14 @@ fn integers_3() {
 #[test]
 #[serial]
 fn integers_4() {
+    // -0 is not a literal, but is the valid negation of 0
     let _cmd = Command::cargo_bin("brink")
     .unwrap()
     .arg("tests/integers_4.brink")
     .assert()
-    .failure()
-    .stderr(predicates::str::contains("[AST_19]"));
+    .success();
+
+    fs::remove_file("output.bin").unwrap();
 }
 
 #[test]
@@ -1122,6 +1127,31 @@ fn print_2() {
     fs::remove_file("output.bin").unwrap();
 }
 
+#[test]
+fn printf_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/printf_1.brink")
+                .arg("-o printf_1.bin")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains("addr=1005 size=5\nfive is 101 in binary, -3%\n"));
+
+    fs::remove_file("printf_1.bin").unwrap();
+}
+
+#[test]
+fn printf_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/printf_2.brink")
+                .arg("-o printf_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_20]"));
+    let _ = fs::remove_file("printf_2.bin");
+}
+
 #[test]
 fn wrs_1() {
     let _cmd = Command::cargo_bin("brink")
@@ -1132,7 +1162,7 @@ fn wrs_1() {
                 .success();
 
     // Verify output file is correct.  If so, then clean up.
-    assert_eq!("123\0456 Wow! 18 2\n", fs::read_to_string("wrs_1.bin").unwrap());
+    assert_eq!("123\x00456 Wow! 18 2\n", fs::read_to_string("wrs_1.bin").unwrap());
     fs::remove_file("wrs_1.bin").unwrap();
 }
 
@@ -1348,7 +1378,7 @@ fn align_2() {
     let temp : Vec<u8> = vec![
         1, 2, 3, 4, 5, 0, 0, 0,   0, 0, 0, 0, 0, 0, 0, 0,  // align 16;
         0xAA, 0xAA, 0xAA, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,    // align 8, 0xFF;
-        0xAA, 0xAA, 0xAA, 0x77                             // align 7, 0x77;
+        0xAA, 0xAA, 0xAA, 0x77                             // align 4, 0x77;
         ];
     println!("Bytevec length = {}", bytevec.len() );
     assert!(bytevec.len() == 28);
@@ -1477,9 +1507,2866 @@ fn wrf_3() {
     .arg("tests/wrf_3.brink")
     .assert()
     .failure()
-    .stderr(predicates::str::contains("[AST_19]"));
+    .stderr(predicates::str::contains("[IRDB_29]"));
+}
+
+#[test]
+fn wrbytes_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wrbytes_1.brink")
+                .arg("-o wrbytes_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("wrbytes_1.bin").unwrap();
+    assert_eq!(bytevec, [1, 2, 3, 0xFF, 2, 12, 0, b'H', b'i']);
+    fs::remove_file("wrbytes_1.bin").unwrap();
+}
+
+#[test]
+fn wrbytes_2() {
+    let _cmd = Command::cargo_bin("brink")
+    .unwrap()
+    .arg("tests/wrbytes_2.brink")
+    .arg("-o wrbytes_2.bin")
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("[EXEC_36]"));
+    let _ = fs::remove_file("wrbytes_2.bin");
+}
+
+#[test]
+fn wrbytes_3() {
+    let _cmd = Command::cargo_bin("brink")
+    .unwrap()
+    .arg("tests/wrbytes_3.brink")
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("[AST_30]"));
+}
+
+#[test]
+fn recover_1() {
+    let cmd = Command::cargo_bin("brink")
+    .unwrap()
+    .arg("tests/recover_1.brink")
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains("[AST_3]"));
+
+    // Exactly one error for the malformed statement
+    let stderr = String::from_utf8_lossy(&cmd.get_output().stderr).to_string();
+    assert_eq!(stderr.matches("[AST_").count(), 1);
+}
+
+#[test]
+fn dump_fixedpoint_1() {
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/dump_fixedpoint_1.brink")
+                .arg("-o dump_fixedpoint_1.bin")
+                .arg("--dump-fixedpoint")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains("fixedpoint pass 1:"))
+                .stdout(predicates::str::contains("lid 3 SectionStart: img 0 -> 3"));
+
+    // The forward sizeof reference must settle within 3 passes
+    let stdout = String::from_utf8_lossy(&cmd.get_output().stdout).to_string();
+    assert!(stdout.contains("fixedpoint: converged after 3 passes"));
+    assert!(!stdout.contains("fixedpoint pass 4:"));
+    fs::remove_file("dump_fixedpoint_1.bin").unwrap();
+}
+
+#[test]
+fn fixedpoint_single_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/fixedpoint_single_1.brink")
+                .arg("-o fixedpoint_single_1.bin")
+                .arg("--dump-fixedpoint")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains("fixedpoint: converged after 1 passes"));
+    assert_eq!(fs::read("fixedpoint_single_1.bin").unwrap(),
+               [b'H', b'e', b'l', b'l', b'o', 0xFF, 0xFF, 0xFF, 0xFF, 0]);
+    fs::remove_file("fixedpoint_single_1.bin").unwrap();
+}
+
+#[test]
+fn assert_if_written_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/assert_if_written_1.brink")
+                .arg("-o assert_if_written_1.bin")
+                .assert()
+                .success();
+    fs::remove_file("assert_if_written_1.bin").unwrap();
+}
+
+#[test]
+fn assert_if_written_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/assert_if_written_2.brink")
+                .arg("-o assert_if_written_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_2]"));
+    let _ = fs::remove_file("assert_if_written_2.bin");
+}
+
+#[test]
+fn assert_if_written_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/assert_if_written_3.brink")
+                .arg("-o assert_if_written_3.bin")
+                .assert()
+                .success();
+    let bytevec = fs::read("assert_if_written_3.bin").unwrap();
+    assert_eq!(bytevec, [1]);
+    fs::remove_file("assert_if_written_3.bin").unwrap();
+}
+
+#[test]
+fn output_on_error_1() {
+    let _ = fs::remove_file("output_on_error_1.bin");
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/output_on_error_1.brink")
+                .arg("-o output_on_error_1.bin")
+                .arg("--output-on-error")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_2]"))
+                .stderr(predicates::str::contains("[PROC_6]"));
+
+    // Only the bytes before the failing assert are in the file.
+    let bytevec = fs::read("output_on_error_1.bin").unwrap();
+    assert_eq!(bytevec, [1, 2]);
+    fs::remove_file("output_on_error_1.bin").unwrap();
+}
+
+#[test]
+fn output_on_error_2() {
+    let _ = fs::remove_file("output_on_error_2.bin");
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/output_on_error_1.brink")
+                .arg("-o output_on_error_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_2]"));
+
+    // Without --output-on-error, an error produces no output file.
+    assert!(fs::metadata("output_on_error_2.bin").is_err());
+}
+
+#[test]
+fn include_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/include_1.brink")
+                .arg("-o include_1.bin")
+                .assert()
+                .success();
+    assert_eq!("ACBC", fs::read_to_string("include_1.bin").unwrap());
+    fs::remove_file("include_1.bin").unwrap();
+}
+
+#[test]
+fn include_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/include_2.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_29]"));
+}
+
+#[test]
+fn include_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/include_3.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_9]"))
+                .stderr(predicates::str::contains("creates a cycle"));
+}
+
+#[test]
+fn include_4() {
+    // Both definitions are reported in their original included files
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/include_4.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_29]"))
+                .stderr(predicates::str::contains("tests/include/dup_y.brink:2:9"))
+                .stderr(predicates::str::contains("tests/include/dup_x.brink:1:1"));
+}
+
+#[test]
+fn include_5() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/include_5.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_7]"));
+}
+
+#[test]
+fn include_6() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/include_6.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_23]"));
+}
+
+#[test]
+fn output_addr_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/output_addr_1.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_15]"))
+                .stderr(predicates::str::contains("out of range"));
+}
+
+#[test]
+fn tee_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/tee_1.brink")
+                .arg("-o tee_1.bin")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains("Hello 3\n"));
+    assert_eq!("AHello 3\n", fs::read_to_string("tee_1.bin").unwrap());
+    fs::remove_file("tee_1.bin").unwrap();
+}
+
+#[test]
+fn tee_2() {
+    // noprint suppresses the console output, but not the file output
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/tee_1.brink")
+                .arg("-o tee_2.bin")
+                .arg("--noprint")
+                .assert()
+                .success()
+                .stdout(predicates::str::is_empty());
+    assert_eq!("AHello 3\n", fs::read_to_string("tee_2.bin").unwrap());
+    fs::remove_file("tee_2.bin").unwrap();
+}
+
+#[test]
+fn section_base_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/section_base_1.brink")
+                .arg("-o section_base_1.bin")
+                .assert()
+                .success();
+    let bytevec = fs::read("section_base_1.bin").unwrap();
+    assert_eq!(bytevec, [0, 0, 0, 0, 1, 0x01, 0x80, 0x01, 0x80, 0, 0, 0, 0, 0, 0]);
+    fs::remove_file("section_base_1.bin").unwrap();
+}
+
+#[test]
+fn section_base_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/section_base_2.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_32]"));
+}
+
+#[test]
+fn size_overflow_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/size_overflow_1.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_37]"));
+}
+
+#[test]
+fn size_overflow_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/size_overflow_2.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_38]"));
+}
+#[test]
+fn warn_section_size_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/warn_section_size_1.brink")
+                .arg("-o warn_section_size_1.bin")
+                .arg("--warn-section-size=0x100")
+                .assert()
+                .success()
+                .stderr(predicates::str::contains("[EXEC_40]"))
+                .stderr(predicates::str::contains("'big'"))
+                .stderr(predicates::str::contains("'top'"));
+    let _ = fs::remove_file("warn_section_size_1.bin");
+}
+#[test]
+fn warn_section_size_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/warn_section_size_1.brink")
+                .arg("-o warn_section_size_2.bin")
+                .arg("--warn-section-size=big")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_19]"));
+    assert!(fs::metadata("warn_section_size_2.bin").is_err());
+}
+#[test]
+fn roundup_pow2_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/roundup_pow2_1.brink")
+                .arg("-o roundup_pow2_1.bin")
+                .assert()
+                .success();
+    let bin = fs::read("roundup_pow2_1.bin").unwrap();
+    assert_eq!(bin, b"Hello\0\0\0\x11\x22\x33\x44");
+    fs::remove_file("roundup_pow2_1.bin").unwrap();
+}
+
+#[test]
+fn roundup_pow2_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/roundup_pow2_2.brink")
+                .arg("-o roundup_pow2_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_20]"));
+    let _ = fs::remove_file("roundup_pow2_2.bin");
+}
+#[test]
+fn verify_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/verify_1.brink")
+                .arg("-o verify_1.bin")
+                .arg("--verify=tests/verify_1.bin")
+                .assert()
+                .success();
+    // Verify mode never writes an output file
+    assert!(fs::metadata("verify_1.bin").is_err());
 }
 
+#[test]
+fn verify_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/verify_1.brink")
+                .arg("-o verify_2.bin")
+                .arg("--verify=tests/verify_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_17]"))
+                .stderr(predicates::str::contains("at offset 0x6: output byte 0x02, expected byte 0x03"));
+    assert!(fs::metadata("verify_2.bin").is_err());
+}
+
+#[test]
+fn verify_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/verify_1.brink")
+                .arg("-o verify_3.bin")
+                .arg("--verify=tests/verify_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_18]"))
+                .stderr(predicates::str::contains("[PROC_22]"))
+                .stderr(predicates::str::contains("at offset 0x6"));
+    assert!(fs::metadata("verify_3.bin").is_err());
+}
+
+#[test]
+fn verify_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/verify_4.brink")
+                .arg("-o verify_4.bin")
+                .arg("--verify=tests/verify_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_17]"))
+                .stderr(predicates::str::contains("[PROC_13]"))
+                .stderr(predicates::str::contains(
+                    "  0x00000000  00 00 00 00 00 00 00 00   00 00 00 00 00 00 00 00"))
+                .stderr(predicates::str::contains(
+                    "* 0x00000008  11 11 11 11 11 11 11 11   11 11 11 11 99 11 11 11"))
+                .stderr(predicates::str::contains(
+                    "  0x00000010  22 22 22 22 22 22 22 22   22 22 22 22 22 22 22 22"));
+    assert!(fs::metadata("verify_4.bin").is_err());
+}
+
+#[test]
+fn concat_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/concat_1.brink")
+                .arg("-o concat_1.bin")
+                .assert()
+                .success();
+    let bin = fs::read("concat_1.bin").unwrap();
+    assert_eq!(bin, b"Hello\x01\x01\x01!");
+    fs::remove_file("concat_1.bin").unwrap();
+}
+
+#[test]
+fn concat_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/concat_2.brink")
+                .arg("-o concat_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_6]"));
+    let _ = fs::remove_file("concat_2.bin");
+}
+
+#[test]
+fn concat_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/concat_3.brink")
+                .arg("-o concat_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_55]"));
+    let _ = fs::remove_file("concat_3.bin");
+}
+
+#[test]
+fn concat_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/concat_4.brink")
+                .arg("-o concat_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_35]"));
+    let _ = fs::remove_file("concat_4.bin");
+}
+#[test]
+fn encoding_table_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/encoding_table_1.brink")
+                .arg("-o encoding_table_1.bin")
+                .arg("--encoding-table=tests/encoding/table_1.txt")
+                .assert()
+                .success();
+    let bin = fs::read("encoding_table_1.bin").unwrap();
+    assert_eq!(bin, [0x01, 0x02, 0xFF, 0x02, 0x01]);
+    fs::remove_file("encoding_table_1.bin").unwrap();
+}
+
+#[test]
+fn encoding_table_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/encoding_table_2.brink")
+                .arg("-o encoding_table_2.bin")
+                .arg("--encoding-table=tests/encoding/table_1.txt")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_42]"));
+    let _ = fs::remove_file("encoding_table_2.bin");
+}
+#[test]
+fn ensure_nonempty_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/ensure_nonempty_1.brink")
+                .arg("-o ensure_nonempty_1.bin")
+                .assert()
+                .success();
+    let bin = fs::read("ensure_nonempty_1.bin").unwrap();
+    assert_eq!(bin, [1]);
+    fs::remove_file("ensure_nonempty_1.bin").unwrap();
+}
+
+#[test]
+fn ensure_nonempty_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/ensure_nonempty_2.brink")
+                .arg("-o ensure_nonempty_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_43]"));
+    let _ = fs::remove_file("ensure_nonempty_2.bin");
+}
+#[test]
+fn dump_operands_1() {
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/dump_operands_1.brink")
+                .arg("-o dump_operands_1.bin")
+                .arg("--dump-operands")
+                .assert()
+                .success();
+    let stdout = String::from_utf8_lossy(&cmd.get_output().stdout).to_string();
+    assert!(stdout.contains("operand 1: U64 constant 0x1234, span 49..56"));
+    assert!(stdout.contains("Integer constant 7"));
+    assert!(stdout.contains("variable, output of lid 2"));
+    fs::remove_file("dump_operands_1.bin").unwrap();
+}
+#[test]
+fn suggest_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/suggest_1.brink")
+                .arg("-o suggest_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_16]"))
+                .stderr(predicates::str::contains("Did you mean 'mysection'?"));
+    let _ = fs::remove_file("suggest_1.bin");
+}
+#[test]
+fn suggest_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/suggest_2.brink")
+                .arg("-o suggest_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[LINEAR_6]"))
+                .stderr(predicates::str::contains("Did you mean 'start_of_data'?"));
+    let _ = fs::remove_file("suggest_2.bin");
+}
+#[test]
+fn reserved_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/reserved_1.brink")
+                .arg("-o reserved_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_36]"))
+                .stderr(predicates::str::contains("'assert' is a reserved keyword"));
+    let _ = fs::remove_file("reserved_1.bin");
+}
+
+#[test]
+fn reserved_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/reserved_2.brink")
+                .arg("-o reserved_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("'print' is a reserved keyword"));
+    let _ = fs::remove_file("reserved_2.bin");
+}
+#[test]
+fn cache_1() {
+    let _ = fs::remove_file("cache_1.bin.brinkcache");
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/cache_1.brink")
+                .arg("-o cache_1.bin")
+                .arg("--cache")
+                .assert()
+                .success();
+    let stderr = String::from_utf8_lossy(&cmd.get_output().stderr).to_string();
+    assert!(!stderr.contains("[PROC_12]"));
+    let mtime = fs::metadata("cache_1.bin").unwrap().modified().unwrap();
+
+    // Unchanged input is a cache hit and does not rewrite the output
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/cache_1.brink")
+                .arg("-o cache_1.bin")
+                .arg("--cache")
+                .assert()
+                .success()
+                .stderr(predicates::str::contains("[PROC_12]"));
+    assert_eq!(mtime, fs::metadata("cache_1.bin").unwrap().modified().unwrap());
+
+    // A different encoding table invalidates the cache
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/encoding_table_1.brink")
+                .arg("-o cache_1.bin")
+                .arg("--cache")
+                .arg("--encoding-table=tests/encoding/table_1.txt")
+                .assert()
+                .success();
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/encoding_table_1.brink")
+                .arg("-o cache_1.bin")
+                .arg("--cache")
+                .assert()
+                .success();
+    let stderr = String::from_utf8_lossy(&cmd.get_output().stderr).to_string();
+    assert!(!stderr.contains("[PROC_12]"));
+    assert_eq!(fs::read("cache_1.bin").unwrap(), b"AB BA");
+
+    fs::remove_file("cache_1.bin").unwrap();
+    fs::remove_file("cache_1.bin.brinkcache").unwrap();
+}
+#[test]
+fn output_align_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/output_align_1.brink")
+                .arg("-o output_align_1.bin")
+                .assert()
+                .success();
+    fs::remove_file("output_align_1.bin").unwrap();
+}
+
+#[test]
+fn output_align_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/output_align_2.brink")
+                .arg("-o output_align_2.bin")
+                .assert()
+                .success();
+    fs::remove_file("output_align_2.bin").unwrap();
+}
+
+#[test]
+fn output_align_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/output_align_3.brink")
+                .arg("-o output_align_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_32]"));
+    let _ = fs::remove_file("output_align_3.bin");
+}
+#[test]
+fn is_pow2_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/is_pow2_1.brink")
+                .arg("-o is_pow2_1.bin")
+                .assert()
+                .success();
+    fs::remove_file("is_pow2_1.bin").unwrap();
+}
+
+#[test]
+fn is_pow2_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/is_pow2_2.brink")
+                .arg("-o is_pow2_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_2]"));
+    let _ = fs::remove_file("is_pow2_2.bin");
+}
+#[test]
+fn once_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/once_1.brink")
+                .arg("-o once_1.bin")
+                .assert()
+                .success();
+    let bin = fs::read("once_1.bin").unwrap();
+    assert_eq!(bin, [0x78, 0x56, 0x34, 0x12, 1]);
+    fs::remove_file("once_1.bin").unwrap();
+}
+
+#[test]
+fn once_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/once_2.brink")
+                .arg("-o once_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_70]"));
+    let _ = fs::remove_file("once_2.bin");
+}
+
+#[test]
+fn once_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/once_3.brink")
+                .arg("-o once_3.bin")
+                .assert()
+                .success();
+    let bin = fs::read("once_3.bin").unwrap();
+    assert_eq!(bin, [2]);
+    fs::remove_file("once_3.bin").unwrap();
+}
+#[test]
+fn list_labels_1() {
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/list_labels_1.brink")
+                .arg("-o list_labels_1.bin")
+                .arg("--list-labels")
+                .assert()
+                .success();
+    let stdout = String::from_utf8_lossy(&cmd.get_output().stdout).to_string();
+    assert!(stdout.contains("start: abs 0x1000, img 0x0, defined at tests/list_labels_1.brink:8:5"));
+    assert!(stdout.contains("bar_end: abs 0x1006, img 0x6, defined at tests/list_labels_1.brink:4:5"));
+    assert!(stdout.contains("the_end: abs 0x1006, img 0x6, defined at tests/list_labels_1.brink:11:5"));
+    // Listing labels does not write an output file
+    assert!(fs::metadata("list_labels_1.bin").is_err());
+}
+
+#[test]
+fn split_sections_1() {
+    let _ = fs::remove_dir_all("split_sections_1");
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/split_sections_1.brink")
+                .arg("-o split_sections_1.bin")
+                .arg("--split-sections=split_sections_1")
+                .assert()
+                .success();
+    assert_eq!(fs::read("split_sections_1/bar.bin").unwrap(), b"Hello");
+    assert_eq!(fs::read("split_sections_1/bar_2.bin").unwrap(), b"Hello");
+    // Split file sizes match sizeof in the source
+    assert_eq!(fs::read("split_sections_1/baz.bin").unwrap(), [0x34, 0x12]);
+    // The combined output is still written
+    assert_eq!(fs::read("split_sections_1.bin").unwrap().len(), 12);
+    fs::remove_dir_all("split_sections_1").unwrap();
+    fs::remove_file("split_sections_1.bin").unwrap();
+}
+
+#[test]
+fn clamp_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/clamp_1.brink")
+                .arg("-o clamp_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("clamp_1.bin").unwrap(), [0xFF, 0, 0, 0, 0]);
+    fs::remove_file("clamp_1.bin").unwrap();
+}
+
+#[test]
+fn clamp_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/clamp_2.brink")
+                .arg("-o clamp_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_76]"));
+    let _ = fs::remove_file("clamp_2.bin");
+}
+
+#[test]
+fn clamp_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/clamp_3.brink")
+                .arg("-o clamp_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_45]"));
+    let _ = fs::remove_file("clamp_3.bin");
+}
+
+#[test]
+fn clamp_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/clamp_4.brink")
+                .arg("-o clamp_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_36]"));
+    let _ = fs::remove_file("clamp_4.bin");
+}
+
+#[test]
+fn clamp_5() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/clamp_5.brink")
+                .arg("-o clamp_5.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_35]"));
+    let _ = fs::remove_file("clamp_5.bin");
+}
+
+#[test]
+fn abs_value_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/abs_value_1.brink")
+                .arg("-o abs_value_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("abs_value_1.bin").unwrap(), [3]);
+    fs::remove_file("abs_value_1.bin").unwrap();
+}
+
+#[test]
+fn abs_value_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/abs_value_2.brink")
+                .arg("-o abs_value_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_46]"));
+    let _ = fs::remove_file("abs_value_2.bin");
+}
+
+#[test]
+fn section_depth_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/section_depth_1.brink")
+                .arg("-o section_depth_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_38]"))
+                .stderr(predicates::str::contains("s63 -> s64 -> s65"));
+    let _ = fs::remove_file("section_depth_1.bin");
+}
+
+#[test]
+fn section_depth_2() {
+    // Deep, but legal section nesting does not exceed the expression depth
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/section_depth_2.brink")
+                .arg("-o section_depth_2.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("section_depth_2.bin").unwrap(), [1]);
+    fs::remove_file("section_depth_2.bin").unwrap();
+}
+
+#[test]
+fn output_addr_neg_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/output_addr_neg_1.brink")
+                .arg("-o output_addr_neg_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_39]"))
+                .stderr(predicates::str::contains("cannot be negative"));
+    let _ = fs::remove_file("output_addr_neg_1.bin");
+}
+
+#[test]
+fn label_collision_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/label_collision_1.brink")
+                .arg("-o label_collision_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_40]"))
+                .stderr(predicates::str::contains("Label name 'bar' is already the name of a section"));
+    let _ = fs::remove_file("label_collision_1.bin");
+}
+
+#[test]
+fn label_collision_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/label_collision_2.brink")
+                .arg("-o label_collision_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_40]"))
+                .stderr(predicates::str::contains("Label name 'bar' is already the name of a section"));
+    assert!(fs::metadata("label_collision_2.bin").is_err());
+}
+
+#[test]
+fn count_bits_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/count_bits_1.brink")
+                .arg("-o count_bits_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("count_bits_1.bin").unwrap(), [1]);
+    fs::remove_file("count_bits_1.bin").unwrap();
+}
+
+#[test]
+fn relax_types_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/relax_types_1.brink")
+                .arg("-o relax_types_1.bin")
+                .arg("--relax-types")
+                .assert()
+                .success()
+                .stderr(predicates::str::contains("[IRDB_22]"));
+    assert_eq!(fs::read("relax_types_1.bin").unwrap(), [8, 0x10]);
+    fs::remove_file("relax_types_1.bin").unwrap();
+}
+
+#[test]
+fn relax_types_2() {
+    // Without --relax-types, mixing types is an error
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/relax_types_1.brink")
+                .arg("-o relax_types_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_1]"));
+    let _ = fs::remove_file("relax_types_2.bin");
+}
+
+#[test]
+fn wr_xor_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wr_xor_1.brink")
+                .arg("-o wr_xor_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("wr_xor_1.bin").unwrap(),
+               [b'a', b'b', b'c', b'x', b'y', 0x0F ^ b'x', 0xF0 ^ b'y', 0xFF ^ b'x']);
+    fs::remove_file("wr_xor_1.bin").unwrap();
+}
+
+#[test]
+fn wr_xor_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wr_xor_2.brink")
+                .arg("-o wr_xor_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_47]"));
+    let _ = fs::remove_file("wr_xor_2.bin");
+}
+
+#[test]
+fn dump_tokens_1() {
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/dump_tokens_1.brink")
+                .arg("--dump-tokens")
+                .arg("--dump-ast-text")
+                .arg("-o dump_tokens_1.bin")
+                .assert()
+                .success();
+    let stdout = String::from_utf8(cmd.get_output().stdout.clone()).unwrap();
+    assert!(stdout.contains("L3: Wr8 wr8"));
+    assert!(stdout.contains("L3:     wr8"));
+    fs::remove_file("dump_tokens_1.bin").unwrap();
+}
+
+#[test]
+fn fuzz_config_1() {
+    let config = process::FuzzConfig::default();
+
+    // A large alignment would normally produce a 256MB image
+    let src = "section foo { wr8 1; align 0x10000000; } output foo;";
+    assert!(process::process_fuzz(src, &config).is_none());
+
+    // Includes and file writes are rejected
+    let src = "include \"tests/include/dup_x.brink\"; section foo { wr8 1; } output foo;";
+    assert!(process::process_fuzz(src, &config).is_none());
+    let src = "section foo { wrf \"tests/verify_1.bin\"; } output foo;";
+    assert!(process::process_fuzz(src, &config).is_none());
+    // Even a file write that never executes
+    let src = "section foo { wr8 1; if (0) { wrf \"tests/verify_1.bin\"; } } output foo;";
+    assert!(process::process_fuzz(src, &config).is_none());
+
+    let src = "section foo { wr8 1; align 4; } output foo;";
+    assert_eq!(process::process_fuzz(src, &config), Some(vec![1, 0, 0, 0]));
+
+    // Nested repeats that expand to 9 million operations, but no output
+    let src = "section foo { repeat (1000000) { repeat (9) { assert 1; } } } output foo;";
+    assert!(process::process_fuzz(src, &config).is_none());
+}
+
+#[test]
+fn align_value_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/align_value_1.brink")
+                .arg("-o align_value_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("align_value_1.bin").unwrap(), [8, 4, 0, 0, 0, 0, 0]);
+    fs::remove_file("align_value_1.bin").unwrap();
+}
+
+#[test]
+fn align_value_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/align_value_2.brink")
+                .arg("-o align_value_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_51]"));
+    let _ = fs::remove_file("align_value_2.bin");
+}
+
+#[test]
+fn align_value_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/align_value_3.brink")
+                .arg("-o align_value_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_77]"));
+    let _ = fs::remove_file("align_value_3.bin");
+}
+
+#[test]
+fn align_value_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/align_value_4.brink")
+                .arg("-o align_value_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_50]"));
+    let _ = fs::remove_file("align_value_4.bin");
+}
+
+#[test]
+fn paren_1() {
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/paren_1.brink")
+                .arg("-o paren_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_54]"));
+    // Strip the terminal color escape sequences
+    let stderr = String::from_utf8(cmd.get_output().stderr.clone()).unwrap();
+    let mut plain = String::new();
+    let mut chars = stderr.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+    // Secondary label '-' on the open paren, primary label '^' on the semicolon
+    assert!(plain.contains("    assert (1 + 2;\n"));
+    assert!(plain.contains("│            -     ^\n"));
+    let _ = fs::remove_file("paren_1.bin");
+}
+
+#[test]
+fn quiet_success_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/quiet_success_1.brink")
+                .arg("-o quiet_success_1.bin")
+                .arg("--relax-types")
+                .arg("--quiet-success")
+                .assert()
+                .success()
+                .stdout("")
+                .stderr("");
+    assert_eq!(fs::read("quiet_success_1.bin").unwrap(), [8]);
+    fs::remove_file("quiet_success_1.bin").unwrap();
+}
+
+#[test]
+fn quiet_success_2() {
+    // Deferred warnings print along with the error
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/quiet_success_2.brink")
+                .arg("-o quiet_success_2.bin")
+                .arg("--relax-types")
+                .arg("--quiet-success")
+                .assert()
+                .failure()
+                .stdout("")
+                .stderr(predicates::str::contains("[IRDB_22]"))
+                .stderr(predicates::str::contains("[EXEC_2]"));
+    let _ = fs::remove_file("quiet_success_2.bin");
+}
+
+#[test]
+fn less_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/less_1.brink")
+                .arg("-o less_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("less_1.bin").unwrap(), [1, 0]);
+    fs::remove_file("less_1.bin").unwrap();
+}
+
+#[test]
+fn greater_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/greater_1.brink")
+                .arg("-o greater_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("greater_1.bin").unwrap(), [1, 0]);
+    fs::remove_file("greater_1.bin").unwrap();
+}
+
+#[test]
+fn less_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/less_2.brink")
+                .arg("-o less_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_2]"));
+    let _ = fs::remove_file("less_2.bin");
+}
+
+#[test]
+fn compare_lex_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/compare_lex_1.brink")
+                .arg("-o compare_lex_1.bin")
+                .assert()
+                .success();
+    fs::remove_file("compare_lex_1.bin").unwrap();
+}
+
+#[test]
+fn negate_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/negate_1.brink")
+                .arg("-o negate_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("negate_1.bin").unwrap(), [0xFC, 0, 0, 0, 0]);
+    fs::remove_file("negate_1.bin").unwrap();
+}
+
+#[test]
+fn negate_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/negate_2.brink")
+                .arg("-o negate_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_52]"));
+    let _ = fs::remove_file("negate_2.bin");
+}
+
+#[test]
+fn negate_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/negate_3.brink")
+                .arg("-o negate_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_52]"));
+    let _ = fs::remove_file("negate_3.bin");
+}
+
+#[test]
+fn input_glob_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("--input-glob=tests/glob/*.brink")
+                .arg("--output-dir=input_glob_1")
+                .assert()
+                .success();
+    assert_eq!(fs::read("input_glob_1/glob_a.bin").unwrap(), [1]);
+    assert_eq!(fs::read("input_glob_1/glob_b.bin").unwrap(), [2, 2]);
+    fs::remove_dir_all("input_glob_1").unwrap();
+}
+
+#[test]
+fn input_glob_2() {
+    // Continue past the failed file, then report it
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("--input-glob=tests/glob_fail/*.brink")
+                .arg("--output-dir=input_glob_2")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_2]"))
+                .stderr(predicates::str::contains("1 of 2 input files failed"));
+    assert!(fs::metadata("input_glob_2/glob_a.bin").is_err());
+    assert_eq!(fs::read("input_glob_2/glob_b.bin").unwrap(), [3]);
+    fs::remove_dir_all("input_glob_2").unwrap();
+}
+
+#[test]
+fn input_glob_3() {
+    // Stop at the failed file
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("--input-glob=tests/glob_fail/*.brink")
+                .arg("--output-dir=input_glob_3")
+                .arg("--fail-fast")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_2]"));
+    assert!(fs::metadata("input_glob_3/glob_b.bin").is_err());
+    let _ = fs::remove_dir_all("input_glob_3");
+}
+
+#[test]
+fn input_glob_4() {
+    // Without an output directory, every input would write output.bin
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("--input-glob=tests/glob/*.brink")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("--output-dir"));
+}
+
+#[test]
+fn bit_not_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/bit_not_1.brink")
+                .arg("-o bit_not_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("bit_not_1.bin").unwrap(),
+               [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xA5, 0, 0, 0, 0]);
+    fs::remove_file("bit_not_1.bin").unwrap();
+}
+
+#[test]
+fn header_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/header_1.brink")
+                .arg("-o header_1.bin")
+                .arg("--deterministic")
+                .assert()
+                .success();
+    let mut expected = b"Built by brink\nSize: 0x000000000000002A\n".to_vec();
+    expected.extend_from_slice(&[0xAA, 0xAA]);
+    assert_eq!(fs::read("header_1.bin").unwrap(), expected);
+    fs::remove_file("header_1.bin").unwrap();
+}
+
+#[test]
+fn header_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/header_1.brink")
+                .arg("-o header_2.bin")
+                .assert()
+                .success();
+    let out = fs::read("header_2.bin").unwrap();
+    let text = String::from_utf8_lossy(&out);
+    assert!(text.starts_with("Built by brink\nSize: 0x0000000000000048\nDate: "));
+    assert!(out.ends_with(b" UTC\n\xAA\xAA"));
+    fs::remove_file("header_2.bin").unwrap();
+}
+
+#[test]
+fn logical_not_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/logical_not_1.brink")
+                .arg("-o logical_not_1.bin")
+                .assert()
+                .success();
+    assert_eq!(fs::read("logical_not_1.bin").unwrap(), [1, 0, 0, 0, 0, 0]);
+    fs::remove_file("logical_not_1.bin").unwrap();
+}
+
+#[test]
+fn top_level_write_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/top_level_write_1.brink")
+                .arg("-o top_level_write_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_43]"))
+                .stderr(predicates::str::contains("must be inside a section"));
+}
+
+#[test]
+fn wrx_be_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wrx_be_1.brink")
+                .arg("-o wrx_be_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("wrx_be_1.bin").unwrap();
+    assert_eq!(bytevec, [0x56, 0x78,
+                         0x34, 0x56, 0x78,
+                         0x12, 0x34, 0x56, 0x78,
+                         0x00, 0x12, 0x34, 0x56, 0x78,
+                         0x00, 0x00, 0x12, 0x34, 0x56, 0x78,
+                         0x00, 0x00, 0x00, 0x12, 0x34, 0x56, 0x78,
+                         0x00, 0x00, 0x00, 0x00, 0x12, 0x34, 0x56, 0x78]);
+    fs::remove_file("wrx_be_1.bin").unwrap();
+}
+
+#[test]
+fn wrx_be_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wrx_be_2.brink")
+                .arg("-o wrx_be_2.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("wrx_be_2.bin").unwrap();
+    assert_eq!(bytevec, [0x12, 0x34, 0x56, 0x78, 0xAB, 0xCD, 0xEF, 0x42,
+                         0xAB, 0xCD, 0xEF, 0x42,
+                         0xCD, 0xEF, 0x42,
+                         0xEF, 0x42, 0xEF, 0x42,
+                         0xFF, 0xFE]);
+    fs::remove_file("wrx_be_2.bin").unwrap();
+}
+
+#[test]
+fn emit_ir_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/emit_ir_1.brink")
+                .arg("-o emit_ir_1.bin")
+                .arg("--emit-ir")
+                .arg("emit_ir_1.bir")
+                .assert()
+                .success();
+
+    let ir = fs::read_to_string("emit_ir_1.bir").unwrap();
+    assert!(ir.starts_with("brink-ir 1\n"));
+    assert!(ir.contains("operand 4 QuotedString const \"a\\n\""));
+
+    // Operations appear in linear order
+    let ops: Vec<&str> = ir.lines()
+                           .filter(|l| l.starts_with("op "))
+                           .map(|l| l.split(' ').nth(2).unwrap())
+                           .collect();
+    assert_eq!(ops, ["SectionStart", "Add", "Wr8", "Wrs", "SectionEnd"]);
+    fs::remove_file("emit_ir_1.bir").unwrap();
+    fs::remove_file("emit_ir_1.bin").unwrap();
+}
+
+#[test]
+fn endian_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/endian_1.brink")
+                .arg("-o endian_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("endian_1.bin").unwrap();
+    assert_eq!(bytevec, [0x12, 0x34,
+                         0x34, 0x12,
+                         0x00, 0x00, 0x12, 0x34,
+                         0x12, 0x34, 0x56,
+                         0x12, 0x34,
+                         0x34, 0x12,
+                         0x12, 0x34]);
+    fs::remove_file("endian_1.bin").unwrap();
+}
+
+#[test]
+fn endian_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/endian_2.brink")
+                .arg("-o endian_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_44]"));
+}
+
+#[test]
+fn sizeof_empty_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/sizeof_empty_1.brink")
+                .arg("-o sizeof_empty_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("sizeof_empty_1.bin").unwrap();
+    assert_eq!(bytevec, [0, 0, 0, 0, 0, 0, 0x15, 1, 1, 1, 1, 1]);
+    fs::remove_file("sizeof_empty_1.bin").unwrap();
+}
+
+#[test]
+fn sizeof_empty_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/sizeof_empty_2.brink")
+                .arg("-o sizeof_empty_2.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("sizeof_empty_2.bin").unwrap();
+    assert_eq!(bytevec, [0x28, 0, 0x28, 0, 0x28, 0, 0, 0]);
+    fs::remove_file("sizeof_empty_2.bin").unwrap();
+}
+
+#[test]
+fn wrf_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wrf_dir/wrf_4.brink")
+                .arg("-o wrf_4.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("wrf_4.bin").unwrap();
+    assert_eq!(bytevec, b"AB\x01\x02\x03\x04\x0DHello!");
+    fs::remove_file("wrf_4.bin").unwrap();
+}
+
+#[test]
+fn stage_1() {
+    // Stopping after the IR stage skips the failing assert
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/stage_1.brink")
+                .arg("-o stage_1.bin")
+                .arg("--stage")
+                .arg("ir")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains("op 4 Assert"));
+    assert!(fs::metadata("stage_1.bin").is_err());
+
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/stage_1.brink")
+                .arg("-o stage_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_2]"));
+}
+
+#[test]
+fn string_concat_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/string_concat_1.brink")
+                .arg("-o string_concat_1.bin")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains("Hello, world\n"));
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("string_concat_1.bin").unwrap();
+    assert_eq!(bytevec, b"foobarabc-3");
+    fs::remove_file("string_concat_1.bin").unwrap();
+}
+
+#[test]
+fn string_concat_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/string_concat_2.brink")
+                .arg("-o string_concat_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_24]"));
+}
+
+#[test]
+fn fill_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/fill_1.brink")
+                .arg("-o fill_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("fill_1.bin").unwrap();
+    assert_eq!(bytevec.len(), 0x10008);
+    assert_eq!(bytevec[0..4], [0x01, 0xFF, 0xFF, 0xFF]);
+    assert!(bytevec[4..0x10004].iter().all(|&b| b == 0x55));
+    assert_eq!(bytevec[0x10004..], [0xFF, 0xFF, 0x42, 0x42]);
+    fs::remove_file("fill_1.bin").unwrap();
+}
+
+#[test]
+fn fill_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/fill_2.brink")
+                .arg("-o fill_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_53]"));
+}
+
+#[test]
+fn assert_in_range_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/assert_in_range_1.brink")
+                .arg("-o assert_in_range_1.bin")
+                .assert()
+                .success();
+    fs::remove_file("assert_in_range_1.bin").unwrap();
+}
+
+#[test]
+fn assert_in_range_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/assert_in_range_2.brink")
+                .arg("-o assert_in_range_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_54]"))
+                .stderr(predicates::str::contains("Value 12 is not in range 2 to 10"));
+}
+
+#[test]
+fn append_1() {
+    let _ = fs::remove_file("append_1.bin");
+    for _ in 0..2 {
+        let _cmd = Command::cargo_bin("brink")
+                    .unwrap()
+                    .arg("tests/append_1.brink")
+                    .arg("-o append_1.bin")
+                    .arg("--append")
+                    .assert()
+                    .success();
+    }
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("append_1.bin").unwrap();
+    assert_eq!(bytevec, b"AB\x12AB\x12");
+    fs::remove_file("append_1.bin").unwrap();
+}
+
+#[test]
+fn sizeof_span_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/sizeof_span_1.brink")
+                .arg("-o sizeof_span_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("sizeof_span_1.bin").unwrap();
+    assert_eq!(bytevec, b"\x08record\x34\x12\x08\x09\x00");
+    fs::remove_file("sizeof_span_1.bin").unwrap();
+}
+
+#[test]
+fn sizeof_span_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/sizeof_span_2.brink")
+                .arg("-o sizeof_span_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_55]"));
+}
+
+#[test]
+fn long_string_1() {
+    // Build a multi-megabyte single-line string with escapes.  A
+    // quadratic lexer or escape scanner would take far too long here.
+    let count = 400_000;
+    let src = format!("section foo {{\n    wrs \"{}\";\n}}\noutput foo;\n",
+                      "ab\\\"c\\n".repeat(count));
+    fs::write("long_string_1.brink", src).unwrap();
+
+    let start = std::time::Instant::now();
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("long_string_1.brink")
+                .arg("-o long_string_1.bin")
+                .assert()
+                .success();
+    assert!(start.elapsed().as_secs() < 30);
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("long_string_1.bin").unwrap();
+    assert_eq!(bytevec, "ab\"c\n".repeat(count).as_bytes());
+    fs::remove_file("long_string_1.bin").unwrap();
+    fs::remove_file("long_string_1.brink").unwrap();
+}
+
+#[test]
+fn crc32_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/crc32_1.brink")
+                .arg("-o crc32_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("crc32_1.bin").unwrap();
+    assert_eq!(bytevec, b"123456789\x26\x39\xF4\xCB\x26");
+    fs::remove_file("crc32_1.bin").unwrap();
+}
+
+#[test]
+fn crc32_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/crc32_2.brink")
+                .arg("-o crc32_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_58]"));
+}
+
+#[test]
+fn crc32_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/crc32_3.brink")
+                .arg("-o crc32_3.bin")
+                .assert()
+                .success();
+
+    // crc32(a) is 0xB6CC4292 and crc32(b) covers a and its CRC
+    let bytevec = fs::read("crc32_3.bin").unwrap();
+    assert_eq!(bytevec, [0x01, 0x02, 0x92, 0x42, 0xCC, 0xB6, 0x1C, 0xDF, 0x44, 0x21]);
+    fs::remove_file("crc32_3.bin").unwrap();
+}
+
+#[test]
+fn bit_reverse_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/bit_reverse_1.brink")
+                .arg("-o bit_reverse_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("bit_reverse_1.bin").unwrap();
+    assert_eq!(bytevec, b"\x80\x0F\xFF\x00\xC0");
+    fs::remove_file("bit_reverse_1.bin").unwrap();
+}
+
+#[test]
+fn bit_reverse_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/bit_reverse_2.brink")
+                .arg("-o bit_reverse_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_59]"));
+}
+
+#[test]
+fn assemble_1() {
+    // Assemble in memory through the library entry point
+    let src = "section foo { wrs \"Hi\"; wr16 0x1234; wr8 sizeof(foo); }\noutput foo;\n";
+    let bytevec = process::assemble("assemble_1.brink", src,
+                                     &process::Options::default()).unwrap();
+    assert_eq!(bytevec, b"Hi\x34\x12\x05");
+}
+
+#[test]
+fn assemble_2() {
+    // Errors return an Err without writing anything
+    let src = "section foo { assert 1 == 2; }\noutput foo;\n";
+    let err = process::assemble("assemble_2.brink", src,
+                                &process::Options::default()).unwrap_err();
+    assert!(err.to_string().contains("[PROC_4]"));
+}
+
+#[test]
+fn to_i64_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/to_i64_3.brink")
+                .arg("-o to_i64_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_12]"))
+                .stderr(predicates::str::contains("Can't convert from QuotedString to I64"));
+}
+
+#[test]
+fn to_u64_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/to_u64_2.brink")
+                .arg("-o to_u64_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_17]"))
+                .stderr(predicates::str::contains("Can't convert from QuotedString to U64"));
+}
+
+#[test]
+fn assemble_to_1() {
+    // Execute into any Write, here a Vec<u8>
+    let src = "section foo { wr8 1; wr bar; } section bar { wrs \"ab\"; }\n\
+               output foo;\n";
+    let mut bytevec: Vec<u8> = Vec::new();
+    process::assemble_to("assemble_to_1.brink", src, &process::Options::default(),
+                         &mut bytevec).unwrap();
+    assert_eq!(bytevec, b"\x01ab");
+}
+
+#[test]
+fn assemble_to_2() {
+    // Discarding the output is harmless
+    let src = "section foo { wrs \"Hello\"; wr32 sizeof(foo); }\noutput foo;\n";
+    process::assemble_to("assemble_to_2.brink", src, &process::Options::default(),
+                         &mut std::io::sink()).unwrap();
+}
+
+#[test]
+fn strict_section_order_1() {
+    // Forward references are valid by default
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/strict_section_order_1.brink")
+                .arg("-o strict_section_order_1.bin")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let bytevec = fs::read("strict_section_order_1.bin").unwrap();
+    assert_eq!(bytevec, b"Hdr\x01");
+    fs::remove_file("strict_section_order_1.bin").unwrap();
+}
+
+#[test]
+fn strict_section_order_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/strict_section_order_1.brink")
+                .arg("-o strict_section_order_2.bin")
+                .arg("--strict-section-order")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_46]"))
+                .stderr(predicates::str::contains("payload"));
+}
+
+#[test]
+fn ihex_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/ihex_1.brink")
+                .arg("-o ihex_1.hex")
+                .arg("--format")
+                .arg("ihex")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let hex = fs::read_to_string("ihex_1.hex").unwrap();
+    assert_eq!(hex, ":1080000030313233343536373839414243444546CE\n\
+                     :038010005555556E\n\
+                     :00000001FF\n");
+    fs::remove_file("ihex_1.hex").unwrap();
+}
+
+#[test]
+fn ihex_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/ihex_2.brink")
+                .arg("-o ihex_2.hex")
+                .arg("--format")
+                .arg("ihex")
+                .assert()
+                .success();
+
+    // Records split at the 64KB boundary with extended linear addresses
+    let hex = fs::read_to_string("ihex_2.hex").unwrap();
+    assert_eq!(hex, ":020000040001F9\n\
+                     :08FFF800AAAAAAAAAAAAAAAAB1\n\
+                     :020000040002F8\n\
+                     :08000000AAAAAAAAAAAAAAAAA8\n\
+                     :00000001FF\n");
+    fs::remove_file("ihex_2.hex").unwrap();
+}
+
+#[test]
+fn print_flush_1() {
+    // Send stdout and stderr to the same file to observe their order
+    let log = fs::File::create("print_flush_1.log").unwrap();
+    let status = std::process::Command::new(assert_cmd::cargo::cargo_bin("brink"))
+            .arg("tests/print_flush_1.brink")
+            .args(["-o", "print_flush_1.bin"])
+            .stdout(log.try_clone().unwrap())
+            .stderr(log)
+            .status()
+            .unwrap();
+    assert!(!status.success());
+
+    let text = fs::read_to_string("print_flush_1.log").unwrap();
+    let print_pos = text.find("Before the assert").unwrap();
+    let err_pos = text.find("[EXEC_").unwrap();
+    assert!(print_pos < err_pos);
+    fs::remove_file("print_flush_1.log").unwrap();
+}
+
+#[test]
+fn srec_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/srec_1.brink")
+                .arg("-o srec_1.s")
+                .arg("--format")
+                .arg("srec")
+                .assert()
+                .success();
+
+    // Verify output file is correct.  If so, then clean up.
+    let srec = fs::read_to_string("srec_1.s").unwrap();
+    assert_eq!(srec, "S00F0000737265635F312E6272696E6B6F\n\
+                      S113800030313233343536373839414243444546CA\n\
+                      S10680105555556A\n\
+                      S90380007C\n");
+    fs::remove_file("srec_1.s").unwrap();
+}
+
+#[test]
+fn srec_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/srec_2.brink")
+                .arg("-o srec_2.s")
+                .arg("--format")
+                .arg("srec")
+                .assert()
+                .success();
+
+    // Addresses above 16MB need S3 data and an S7 termination record
+    let srec = fs::read_to_string("srec_2.s").unwrap();
+    assert_eq!(srec, "S00F0000737265635F322E6272696E6B6E\n\
+                      S30901000000AAAAAAAA4D\n\
+                      S70501000000F9\n");
+    fs::remove_file("srec_2.s").unwrap();
+}
+
+#[test]
+fn srec_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/srec_3.brink")
+                .arg("-o srec_3.s")
+                .arg("--format")
+                .arg("srec")
+                .assert()
+                .success();
+
+    // The top address 0xFFFFFF fits S2 data and an S8 termination record
+    let srec = fs::read_to_string("srec_3.s").unwrap();
+    assert_eq!(srec, "S00F0000737265635F332E6272696E6B6D\n\
+                      S208FFFFFCAAAAAAAA55\n\
+                      S804FFFFFC01\n");
+    fs::remove_file("srec_3.s").unwrap();
+}
+
+#[test]
+fn sizeof_stable_1() {
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/sizeof_stable_1.brink")
+                .arg("-o sizeof_stable_1.bin")
+                .arg("--dump-fixedpoint")
+                .assert()
+                .success();
+
+    // Reporting zero for the transient size took 5 passes.  The last
+    // stable size settles in 4.
+    let stdout = String::from_utf8_lossy(&cmd.get_output().stdout).to_string();
+    assert!(stdout.contains("fixedpoint: converged after 4 passes"));
+    assert!(!stdout.contains("fixedpoint pass 5:"));
+
+    let mut expected = vec![0u8; 24];
+    expected.extend_from_slice(b"0123456789\xFF\xFF");
+    let bytevec = fs::read("sizeof_stable_1.bin").unwrap();
+    assert_eq!(bytevec, expected);
+    fs::remove_file("sizeof_stable_1.bin").unwrap();
+}
+
+#[test]
+fn carray_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/carray_1.brink")
+                .arg("-o carray_1.bin")
+                .assert()
+                .success();
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/carray_1.brink")
+                .arg("-o carray_1.h")
+                .arg("--format")
+                .arg("carray")
+                .assert()
+                .success();
+
+    // The array bytes and length macro must match the binary output
+    let bytevec = fs::read("carray_1.bin").unwrap();
+    let header = fs::read_to_string("carray_1.h").unwrap();
+    assert!(header.starts_with("const unsigned char firmware[] = {\n"));
+    assert!(header.ends_with(&format!("}};\n#define FIRMWARE_LEN {}\n", bytevec.len())));
+    let array_bytes: Vec<u8> = header.split(|c: char| c.is_whitespace() || c == ',')
+            .filter_map(|tok| tok.strip_prefix("0x"))
+            .map(|hex| u8::from_str_radix(hex, 16).unwrap())
+            .collect();
+    assert_eq!(array_bytes, bytevec);
+    assert_eq!(header.lines().nth(1).unwrap().matches("0x").count(), 16);
+    fs::remove_file("carray_1.bin").unwrap();
+    fs::remove_file("carray_1.h").unwrap();
+}
+
+#[test]
+fn carray_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/carray_1.brink")
+                .arg("-o carray_2.h")
+                .arg("--format")
+                .arg("carray")
+                .arg("--array-name")
+                .arg("blob")
+                .arg("--array-type")
+                .arg("static const uint8_t")
+                .assert()
+                .success();
+
+    let header = fs::read_to_string("carray_2.h").unwrap();
+    assert!(header.starts_with("static const uint8_t blob[] = {\n"));
+    assert!(header.ends_with("#define BLOB_LEN 20\n"));
+    fs::remove_file("carray_2.h").unwrap();
+}
+
+#[test]
+fn truncation_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/truncation_1.brink")
+                .arg("-o truncation_1.bin")
+                .assert()
+                .success()
+                .stderr(predicates::str::contains("[EXEC_61]"));
+
+    let bytevec = fs::read("truncation_1.bin").unwrap();
+    assert_eq!(bytevec, [0x00, 0x45, 0x23, 0xFF, 0xFF]);
+    fs::remove_file("truncation_1.bin").unwrap();
+}
+
+#[test]
+fn truncation_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/truncation_2.brink")
+                .arg("-o truncation_2.bin")
+                .assert()
+                .success()
+                .stderr(predicates::str::contains("[EXEC_61]").not());
+
+    let bytevec = fs::read("truncation_2.bin").unwrap();
+    assert_eq!(bytevec, [0xFF, 0x80, 0xFF, 0xFF, 0xFF]);
+    fs::remove_file("truncation_2.bin").unwrap();
+}
+
+#[test]
+fn truncation_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/truncation_1.brink")
+                .arg("-o truncation_3.bin")
+                .arg("--no-truncation-warnings")
+                .assert()
+                .success()
+                .stderr(predicates::str::contains("[EXEC_61]").not());
+
+    fs::remove_file("truncation_3.bin").unwrap();
+}
+
+#[test]
+fn hexdump_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/hexdump_1.brink")
+                .arg("-o hexdump_1.bin")
+                .arg("--hexdump")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains(
+                    "00001000: 4865 6c6c 6f2c 2077 6f72 6c64 2100 0102  Hello, world!...\n\
+                     00001010: 7f41                                     .A\n"));
+
+    let bytevec = fs::read("hexdump_1.bin").unwrap();
+    assert_eq!(bytevec.len(), 18);
+    fs::remove_file("hexdump_1.bin").unwrap();
+}
+
+#[test]
+fn hexdump_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/hexdump_1.brink")
+                .arg("-o hexdump_2.bin")
+                .arg("--hexdump")
+                .arg("-q")
+                .assert()
+                .success()
+                .stdout(predicates::str::is_empty());
+
+    fs::remove_file("hexdump_2.bin").unwrap();
+}
+
+#[test]
+fn join_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/join_1.brink")
+                .arg("-o join_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("join_1.bin").unwrap();
+    assert_eq!(bytevec, b"a, bc, 30x10");
+    fs::remove_file("join_1.bin").unwrap();
+}
+
+#[test]
+fn join_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/join_2.brink")
+                .arg("-o join_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_20]"));
+}
+
+#[test]
+fn define_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/define_1.brink")
+                .arg("-o define_1.bin")
+                .args(["-D", "VERSION=0x01020304", "-D", "REV=4", "-D", "NAME=boot"])
+                .args(["--define", "MAJOR=1", "--define", "MINOR=-2"])
+                .assert()
+                .success();
+
+    let bytevec = fs::read("define_1.bin").unwrap();
+    assert_eq!(bytevec, b"\x04\x03\x02\x01\x05boot1.-2");
+    fs::remove_file("define_1.bin").unwrap();
+}
+
+#[test]
+fn define_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/define_1.brink")
+                .arg("-o define_2.bin")
+                .args(["-D", "VERSION=1", "-D", "REV=4", "-D", "NAME=boot", "-D", "MAJOR=1"])
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_29]"));
+}
+
+#[test]
+fn define_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/define_1.brink")
+                .arg("-o define_3.bin")
+                .args(["-D", "wr8=1"])
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_24]"));
+}
+
+#[test]
+fn define_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/define_1.brink")
+                .arg("-o define_4.bin")
+                .args(["-D", "COUNT"])
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_14]"));
+}
+
+#[test]
+fn const_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/const_1.brink")
+                .arg("-o const_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("const_1.bin").unwrap();
+    assert_eq!(bytevec, [0xBE, 0xBA, 0xFE, 0xCA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA]);
+    fs::remove_file("const_1.bin").unwrap();
+}
+
+#[test]
+fn const_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/const_2.brink")
+                .arg("-o const_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_48]"));
+}
+
+#[test]
+fn const_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/const_3.brink")
+                .arg("-o const_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[LINEAR_12]"));
+}
+
+#[test]
+fn help_lang_1() {
+    let cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("--help-lang")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains("wrs <expr>"))
+                .stdout(predicates::str::contains("sizeof(<name>"))
+                .stdout(predicates::str::contains("section <name> = <name> ++ <name>"));
+
+    // Every keyword token in the lexer must appear in some line of the
+    // reference.  The wrN and wrNbe entries cover the sized writes.
+    let stdout = String::from_utf8_lossy(&cmd.get_output().stdout).to_string();
+    let words: Vec<&str> = stdout.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                                 .collect();
+    let src = fs::read_to_string("ast/ast.rs").unwrap();
+    for tok in src.split("#[token(\"").skip(1) {
+        let keyword = tok.split('"').next().unwrap();
+        if !keyword.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+            continue; // punctuation
+        }
+        let sized_write = keyword.starts_with("wr") && keyword.len() > 2 &&
+                          keyword[2..].trim_end_matches("be").chars().all(|c| c.is_ascii_digit());
+        let expected = match keyword {
+            k if sized_write && k.ends_with("be") => "wrNbe",
+            _ if sized_write => "wrN",
+            k => k,
+        };
+        assert!(words.contains(&expected), "--help-lang is missing keyword '{}'", keyword);
+    }
+}
+
+#[test]
+fn if_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/if_1.brink")
+                .arg("-o if_1.bin")
+                .args(["-D", "DEBUG=1"])
+                .assert()
+                .success();
+
+    let bytevec = fs::read("if_1.bin").unwrap();
+    assert_eq!(bytevec, b"debug\x05\x06");
+    fs::remove_file("if_1.bin").unwrap();
+
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/if_1.brink")
+                .arg("-o if_1_rel.bin")
+                .args(["-D", "DEBUG=0"])
+                .assert()
+                .success();
+
+    let bytevec = fs::read("if_1_rel.bin").unwrap();
+    assert_eq!(bytevec, b"rel\x03\x04");
+    fs::remove_file("if_1_rel.bin").unwrap();
+}
+
+#[test]
+fn if_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/if_2.brink")
+                .arg("-o if_2.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("if_2.bin").unwrap();
+    assert_eq!(bytevec, [0x02, 0xAA, 0xBB, 0xBB]);
+    fs::remove_file("if_2.bin").unwrap();
+}
+
+#[test]
+fn if_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/if_3.brink")
+                .arg("-o if_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_62]"));
+}
+
+#[test]
+fn if_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/if_4.brink")
+                .arg("-o if_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_50]"));
+}
+
+#[test]
+fn builtin_args_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/builtin_args_1.brink")
+                .arg("-o builtin_args_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_51]"))
+                .stderr(predicates::str::contains("sizeof expects a section or label name"));
+}
+
+#[test]
+fn builtin_args_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/builtin_args_2.brink")
+                .arg("-o builtin_args_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_51]"))
+                .stderr(predicates::str::contains("to_u64 expects an expression"));
+}
+
+#[test]
+fn repeat_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/repeat_1.brink")
+                .arg("-o repeat_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("repeat_1.bin").unwrap();
+    assert_eq!(bytevec.len(), 12);
+    assert_eq!(bytevec, [0x00, 0x00, 0xEE, 0x01, 0x00, 0xEE,
+                         0x02, 0x00, 0xEE, 0x03, 0x00, 0xEE]);
+    fs::remove_file("repeat_1.bin").unwrap();
+}
+
+#[test]
+fn repeat_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/repeat_2.brink")
+                .arg("-o repeat_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[LINEAR_13]"));
+}
+
+#[test]
+fn repeat_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/repeat_3.brink")
+                .arg("-o repeat_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[LINEAR_15]"));
+}
+
+#[test]
+fn repeat_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/repeat_4.brink")
+                .arg("-o repeat_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[LINEAR_20]"));
+}
+
+#[test]
+fn wr_slice_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wr_slice_1.brink")
+                .arg("-o wr_slice_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("wr_slice_1.bin").unwrap();
+    assert_eq!(bytevec, [0xAA, 0x22, 0x33, 0x03, 0x05]);
+    fs::remove_file("wr_slice_1.bin").unwrap();
+}
+
+#[test]
+fn wr_slice_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wr_slice_2.brink")
+                .arg("-o wr_slice_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_64]"));
+}
+
+#[test]
+fn wr_slice_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wr_slice_3.brink")
+                .arg("-o wr_slice_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_73]"));
+    assert!(fs::metadata("wr_slice_3.bin").is_err());
+}
+
+#[test]
+fn wr_slice_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/wr_slice_4.brink")
+                .arg("-o wr_slice_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_72]"));
+    assert!(fs::metadata("wr_slice_4.bin").is_err());
+}
+
+#[test]
+fn pow_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/pow_1.brink")
+                .arg("-o pow_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("pow_1.bin").unwrap();
+    assert_eq!(bytevec, [0x00, 0x04, 0x21, 0xE8, 0x1F, 0x29, 0x52, 0xB4, 0xB8, 0xA8]);
+    fs::remove_file("pow_1.bin").unwrap();
+}
+
+#[test]
+fn pow_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/pow_2.brink")
+                .arg("-o pow_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_65]"));
+}
+
+#[test]
+fn round_to_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/round_to_1.brink")
+                .arg("-o round_to_1.bin")
+                .args(["--round-to", "512"])
+                .assert()
+                .success();
+
+    let bytevec = fs::read("round_to_1.bin").unwrap();
+    assert_eq!(bytevec.len(), 512);
+    assert_eq!(&bytevec[..10], b"0123456789");
+    assert!(bytevec[10..].iter().all(|&b| b == 0));
+    fs::remove_file("round_to_1.bin").unwrap();
+}
+
+#[test]
+fn round_to_2() {
+    // An image that is already a multiple is unchanged
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/round_to_1.brink")
+                .arg("-o round_to_2.bin")
+                .args(["--round-to", "5"])
+                .assert()
+                .success();
+
+    let bytevec = fs::read("round_to_2.bin").unwrap();
+    assert_eq!(bytevec, b"0123456789");
+    fs::remove_file("round_to_2.bin").unwrap();
+}
+
+#[test]
+fn round_to_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/round_to_1.brink")
+                .arg("-o round_to_3.bin")
+                .args(["--round-to", "0"])
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_15]"));
+}
+
+#[test]
+fn minmax_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/minmax_1.brink")
+                .arg("-o minmax_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("minmax_1.bin").unwrap();
+    assert_eq!(bytevec, [0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0x04, 0x06, 0x03, 0x06, 0x06]);
+    fs::remove_file("minmax_1.bin").unwrap();
+}
+
+#[test]
+fn minmax_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/minmax_2.brink")
+                .arg("-o minmax_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_51]"));
+}
+
+#[test]
+fn iteration_count_1() {
+    // The forward sizeof reference in this source takes 3 passes
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/dump_fixedpoint_1.brink")
+                .arg("-o iteration_count_1.bin")
+                .arg("-v")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains("[INFO] Location counters converged after 3 passes"));
+    fs::remove_file("iteration_count_1.bin").unwrap();
+}
+
+#[test]
+fn align_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/align_3.brink")
+                .arg("-o align_3.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("align_3.bin").unwrap();
+    assert_eq!(bytevec, [0xAA, 0xAA, 0xAA, 0x00, 0xBB, 0xBB, 0xBB, 0xFF,
+                         0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    fs::remove_file("align_3.bin").unwrap();
+}
+
+#[test]
+fn align_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/align_4.brink")
+                .arg("-o align_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_67]"));
+}
+
+#[test]
+fn align_5() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/align_5.brink")
+                .arg("-o align_5.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_67]"));
+}
+
+#[test]
+fn at_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/at_1.brink")
+                .arg("-o at_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("at_1.bin").unwrap();
+    assert_eq!(bytevec.len(), 17);
+    assert_eq!(bytevec[..4], [0x78, 0x56, 0x34, 0x12]);
+    assert_eq!(bytevec[16], 0xAA);
+    fs::remove_file("at_1.bin").unwrap();
+}
+
+#[test]
+fn at_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/at_2.brink")
+                .arg("-o at_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_68]"))
+                .stderr(predicates::str::contains("Expected absolute address 0x1004, but the actual address is 0x1003"));
+}
+
+#[test]
+fn pack32_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/pack32_1.brink")
+                .arg("-o pack32_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("pack32_1.bin").unwrap();
+    assert_eq!(bytevec, [0x12, 0x34, 0xEF, 0xBE,
+                         0xF3, 0x00, 0xCD, 0xAB,
+                         0x01, 0x00, 0x00, 0x80]);
+    fs::remove_file("pack32_1.bin").unwrap();
+}
+
+#[test]
+fn pack32_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/pack32_2.brink")
+                .arg("-o pack32_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[LINEAR_18]"));
+}
+
+#[test]
+fn pack32_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/pack32_3.brink")
+                .arg("-o pack32_3.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_69]"));
+}
+
+#[test]
+fn emit_state_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/emit_state_1.brink")
+                .arg("-o emit_state_1.bin")
+                .arg("--emit-internal-state")
+                .arg("emit_state_1.txt")
+                .assert()
+                .success();
+
+    let state = fs::read_to_string("emit_state_1.txt").unwrap();
+    assert!(state.starts_with("brink-state 1\n"));
+    // The wr8 in section bar follows the three byte string
+    assert!(state.contains("loc 3 Wr8 img 0x3 sec 0x0 abs 0x1003\n"));
+    assert_eq!(state.lines().count(), 7);
+    fs::remove_file("emit_state_1.txt").unwrap();
+    fs::remove_file("emit_state_1.bin").unwrap();
+}
+
+#[test]
+fn forward_label_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/forward_label_1.brink")
+                .arg("-o forward_label_1.bin")
+                .assert()
+                .success();
+
+    let bytevec = fs::read("forward_label_1.bin").unwrap();
+    assert_eq!(bytevec, [0x0E, 0x00, 0x00, 0x00, 0x0F, 0x00, 0x00, 0x00,
+                         0x11, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0x01, b'x',
+                         b'y', 0x03]);
+    fs::remove_file("forward_label_1.bin").unwrap();
+}
+
+#[test]
+fn forward_label_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/forward_label_2.brink")
+                .arg("-o forward_label_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[EXEC_49]"));
+}
+
+#[test]
+fn const_collide_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/const_collide_1.brink")
+                .arg("-o const_collide_1.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_53]"))
+                .stderr(predicates::str::contains("already the name of a section"));
+}
+
+#[test]
+fn const_collide_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/const_collide_2.brink")
+                .arg("-o const_collide_2.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[AST_56]"))
+                .stderr(predicates::str::contains("already the name of a label"));
+}
+
+#[test]
+fn ihex_3() {
+    // An encoding error leaves no output file behind
+    let _ = fs::remove_file("ihex_3.hex");
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/ihex_3.brink")
+                .arg("-o ihex_3.hex")
+                .args(["--format", "ihex"])
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("beyond the 32-bit address limit"));
+    assert!(fs::metadata("ihex_3.hex").is_err());
+}
+
+#[test]
+fn append_format_1() {
+    // Record formats are complete files, so they can't be appended
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/ihex_1.brink")
+                .arg("-o append_format_1.hex")
+                .args(["--format", "ihex"])
+                .arg("--append")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("'--append' cannot be used with '--format'"));
+    assert!(fs::metadata("append_format_1.hex").is_err());
+}
+
+#[test]
+fn define_5() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/define_2.brink")
+                .arg("-o define_5.bin")
+                .args(["-D", "s=7"])
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_31]"))
+                .stderr(predicates::str::contains("name of a section"));
+}
+
+#[test]
+fn define_6() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/define_3.brink")
+                .arg("-o define_6.bin")
+                .args(["-D", "lab=7"])
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_31]"))
+                .stderr(predicates::str::contains("name of a label"));
+}
+
+#[test]
+fn define_7() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/define_2.brink")
+                .arg("-o define_7.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_31]"))
+                .stderr(predicates::str::contains("section name 's' is not a value"));
+}
+
+#[test]
+fn const_unused_1() {
+    let assert = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/const_unused_1.brink")
+                .arg("-o const_unused_1.bin")
+                .arg("--warn-unused-const")
+                .assert()
+                .success()
+                .stderr(predicates::str::contains("Constant 'UNUSED' is never used"));
+    // Exactly one warning, for the unused constant only
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+    assert_eq!(stderr.matches("[LINEAR_19]").count(), 1);
+    assert!(!stderr.contains("'USED'"));
+    fs::remove_file("const_unused_1.bin").unwrap();
+}
+
+#[test]
+fn const_unused_2() {
+    // No warning without the option
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/const_unused_1.brink")
+                .arg("-o const_unused_2.bin")
+                .assert()
+                .success()
+                .stderr(predicates::str::contains("[LINEAR_19]").not());
+    fs::remove_file("const_unused_2.bin").unwrap();
+}
+
+#[test]
+fn bytes_per_line_1() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/carray_1.brink")
+                .arg("-o bytes_per_line_1.h")
+                .arg("--format")
+                .arg("carray")
+                .arg("--bytes-per-line")
+                .arg("4")
+                .assert()
+                .success();
+
+    // The 20 byte image wraps into 5 lines of 4 bytes
+    let header = fs::read_to_string("bytes_per_line_1.h").unwrap();
+    let lines: Vec<&str> = header.lines().filter(|line| line.contains("0x")).collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines.iter().all(|line| line.matches("0x").count() == 4));
+    assert_eq!(lines[4], "    0xEF, 0xBE, 0xAD, 0xDE,");
+    fs::remove_file("bytes_per_line_1.h").unwrap();
+}
+
+#[test]
+fn bytes_per_line_2() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/hexdump_1.brink")
+                .arg("-o bytes_per_line_2.bin")
+                .arg("--hexdump")
+                .arg("--bytes-per-line=5")
+                .assert()
+                .success()
+                .stdout(predicates::str::contains(
+                    "00001000: 4865 6c6c 6f  Hello\n\
+                     00001005: 2c20 776f 72  , wor\n\
+                     0000100a: 6c64 2100 01  ld!..\n\
+                     0000100f: 027f 41       ..A\n"));
+
+    fs::remove_file("bytes_per_line_2.bin").unwrap();
+}
+
+#[test]
+fn bytes_per_line_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/carray_1.brink")
+                .arg("-o bytes_per_line_3.h")
+                .arg("--format")
+                .arg("carray")
+                .arg("--bytes-per-line=0")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_20]"));
+    assert!(fs::metadata("bytes_per_line_3.h").is_err());
+}
+
+#[test]
+fn carray_3() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/carray_1.brink")
+                .arg("-o carray_3.h")
+                .arg("--format")
+                .arg("carray")
+                .arg("--array-name")
+                .arg("9blob-x")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[PROC_21]"));
+    assert!(fs::metadata("carray_3.h").is_err());
+}
+
+#[test]
+fn output_align_4() {
+    let _cmd = Command::cargo_bin("brink")
+                .unwrap()
+                .arg("tests/output_align_4.brink")
+                .arg("-o output_align_4.bin")
+                .assert()
+                .failure()
+                .stderr(predicates::str::contains("[IRDB_32]"))
+                .stderr(predicates::str::contains(
+                    "Alignment must be a non-zero power of 2, but found 24"));
+    assert!(fs::metadata("output_align_4.bin").is_err());
+}
 
 } // mod tests
 